@@ -0,0 +1,475 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::gamepad::{
+    default_axis_bindings, default_button_bindings, AxisBinding, ButtonBinding, GamepadState,
+};
+
+/// What the keys and gamepads do, independent of which of them are bound to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveForward,
+    MoveBackward,
+    MoveRight,
+    MoveLeft,
+    RotateUp,
+    RotateDown,
+    RotateRight,
+    RotateLeft,
+    ToggleCameraMode,
+}
+
+/// A modifier held together with the key of a binding; either the left or the right key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Modifier {
+    Shift,
+    Control,
+    Alt,
+    Meta,
+}
+
+impl Modifier {
+    // Codes of the keys that hold the modifier
+    fn codes(self) -> [&'static str; 2] {
+        match self {
+            Modifier::Shift => ["ShiftLeft", "ShiftRight"],
+            Modifier::Control => ["ControlLeft", "ControlRight"],
+            Modifier::Alt => ["AltLeft", "AltRight"],
+            Modifier::Meta => ["MetaLeft", "MetaRight"],
+        }
+    }
+}
+
+/// A physical key, named like `KeyboardEvent.code` (e.g. `KeyW` whatever the layout
+/// and Shift make of it), that triggers `action` while `modifiers` are held too.
+/// Other modifiers held at the same time do not matter, except that of the bindings
+/// of a key whose modifiers are held, only those needing the most of them trigger.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Binding {
+    pub action: Action,
+    pub code: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modifiers: Vec<Modifier>,
+}
+
+impl Binding {
+    pub fn new(action: Action, code: &str) -> Self {
+        Self {
+            action,
+            code: code.to_string(),
+            modifiers: Vec::new(),
+        }
+    }
+
+    pub fn with_modifier(mut self, modifier: Modifier) -> Self {
+        self.modifiers.push(modifier);
+        self
+    }
+}
+
+/// A set of bindings, stored as JSON like
+/// `{"bindings":[{"action":"MoveUp","code":"KeyW"},{"action":"MoveDown","code":"KeyS","modifiers":["Shift"]}]}`.
+/// An action can have any number of bindings. Profiles without `axes` or `buttons`
+/// have no gamepad bindings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputProfile {
+    pub bindings: Vec<Binding>,
+    #[serde(default)]
+    pub axes: Vec<AxisBinding>,
+    #[serde(default)]
+    pub buttons: Vec<ButtonBinding>,
+}
+
+impl Default for InputProfile {
+    fn default() -> Self {
+        let bindings = [
+            (Action::MoveUp, "KeyW"),
+            (Action::MoveDown, "KeyS"),
+            (Action::MoveForward, "KeyE"),
+            (Action::MoveBackward, "KeyQ"),
+            (Action::MoveRight, "KeyD"),
+            (Action::MoveLeft, "KeyA"),
+            (Action::RotateUp, "KeyI"),
+            (Action::RotateDown, "KeyK"),
+            (Action::RotateRight, "KeyL"),
+            (Action::RotateLeft, "KeyJ"),
+            (Action::ToggleCameraMode, "KeyC"),
+        ];
+        Self {
+            bindings: bindings
+                .into_iter()
+                .map(|(action, code)| Binding::new(action, code))
+                .collect(),
+            axes: default_axis_bindings(),
+            buttons: default_button_bindings(),
+        }
+    }
+}
+
+impl InputProfile {
+    pub fn from_json(json: &str) -> Result<Self, ProfileError> {
+        serde_json::from_str(json).map_err(ProfileError)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Profiles are always serializable")
+    }
+
+    /// Adds a binding, keeping the other bindings of the action.
+    pub fn bind(&mut self, binding: Binding) {
+        if !self.bindings.contains(&binding) {
+            self.bindings.push(binding);
+        }
+    }
+
+    pub fn bind_axis(&mut self, binding: AxisBinding) {
+        if !self.axes.contains(&binding) {
+            self.axes.push(binding);
+        }
+    }
+
+    pub fn bind_button(&mut self, binding: ButtonBinding) {
+        if !self.buttons.contains(&binding) {
+            self.buttons.push(binding);
+        }
+    }
+
+    /// Removes the key and gamepad bindings of `action`.
+    pub fn unbind(&mut self, action: Action) {
+        self.bindings.retain(|binding| binding.action != action);
+        self.axes.retain(|binding| binding.action != action);
+        self.buttons.retain(|binding| binding.action != action);
+    }
+
+    // The strength of each action from the gamepads; bindings of the same action add up
+    fn gamepad_values(&self, gamepads: &[GamepadState]) -> HashMap<Action, f32> {
+        let mut values = HashMap::new();
+        for gamepad in gamepads {
+            let axes = self
+                .axes
+                .iter()
+                .map(|binding| (binding.action, binding.value(gamepad)));
+            let buttons = self
+                .buttons
+                .iter()
+                .map(|binding| (binding.action, binding.value(gamepad)));
+            for (action, value) in axes.chain(buttons).filter(|(_, value)| *value > 0.0) {
+                *values.entry(action).or_insert(0.0) += value;
+            }
+        }
+        values
+    }
+}
+
+/// A profile that is not valid JSON or names an unknown action or modifier.
+#[derive(Debug)]
+pub struct ProfileError(serde_json::Error);
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid input profile: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+/// Tracks the held keys and the gamepads, and the actions they trigger through an
+/// `InputProfile`. Presses and releases between two `update`s are both reported,
+/// however quick.
+pub struct ActionMap {
+    profile: InputProfile,
+    held: HashSet<String>,
+    gamepads: Vec<GamepadState>,
+    // strength of the triggered actions; 1 for keys
+    active: HashMap<Action, f32>,
+    // edges since the last update
+    pressed: HashSet<Action>,
+    released: HashSet<Action>,
+}
+
+impl ActionMap {
+    pub fn new(profile: InputProfile) -> Self {
+        Self {
+            profile,
+            held: HashSet::new(),
+            gamepads: Vec::new(),
+            active: HashMap::new(),
+            pressed: HashSet::new(),
+            released: HashSet::new(),
+        }
+    }
+
+    pub fn profile(&self) -> &InputProfile {
+        &self.profile
+    }
+
+    /// Actions held under the previous profile and not under the new one are released.
+    pub fn set_profile(&mut self, profile: InputProfile) {
+        self.profile = profile;
+        self.refresh();
+    }
+
+    /// `code` names the physical key like `KeyboardEvent.code`. Repeated presses
+    /// of a held key change nothing.
+    pub fn key_input(&mut self, code: &str, released: bool) {
+        let changed = if released {
+            self.held.remove(code)
+        } else {
+            self.held.insert(code.to_string())
+        };
+        if changed {
+            self.refresh();
+        }
+    }
+
+    /// Replaces the state of the gamepads with the current one, e.g. once per update.
+    pub fn gamepad_input(&mut self, gamepads: &[GamepadState]) {
+        self.gamepads = gamepads.to_vec();
+        self.refresh();
+    }
+
+    /// Whether any binding of `action` is held.
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.active.contains_key(&action)
+    }
+
+    /// How strongly `action` is triggered: 1 for keys, the response of the axes for
+    /// gamepads, and 0 when it is not.
+    pub fn value(&self, action: Action) -> f32 {
+        self.active.get(&action).copied().unwrap_or(0.0)
+    }
+
+    /// Whether `action` started since the last update.
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    /// Whether `action` stopped since the last update.
+    pub fn just_released(&self, action: Action) -> bool {
+        self.released.contains(&action)
+    }
+
+    /// The actions held, in no particular order.
+    pub fn pressed(&self) -> impl Iterator<Item = Action> + '_ {
+        self.active.keys().copied()
+    }
+
+    /// The actions held with their strength, in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = (Action, f32)> + '_ {
+        self.active.iter().map(|(action, value)| (*action, *value))
+    }
+
+    /// Forgets the edges reported since the last update.
+    pub fn update(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+    }
+
+    /// Releases every key and gamepad, e.g. when the window loses the focus and misses
+    /// the releases.
+    pub fn purge(&mut self) {
+        self.held.clear();
+        self.gamepads.clear();
+        self.refresh();
+    }
+
+    fn is_held(&self, binding: &Binding) -> bool {
+        self.held.contains(&binding.code)
+            && binding.modifiers.iter().all(|modifier| {
+                modifier
+                    .codes()
+                    .iter()
+                    .any(|code| self.held.contains(*code))
+            })
+    }
+
+    // The actions of the held bindings that need the most modifiers of their key, so
+    // that Shift+ArrowUp does not also trigger the binding of ArrowUp alone
+    fn held_actions(&self) -> Vec<Action> {
+        let mut matches: HashMap<&str, (usize, Vec<Action>)> = HashMap::new();
+        for binding in self.profile.bindings.iter().filter(|b| self.is_held(b)) {
+            let modifiers = binding.modifiers.iter().collect::<HashSet<_>>().len();
+            let (most, actions) = matches
+                .entry(binding.code.as_str())
+                .or_insert((modifiers, Vec::new()));
+            if modifiers > *most {
+                *most = modifiers;
+                actions.clear();
+            }
+            if modifiers == *most {
+                actions.push(binding.action);
+            }
+        }
+        matches
+            .into_values()
+            .flat_map(|(_, actions)| actions)
+            .collect()
+    }
+
+    fn refresh(&mut self) {
+        let mut active = self.profile.gamepad_values(&self.gamepads);
+        for action in self.held_actions() {
+            let value = active.entry(action).or_insert(0.0);
+            *value = value.max(1.0);
+        }
+        self.pressed.extend(
+            active
+                .keys()
+                .filter(|action| !self.active.contains_key(action)),
+        );
+        self.released.extend(
+            self.active
+                .keys()
+                .filter(|action| !active.contains_key(action)),
+        );
+        self.active = active;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(bindings: Vec<Binding>) -> InputProfile {
+        InputProfile {
+            bindings,
+            axes: Vec::new(),
+            buttons: Vec::new(),
+        }
+    }
+
+    fn pressed(actions: &ActionMap) -> Vec<Action> {
+        let mut pressed = actions.pressed().collect::<Vec<_>>();
+        pressed.sort_by_key(|action| *action as u8);
+        pressed
+    }
+
+    #[test]
+    fn reports_presses_and_releases_once() {
+        let mut actions = ActionMap::new(InputProfile::default());
+        actions.key_input("KeyW", false);
+        assert!(actions.just_pressed(Action::MoveUp));
+        assert!(actions.is_pressed(Action::MoveUp));
+        assert_eq!(actions.value(Action::MoveUp), 1.0);
+        assert_eq!(pressed(&actions), [Action::MoveUp]);
+
+        // repeated presses of a held key are no new edges
+        actions.update();
+        actions.key_input("KeyW", false);
+        assert!(!actions.just_pressed(Action::MoveUp));
+        assert!(actions.is_pressed(Action::MoveUp));
+
+        actions.update();
+        actions.key_input("KeyW", true);
+        assert!(actions.just_released(Action::MoveUp));
+        assert!(!actions.is_pressed(Action::MoveUp));
+        assert_eq!(actions.value(Action::MoveUp), 0.0);
+        assert!(pressed(&actions).is_empty());
+
+        actions.update();
+        assert!(!actions.just_released(Action::MoveUp));
+    }
+
+    #[test]
+    fn reports_quick_taps_between_updates() {
+        let mut actions = ActionMap::new(InputProfile::default());
+        actions.key_input("KeyC", false);
+        actions.key_input("KeyC", true);
+        assert!(actions.just_pressed(Action::ToggleCameraMode));
+        assert!(actions.just_released(Action::ToggleCameraMode));
+        assert!(!actions.is_pressed(Action::ToggleCameraMode));
+    }
+
+    #[test]
+    fn bindings_need_their_modifiers() {
+        let mut actions = ActionMap::new(profile(vec![Binding::new(Action::MoveDown, "KeyS")
+            .with_modifier(Modifier::Shift)
+            .with_modifier(Modifier::Control)]));
+        actions.key_input("KeyS", false);
+        actions.key_input("ShiftRight", false);
+        assert!(!actions.is_pressed(Action::MoveDown));
+        actions.key_input("ControlLeft", false);
+        assert!(actions.just_pressed(Action::MoveDown));
+
+        // releasing a modifier releases the action
+        actions.update();
+        actions.key_input("ShiftRight", true);
+        assert!(actions.just_released(Action::MoveDown));
+    }
+
+    #[test]
+    fn most_specific_binding_wins() {
+        let mut actions = ActionMap::new(profile(vec![
+            Binding::new(Action::MoveForward, "ArrowUp"),
+            Binding::new(Action::MoveUp, "ArrowUp").with_modifier(Modifier::Shift),
+            Binding::new(Action::RotateUp, "KeyI"),
+        ]));
+        actions.key_input("ArrowUp", false);
+        assert_eq!(pressed(&actions), [Action::MoveForward]);
+
+        // Shift+ArrowUp only moves up, and releases moving forward
+        actions.update();
+        actions.key_input("ShiftLeft", false);
+        assert_eq!(pressed(&actions), [Action::MoveUp]);
+        assert!(actions.just_pressed(Action::MoveUp));
+        assert!(actions.just_released(Action::MoveForward));
+
+        // the modifier does not matter to the bindings of other keys
+        actions.key_input("KeyI", false);
+        assert_eq!(pressed(&actions), [Action::MoveUp, Action::RotateUp]);
+
+        // modifiers no binding needs do not matter either
+        actions.key_input("ShiftLeft", true);
+        actions.key_input("AltLeft", false);
+        assert_eq!(pressed(&actions), [Action::MoveForward, Action::RotateUp]);
+    }
+
+    #[test]
+    fn equally_specific_bindings_all_trigger() {
+        let mut actions = ActionMap::new(profile(vec![
+            Binding::new(Action::MoveForward, "Space"),
+            Binding::new(Action::MoveUp, "Space"),
+        ]));
+        actions.key_input("Space", false);
+        assert_eq!(pressed(&actions), [Action::MoveUp, Action::MoveForward]);
+    }
+
+    #[test]
+    fn purge_and_new_profiles_release_actions() {
+        let mut actions = ActionMap::new(InputProfile::default());
+        actions.key_input("KeyW", false);
+        actions.key_input("KeyD", false);
+        actions.update();
+
+        let mut profile = InputProfile::default();
+        profile.unbind(Action::MoveUp);
+        actions.set_profile(profile);
+        assert!(actions.just_released(Action::MoveUp));
+        assert_eq!(pressed(&actions), [Action::MoveRight]);
+
+        actions.update();
+        actions.purge();
+        assert!(actions.just_released(Action::MoveRight));
+        assert!(pressed(&actions).is_empty());
+    }
+
+    #[test]
+    fn profiles_round_trip_through_json() {
+        let profile = profile(vec![
+            Binding::new(Action::MoveUp, "ArrowUp").with_modifier(Modifier::Shift)
+        ]);
+        assert_eq!(
+            InputProfile::from_json(&profile.to_json()).unwrap(),
+            profile
+        );
+        assert!(
+            InputProfile::from_json(r#"{"bindings":[{"action":"Jump","code":"Space"}]}"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,26 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use futures::future::LocalBoxFuture;
+
+use super::AssetSource;
+
+/// Reads resources from a directory on disk.
+pub struct FileSource {
+    root: PathBuf,
+}
+
+impl FileSource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl AssetSource for FileSource {
+    fn fetch<'a>(&'a self, path: &'a str) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let path = self.root.join(path);
+            std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))
+        })
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::Context;
+use futures::future::LocalBoxFuture;
+use reqwest::Url;
+
+use super::AssetSource;
+
+/// Downloads resources relative to a base URL.
+pub struct HttpSource {
+    base: Url,
+}
+
+impl HttpSource {
+    /// `href` may be any document URL, such as `https://example.com/app/index.html?q=1#top`;
+    /// resources are resolved against its directory as a browser would resolve relative links.
+    pub fn new(href: &str) -> anyhow::Result<Self> {
+        let base = Url::parse(href).with_context(|| format!("Invalid base URL {}", href))?;
+        Ok(Self { base })
+    }
+
+    pub fn resolve(&self, path: &str) -> anyhow::Result<Url> {
+        self.base
+            .join(path)
+            .with_context(|| format!("Failed to resolve {} against {}", path, self.base))
+    }
+}
+
+impl AssetSource for HttpSource {
+    fn fetch<'a>(&'a self, path: &'a str) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        self.fetch_with_progress(path, &|_, _| {})
+    }
+
+    fn fetch_with_progress<'a>(
+        &'a self,
+        path: &'a str,
+        progress: &'a dyn Fn(u64, Option<u64>),
+    ) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let url = self.resolve(path)?;
+            get(url.clone(), progress)
+                .await
+                .with_context(|| format!("Failed to download {}", url))
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn get(url: Url, progress: &dyn Fn(u64, Option<u64>)) -> anyhow::Result<Vec<u8>> {
+    use futures::StreamExt;
+
+    let response = reqwest::get(url).await?.error_for_status()?;
+    let total = response.content_length();
+    let mut bytes = Vec::with_capacity(total.unwrap_or(0) as usize);
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        bytes.extend_from_slice(&chunk?);
+        progress(bytes.len() as u64, total);
+    }
+    Ok(bytes)
+}
+
+// reqwest's async client needs a tokio runtime outside the browser, so native targets
+// use the blocking client on a thread of its own. The future only waits for its messages,
+// which lets a `Loader` time out or cancel while a request hangs.
+#[cfg(not(target_arch = "wasm32"))]
+async fn get(url: Url, progress: &dyn Fn(u64, Option<u64>)) -> anyhow::Result<Vec<u8>> {
+    use futures::StreamExt;
+
+    let (sender, mut receiver) = futures::channel::mpsc::unbounded();
+    std::thread::spawn(move || {
+        let report = |received, total| {
+            sender
+                .unbounded_send(Download::Progress(received, total))
+                .is_ok()
+        };
+        let result = download(url, &report);
+        let _ = sender.unbounded_send(Download::Done(result));
+    });
+
+    while let Some(message) = receiver.next().await {
+        match message {
+            Download::Progress(received, total) => progress(received, total),
+            Download::Done(result) => return result,
+        }
+    }
+    Err(anyhow::anyhow!("The download thread stopped"))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+enum Download {
+    Progress(u64, Option<u64>),
+    Done(anyhow::Result<Vec<u8>>),
+}
+
+// Stops early once `report` returns false, i.e. nobody waits for the download anymore
+#[cfg(not(target_arch = "wasm32"))]
+fn download(url: Url, report: &dyn Fn(u64, Option<u64>) -> bool) -> anyhow::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut response = reqwest::blocking::get(url)?.error_for_status()?;
+    let total = response.content_length();
+    let mut bytes = Vec::with_capacity(total.unwrap_or(0) as usize);
+    let mut chunk = [0; 64 * 1024];
+    loop {
+        let read = response.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&chunk[..read]);
+        if !report(bytes.len() as u64, total) {
+            anyhow::bail!("The download was abandoned");
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        time::{Duration, Instant},
+    };
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::asset::{LoadError, Loader, RetryPolicy};
+
+    // Serves every connection with `respond` on a thread; returns the base URL
+    fn serve(respond: impl Fn(&mut std::net::TcpStream) + Send + 'static) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                respond(&mut stream);
+            }
+        });
+        format!("http://{}/", address)
+    }
+
+    #[test]
+    fn downloads_with_progress() {
+        let base = serve(|stream| {
+            let _ = stream.write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+            );
+        });
+        let source = HttpSource::new(&base).unwrap();
+        let reports = std::cell::RefCell::new(Vec::new());
+        let bytes = block_on(source.fetch_with_progress("earth.obj", &|received, total| {
+            reports.borrow_mut().push((received, total))
+        }))
+        .unwrap();
+        assert_eq!(bytes, b"hello");
+        assert_eq!(reports.borrow().last(), Some(&(5, Some(5))));
+    }
+
+    #[test]
+    fn hanging_requests_time_out() {
+        let base = serve(|_| std::thread::sleep(Duration::from_secs(60)));
+        let loader = Loader::new(HttpSource::new(&base).unwrap())
+            .with_timeout(Some(Duration::from_millis(100)))
+            .with_retry(RetryPolicy {
+                max_attempts: 1,
+                ..RetryPolicy::default()
+            });
+
+        let start = Instant::now();
+        let error = block_on(loader.fetch("earth.obj")).err().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(
+            matches!(error.downcast_ref(), Some(LoadError::TimedOut { .. })),
+            "{:?}",
+            error
+        );
+    }
+}
@@ -0,0 +1,389 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures::{future::LocalBoxFuture, FutureExt};
+use futures_timer::Delay;
+
+use super::AssetSource;
+
+/// How failed downloads are retried. The wait before the `n`-th retry is
+/// `initial_backoff * multiplier^(n-1)`, capped at `max_backoff`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.powi(retry.saturating_sub(1) as i32);
+        self.initial_backoff.mul_f32(factor).min(self.max_backoff)
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Cancelled,
+    TimedOut { path: String, after: Duration },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Cancelled => write!(f, "Loading was cancelled"),
+            LoadError::TimedOut { path, after } => {
+                write!(f, "Loading {} timed out after {:?}", path, after)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Shared flag that aborts every load holding a clone of it.
+#[derive(Clone, Default)]
+pub struct CancelToken(Rc<CancelState>);
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: Cell<bool>,
+    next_waiter: Cell<u64>,
+    // one per pending `cancelled` future, removed when it is dropped
+    wakers: RefCell<HashMap<u64, Waker>>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.set(true);
+        let wakers = std::mem::take(&mut *self.0.wakers.borrow_mut());
+        wakers.into_values().for_each(Waker::wake);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.get()
+    }
+
+    /// Resolves once `cancel` has been called.
+    pub fn cancelled(&self) -> impl Future<Output = ()> + '_ {
+        let id = self.0.next_waiter.get();
+        self.0.next_waiter.set(id + 1);
+        Cancelled { token: self, id }
+    }
+}
+
+// Keeps the latest waker of its task until it resolves or is dropped
+struct Cancelled<'a> {
+    token: &'a CancelToken,
+    id: u64,
+}
+
+impl Future for Cancelled<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        let mut wakers = self.token.0.wakers.borrow_mut();
+        match wakers.get_mut(&self.id) {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            Some(waker) => waker.clone_from(cx.waker()),
+            None => {
+                wakers.insert(self.id, cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Cancelled<'_> {
+    fn drop(&mut self) {
+        self.token.0.wakers.borrow_mut().remove(&self.id);
+    }
+}
+
+/// Receives the path of an asset, the bytes received so far and the total size if known.
+pub type ProgressCallback = Box<dyn Fn(&str, u64, Option<u64>)>;
+
+/// Wraps another source with per-asset timeouts, retries, cancellation and progress reports.
+pub struct Loader<S> {
+    source: S,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    cancel: CancelToken,
+    on_progress: Option<ProgressCallback>,
+}
+
+impl<S: AssetSource> Loader<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            timeout: Some(Duration::from_secs(30)),
+            retry: RetryPolicy::default(),
+            cancel: CancelToken::new(),
+            on_progress: None,
+        }
+    }
+
+    /// Limits a single attempt of a single asset. `None` waits forever.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn on_progress(mut self, callback: impl Fn(&str, u64, Option<u64>) + 'static) -> Self {
+        self.on_progress = Some(Box::new(callback));
+        self
+    }
+
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    async fn attempt(
+        &self,
+        path: &str,
+        progress: &dyn Fn(u64, Option<u64>),
+    ) -> anyhow::Result<Vec<u8>> {
+        let report = |received, total| {
+            if let Some(on_progress) = &self.on_progress {
+                on_progress(path, received, total);
+            }
+            progress(received, total);
+        };
+        let timeout = async {
+            match self.timeout {
+                Some(after) => {
+                    Delay::new(after).await;
+                    after
+                }
+                None => futures::future::pending().await,
+            }
+        };
+
+        futures::select_biased! {
+            _ = self.cancel.cancelled().fuse() => Err(LoadError::Cancelled.into()),
+            result = self.source.fetch_with_progress(path, &report).fuse() => result,
+            after = timeout.fuse() => Err(LoadError::TimedOut {
+                path: path.to_string(),
+                after,
+            }
+            .into()),
+        }
+    }
+}
+
+impl<S: AssetSource> AssetSource for Loader<S> {
+    fn fetch<'a>(&'a self, path: &'a str) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        self.fetch_with_progress(path, &|_, _| {})
+    }
+
+    fn fetch_with_progress<'a>(
+        &'a self,
+        path: &'a str,
+        progress: &'a dyn Fn(u64, Option<u64>),
+    ) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let mut retry = 0;
+            loop {
+                if self.cancel.is_cancelled() {
+                    return Err(LoadError::Cancelled.into());
+                }
+
+                let error = match self.attempt(path, progress).await {
+                    Ok(bytes) => return Ok(bytes),
+                    Err(e) => e,
+                };
+                if self.cancel.is_cancelled() {
+                    return Err(error);
+                }
+
+                retry += 1;
+                if retry >= self.retry.max_attempts {
+                    return Err(
+                        error.context(format!("Failed to load {} after {} attempts", path, retry))
+                    );
+                }
+
+                let backoff = self.retry.backoff(retry);
+                log::warn!("{:#}; retrying in {:?}", error, backoff);
+                futures::select_biased! {
+                    _ = self.cancel.cancelled().fuse() => return Err(LoadError::Cancelled.into()),
+                    _ = Delay::new(backoff).fuse() => {}
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc, time::Instant};
+
+    use futures::{executor::block_on, task::noop_waker_ref};
+
+    use super::*;
+    use crate::asset::MemorySource;
+
+    const PATH: &str = "resources/earth/earth.obj";
+
+    fn quick_retry(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            multiplier: 2.0,
+        }
+    }
+
+    #[test]
+    fn backoff_grows_up_to_the_cap() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_secs(1));
+        assert_eq!(policy.backoff(4), Duration::from_secs(4));
+        assert_eq!(policy.backoff(10), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn retries_failed_fetches() {
+        let source = MemorySource::new()
+            .with(PATH, &b"obj"[..])
+            .with_failures(PATH, 2);
+        let loader = Loader::new(source).with_retry(quick_retry(3));
+        assert_eq!(block_on(loader.fetch(PATH)).unwrap(), b"obj");
+    }
+
+    #[test]
+    fn gives_up_after_the_last_attempt() {
+        let source = MemorySource::new()
+            .with(PATH, &b"obj"[..])
+            .with_failures(PATH, 3);
+        let loader = Loader::new(source).with_retry(quick_retry(3));
+        let error = block_on(loader.fetch(PATH)).err().unwrap();
+        assert!(error.to_string().contains("after 3 attempts"), "{}", error);
+    }
+
+    #[test]
+    fn slow_fetches_time_out() {
+        let source = MemorySource::new()
+            .with(PATH, &b"obj"[..])
+            .with_latency(Duration::from_secs(5));
+        let loader = Loader::new(source)
+            .with_timeout(Some(Duration::from_millis(20)))
+            .with_retry(quick_retry(2));
+
+        let start = Instant::now();
+        let error = block_on(loader.fetch(PATH)).err().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let timed_out = error
+            .chain()
+            .any(|cause| matches!(cause.downcast_ref(), Some(LoadError::TimedOut { .. })));
+        assert!(timed_out, "{:?}", error);
+    }
+
+    #[test]
+    fn cancelling_aborts_a_pending_fetch() {
+        let source = MemorySource::new()
+            .with(PATH, &b"obj"[..])
+            .with_latency(Duration::from_secs(5));
+        let loader = Loader::new(source);
+        let cancel = loader.cancel_token();
+
+        let start = Instant::now();
+        let (result, _) = block_on(futures::future::join(loader.fetch(PATH), async {
+            Delay::new(Duration::from_millis(20)).await;
+            cancel.cancel();
+        }));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(matches!(
+            result.err().unwrap().downcast_ref(),
+            Some(LoadError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn cancelling_stops_the_backoff() {
+        let source = MemorySource::new()
+            .with(PATH, &b"obj"[..])
+            .with_failures(PATH, 1);
+        let loader = Loader::new(source).with_retry(RetryPolicy {
+            initial_backoff: Duration::from_secs(5),
+            ..RetryPolicy::default()
+        });
+        let cancel = loader.cancel_token();
+
+        let (result, _) = block_on(futures::future::join(loader.fetch(PATH), async {
+            Delay::new(Duration::from_millis(20)).await;
+            cancel.cancel();
+        }));
+        assert!(matches!(
+            result.err().unwrap().downcast_ref(),
+            Some(LoadError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn reports_progress() {
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let loader = Loader::new(MemorySource::new().with(PATH, &b"obj"[..])).on_progress({
+            let reports = reports.clone();
+            move |path, received, total| {
+                reports
+                    .borrow_mut()
+                    .push((path.to_string(), received, total))
+            }
+        });
+        block_on(loader.fetch(PATH)).unwrap();
+        assert_eq!(*reports.borrow(), [(PATH.to_string(), 3, Some(3))]);
+    }
+
+    #[test]
+    fn waiters_keep_one_waker() {
+        let token = CancelToken::new();
+        let mut cx = Context::from_waker(noop_waker_ref());
+        {
+            let mut cancelled = Box::pin(token.cancelled());
+            for _ in 0..100 {
+                assert!(cancelled.as_mut().poll(&mut cx).is_pending());
+            }
+            assert_eq!(token.0.wakers.borrow().len(), 1);
+        }
+        assert!(token.0.wakers.borrow().is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use futures::future::LocalBoxFuture;
+
+use super::AssetSource;
+
+/// Serves resources held in memory, e.g. embedded with `include_bytes!`:
+///
+/// ```ignore
+/// let source = MemorySource::new().with(
+///     "resources/earth/earth.obj",
+///     include_bytes!("../resources/earth/earth.obj"),
+/// );
+/// ```
+///
+/// `with_latency` and `with_failures` make it behave like an unreliable network,
+/// which is useful to exercise a `Loader`.
+#[derive(Default)]
+pub struct MemorySource {
+    assets: HashMap<String, Cow<'static, [u8]>>,
+    latency: Option<Duration>,
+    failures: HashMap<String, AtomicU32>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, path: &str, bytes: impl Into<Cow<'static, [u8]>>) -> Self {
+        self.insert(path, bytes);
+        self
+    }
+
+    pub fn insert(&mut self, path: &str, bytes: impl Into<Cow<'static, [u8]>>) {
+        self.assets.insert(path.to_string(), bytes.into());
+    }
+
+    /// Delays every fetch.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Makes the next `count` fetches of `path` fail.
+    pub fn with_failures(mut self, path: &str, count: u32) -> Self {
+        self.failures
+            .insert(path.to_string(), AtomicU32::new(count));
+        self
+    }
+}
+
+impl AssetSource for MemorySource {
+    fn fetch<'a>(&'a self, path: &'a str) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move {
+            if let Some(latency) = self.latency {
+                futures_timer::Delay::new(latency).await;
+            }
+
+            let failing = self.failures.get(path).is_some_and(|remaining| {
+                remaining
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                    .is_ok()
+            });
+            if failing {
+                return Err(anyhow!("Injected failure for {}", path));
+            }
+
+            self.assets
+                .get(path)
+                .map(|bytes| bytes.to_vec())
+                .ok_or_else(|| anyhow!("{} is not in memory", path))
+        })
+    }
+}
@@ -0,0 +1,149 @@
+mod file;
+mod http;
+mod loader;
+mod memory;
+mod record;
+
+pub use file::FileSource;
+pub use http::HttpSource;
+pub use loader::{CancelToken, LoadError, Loader, ProgressCallback, RetryPolicy};
+pub use memory::MemorySource;
+pub use record::RecordingSource;
+
+use std::cell::Cell;
+
+use anyhow::anyhow;
+use futures::future::LocalBoxFuture;
+
+/// Somewhere resources can be read from. `path` is relative to the root of the source,
+/// e.g. `resources/earth/earth.obj`.
+pub trait AssetSource {
+    fn fetch<'a>(&'a self, path: &'a str) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>>;
+
+    /// Same as `fetch`, additionally calling `progress` with the bytes received so far
+    /// and the total size if known. Sources that cannot stream report once at the end.
+    fn fetch_with_progress<'a>(
+        &'a self,
+        path: &'a str,
+        progress: &'a dyn Fn(u64, Option<u64>),
+    ) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let bytes = self.fetch(path).await?;
+            progress(bytes.len() as u64, Some(bytes.len() as u64));
+            Ok(bytes)
+        })
+    }
+}
+
+/// Tries each source in order and returns the first successful result. When a source
+/// fails after reporting progress, progress restarts at zero of an unknown total before
+/// the next one is tried.
+#[derive(Default)]
+pub struct LayeredSource {
+    layers: Vec<Box<dyn AssetSource>>,
+}
+
+impl LayeredSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, source: impl AssetSource + 'static) -> Self {
+        self.push(source);
+        self
+    }
+
+    pub fn push(&mut self, source: impl AssetSource + 'static) {
+        self.layers.push(Box::new(source));
+    }
+}
+
+impl AssetSource for LayeredSource {
+    fn fetch<'a>(&'a self, path: &'a str) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        self.fetch_with_progress(path, &|_, _| {})
+    }
+
+    fn fetch_with_progress<'a>(
+        &'a self,
+        path: &'a str,
+        progress: &'a dyn Fn(u64, Option<u64>),
+    ) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let mut errors = Vec::new();
+            for layer in &self.layers {
+                let reported = Cell::new(false);
+                let report = |received, total| {
+                    reported.set(true);
+                    progress(received, total);
+                };
+                match layer.fetch_with_progress(path, &report).await {
+                    Ok(bytes) => return Ok(bytes),
+                    Err(e) => errors.push(format!("{:#}", e)),
+                }
+                if reported.get() {
+                    progress(0, None);
+                }
+            }
+            Err(anyhow!(
+                "No source provides {}: [{}]",
+                path,
+                errors.join(", ")
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use futures::executor::block_on;
+
+    use super::*;
+
+    // Reports half of the asset, then fails
+    struct Interrupted;
+
+    impl AssetSource for Interrupted {
+        fn fetch<'a>(&'a self, path: &'a str) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+            self.fetch_with_progress(path, &|_, _| {})
+        }
+
+        fn fetch_with_progress<'a>(
+            &'a self,
+            path: &'a str,
+            progress: &'a dyn Fn(u64, Option<u64>),
+        ) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+            Box::pin(async move {
+                progress(50, Some(100));
+                Err(anyhow!("Connection reset fetching {}", path))
+            })
+        }
+    }
+
+    #[test]
+    fn progress_restarts_for_the_fallback() {
+        let source = LayeredSource::new()
+            .with(Interrupted)
+            .with(MemorySource::new().with("earth.obj", &b"obj"[..]));
+        let reports = RefCell::new(Vec::new());
+        let bytes = block_on(source.fetch_with_progress("earth.obj", &|received, total| {
+            reports.borrow_mut().push((received, total))
+        }))
+        .unwrap();
+        assert_eq!(bytes, b"obj");
+        assert_eq!(
+            reports.into_inner(),
+            [(50, Some(100)), (0, None), (3, Some(3))]
+        );
+    }
+
+    #[test]
+    fn fails_when_no_layer_provides_the_asset() {
+        let source = LayeredSource::new()
+            .with(Interrupted)
+            .with(MemorySource::new());
+        let error = block_on(source.fetch("earth.obj")).err().unwrap();
+        assert!(error.to_string().contains("Connection reset"), "{}", error);
+    }
+}
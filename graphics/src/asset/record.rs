@@ -0,0 +1,44 @@
+use std::cell::RefCell;
+
+use futures::future::LocalBoxFuture;
+
+use super::{AssetSource, MemorySource};
+
+/// Passes fetches through to another source and keeps a copy of what it returns,
+/// so the same resources can be read again once that source is gone.
+pub struct RecordingSource<'a> {
+    source: &'a dyn AssetSource,
+    recorded: RefCell<MemorySource>,
+}
+
+impl<'a> RecordingSource<'a> {
+    pub fn new(source: &'a dyn AssetSource) -> Self {
+        Self {
+            source,
+            recorded: RefCell::new(MemorySource::new()),
+        }
+    }
+
+    /// The resources fetched so far.
+    pub fn into_recorded(self) -> MemorySource {
+        self.recorded.into_inner()
+    }
+}
+
+impl AssetSource for RecordingSource<'_> {
+    fn fetch<'a>(&'a self, path: &'a str) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        self.fetch_with_progress(path, &|_, _| {})
+    }
+
+    fn fetch_with_progress<'a>(
+        &'a self,
+        path: &'a str,
+        progress: &'a dyn Fn(u64, Option<u64>),
+    ) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let bytes = self.source.fetch_with_progress(path, progress).await?;
+            self.recorded.borrow_mut().insert(path, bytes.clone());
+            Ok(bytes)
+        })
+    }
+}
@@ -0,0 +1,133 @@
+use futures::executor::block_on;
+use graphics::{
+    asset::FileSource, BloomSettings, EarthGeometry, OffscreenRenderer, SceneOptions, SphereKind,
+    TextureCompression, Tonemapper,
+};
+
+const USAGE: &str = "usage: render <output.png|output.exr> [width] [height] [--fallback] \
+[--resources <directory>] [--sphere <uv|ico|cube>[:<detail>]] [--texture <path>] [--anisotropy <1-16>] \
+[--texture-variant <bc|etc2|astc>:<path.ktx2>]... [--msaa <1|2|4|8>] \
+[--tonemapper <aces|reinhard|agx>] [--exposure <ev>] [--auto-exposure] \
+[--bloom <intensity>[:<threshold>[:<radius>]]]";
+
+fn parse_variant(arg: &str) -> anyhow::Result<(TextureCompression, String)> {
+    let (compression, path) = arg.split_once(':').ok_or_else(|| anyhow::anyhow!(USAGE))?;
+    let compression = match compression {
+        "bc" => TextureCompression::Bc,
+        "etc2" => TextureCompression::Etc2,
+        "astc" => TextureCompression::Astc,
+        _ => return Err(anyhow::anyhow!(USAGE)),
+    };
+    Ok((compression, path.to_string()))
+}
+
+fn parse_tonemapper(arg: &str) -> anyhow::Result<Tonemapper> {
+    match arg {
+        "aces" => Ok(Tonemapper::Aces),
+        "reinhard" => Ok(Tonemapper::Reinhard),
+        "agx" => Ok(Tonemapper::Agx),
+        _ => Err(anyhow::anyhow!(USAGE)),
+    }
+}
+
+fn parse_bloom(arg: &str) -> anyhow::Result<BloomSettings> {
+    let mut bloom = BloomSettings::default();
+    let mut values = arg.split(':');
+    if let Some(intensity) = values.next() {
+        bloom.intensity = intensity.parse()?;
+    }
+    if let Some(threshold) = values.next() {
+        bloom.threshold = threshold.parse()?;
+    }
+    if let Some(radius) = values.next() {
+        bloom.radius = radius.parse()?;
+    }
+    Ok(bloom)
+}
+
+fn parse_sphere(arg: &str) -> anyhow::Result<SphereKind> {
+    let (kind, detail) = match arg.split_once(':') {
+        Some((kind, detail)) => (kind, Some(detail.parse::<u32>()?)),
+        None => (arg, None),
+    };
+    match kind {
+        "uv" => {
+            let segments = detail.unwrap_or(128);
+            Ok(SphereKind::Uv {
+                segments,
+                rings: segments / 2,
+            })
+        }
+        "ico" => Ok(SphereKind::Icosphere {
+            subdivisions: detail.unwrap_or(5),
+        }),
+        "cube" => Ok(SphereKind::CubeSphere {
+            subdivisions: detail.unwrap_or(32),
+        }),
+        _ => Err(anyhow::anyhow!(USAGE)),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut output = None;
+    let mut size = Vec::new();
+    let mut force_fallback_adapter = false;
+    let mut resource_root = env!("CARGO_MANIFEST_DIR").to_string();
+    let mut options = SceneOptions::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fallback" => force_fallback_adapter = true,
+            "--resources" => resource_root = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?,
+            "--sphere" => {
+                let arg = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+                options.earth_geometry = EarthGeometry::Sphere(parse_sphere(&arg)?);
+            }
+            "--anisotropy" => {
+                let arg = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+                options.earth_texture_options.max_anisotropy = arg.parse()?;
+            }
+            "--msaa" => {
+                let arg = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+                options.sample_count = arg.parse()?;
+            }
+            "--tonemapper" => {
+                let arg = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+                options.tonemapper = parse_tonemapper(&arg)?;
+            }
+            "--exposure" => {
+                let arg = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+                options.exposure.ev = arg.parse()?;
+            }
+            "--auto-exposure" => options.exposure.auto = true,
+            "--bloom" => {
+                let arg = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+                options.bloom = parse_bloom(&arg)?;
+            }
+            "--texture-variant" => {
+                let arg = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+                options.earth_texture_variants.push(parse_variant(&arg)?);
+            }
+            "--texture" => {
+                options.earth_texture = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+            }
+            _ if output.is_none() => output = Some(arg),
+            _ => size.push(arg.parse::<u32>()?),
+        }
+    }
+
+    let output = output.ok_or_else(|| anyhow::anyhow!(USAGE))?;
+    let width = size.first().copied().unwrap_or(1920);
+    let height = size.get(1).copied().unwrap_or(1080);
+
+    let source = FileSource::new(resource_root);
+    let renderer = block_on(OffscreenRenderer::new(
+        width,
+        height,
+        force_fallback_adapter,
+        &source,
+        &options,
+    ))?;
+    block_on(renderer.render_to_file(&output))
+}
@@ -0,0 +1,249 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::executor::block_on;
+#[cfg(feature = "gamepad")]
+use graphics::GamepadState;
+use graphics::{asset::FileSource, SceneOptions, State};
+use winit::{
+    application::ApplicationHandler,
+    event::{ElementState, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent},
+    event_loop::{ActiveEventLoop, EventLoop},
+    keyboard::{KeyCode, PhysicalKey},
+    window::{Window, WindowId},
+};
+
+// `State::update` is driven at the same fixed rate as the web frontend
+const UPDATE_INTERVAL: f32 = 1.0 / 60.0;
+
+// Pointer id of the mouse; touches are numbered after it
+const MOUSE_POINTER: i32 = 0;
+// Pixels scrolled per line by wheels that scroll by lines
+const WHEEL_LINE_HEIGHT: f32 = 16.0;
+
+struct Viewer {
+    resource_root: String,
+    window: Option<Arc<Window>>,
+    state: Option<State>,
+    cursor: (f32, f32),
+    initial_time: Instant,
+    last_update: Instant,
+    #[cfg(feature = "gamepad")]
+    gamepads: Option<gilrs::Gilrs>,
+}
+
+impl Viewer {
+    fn new(resource_root: String) -> Self {
+        let now = Instant::now();
+        Self {
+            resource_root,
+            window: None,
+            state: None,
+            cursor: (0.0, 0.0),
+            initial_time: now,
+            last_update: now,
+            #[cfg(feature = "gamepad")]
+            gamepads: gilrs::Gilrs::new()
+                .map_err(|e| log::warn!("Gamepads are unavailable: {}", e))
+                .ok(),
+        }
+    }
+}
+
+impl ApplicationHandler for Viewer {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_some() {
+            return;
+        }
+
+        let window = match event_loop.create_window(
+            Window::default_attributes().with_title("WebGPU Simple Earth Visualizer"),
+        ) {
+            Ok(window) => Arc::new(window),
+            Err(e) => {
+                log::error!("Failed to create window: {:?}", e);
+                event_loop.exit();
+                return;
+            }
+        };
+
+        let size = window.inner_size();
+        let source = FileSource::new(&self.resource_root);
+        match block_on(State::new(
+            window.clone(),
+            size.width.max(1),
+            size.height.max(1),
+            false,
+            source,
+            &SceneOptions::default(),
+        )) {
+            Ok(state) => self.state = Some(state),
+            Err(e) => {
+                log::error!("Failed to create state: {:?}", e);
+                event_loop.exit();
+                return;
+            }
+        }
+        // Edits to the shaders of the crate show up while the viewer runs
+        #[cfg(feature = "hot-reload")]
+        if let Some(state) = &mut self.state {
+            state.watch_shaders(FileSource::new(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/shader"
+            )));
+        }
+
+        window.request_redraw();
+        self.window = Some(window);
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
+        let (Some(window), Some(state)) = (&self.window, &mut self.state) else {
+            return;
+        };
+
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::Resized(size) => {
+                state.resize(size.width, size.height);
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                if event.repeat {
+                    return;
+                }
+                if let PhysicalKey::Code(code) = event.physical_key {
+                    let released = event.state == ElementState::Released;
+                    state.key_input(&key_code_name(code), released);
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = (position.x as f32, position.y as f32);
+                state.pointer_move(MOUSE_POINTER, self.cursor.0, self.cursor.1);
+            }
+            WindowEvent::MouseInput {
+                state: button_state,
+                button: MouseButton::Left,
+                ..
+            } => match button_state {
+                ElementState::Pressed => {
+                    state.pointer_down(MOUSE_POINTER, self.cursor.0, self.cursor.1)
+                }
+                ElementState::Released => state.pointer_up(MOUSE_POINTER),
+            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => -y * WHEEL_LINE_HEIGHT,
+                    MouseScrollDelta::PixelDelta(position) => -position.y as f32,
+                };
+                state.wheel(self.cursor.0, self.cursor.1, delta);
+            }
+            WindowEvent::Touch(touch) => {
+                let id = MOUSE_POINTER + 1 + touch.id as i32;
+                let (x, y) = (touch.location.x as f32, touch.location.y as f32);
+                match touch.phase {
+                    TouchPhase::Started => state.pointer_down(id, x, y),
+                    TouchPhase::Moved => state.pointer_move(id, x, y),
+                    TouchPhase::Ended | TouchPhase::Cancelled => state.pointer_up(id),
+                }
+            }
+            WindowEvent::Focused(false) => state.leave(),
+            WindowEvent::RedrawRequested => {
+                if state.is_device_lost() && !block_on(state.restore()) {
+                    event_loop.exit();
+                    return;
+                }
+                let step = Duration::from_secs_f32(UPDATE_INTERVAL);
+                while self.last_update.elapsed() >= step {
+                    #[cfg(feature = "gamepad")]
+                    if let Some(gilrs) = &mut self.gamepads {
+                        state.gamepad_input(&read_gamepads(gilrs));
+                    }
+                    let time = self.initial_time.elapsed().as_secs_f32() / UPDATE_INTERVAL;
+                    state.update(time);
+                    self.last_update += step;
+                }
+                state.render();
+                window.request_redraw();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let resource_root = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| env!("CARGO_MANIFEST_DIR").to_string());
+
+    let event_loop = match EventLoop::new() {
+        Ok(event_loop) => event_loop,
+        Err(e) => {
+            log::error!("Failed to create event loop: {:?}", e);
+            return;
+        }
+    };
+
+    let mut viewer = Viewer::new(resource_root);
+    if let Err(e) = event_loop.run_app(&mut viewer) {
+        log::error!("Event loop terminated: {:?}", e);
+    }
+}
+
+// The gamepads laid out like the standard mapping of the browser Gamepad API, so that
+// profiles work on both. The Z axes of six-axis controllers follow as axes 4 and 5.
+#[cfg(feature = "gamepad")]
+fn read_gamepads(gilrs: &mut gilrs::Gilrs) -> Vec<GamepadState> {
+    use gilrs::{Axis, Button};
+
+    // the events update the state cached for each gamepad
+    while gilrs.next_event().is_some() {}
+    gilrs
+        .gamepads()
+        .map(|(_, gamepad)| GamepadState {
+            id: gamepad.name().to_string(),
+            axes: vec![
+                gamepad.value(Axis::LeftStickX),
+                -gamepad.value(Axis::LeftStickY),
+                gamepad.value(Axis::RightStickX),
+                -gamepad.value(Axis::RightStickY),
+                gamepad.value(Axis::LeftZ),
+                gamepad.value(Axis::RightZ),
+            ],
+            buttons: [
+                Button::South,
+                Button::East,
+                Button::West,
+                Button::North,
+                Button::LeftTrigger,
+                Button::RightTrigger,
+                Button::LeftTrigger2,
+                Button::RightTrigger2,
+                Button::Select,
+                Button::Start,
+                Button::LeftThumb,
+                Button::RightThumb,
+                Button::DPadUp,
+                Button::DPadDown,
+                Button::DPadLeft,
+                Button::DPadRight,
+                Button::Mode,
+            ]
+            .into_iter()
+            .map(|button| gamepad.button_data(button).map_or(0.0, |data| data.value()))
+            .collect(),
+        })
+        .collect()
+}
+
+// The name of a key as in `KeyboardEvent.code`, which winit mostly shares
+fn key_code_name(code: KeyCode) -> String {
+    match code {
+        KeyCode::SuperLeft => "MetaLeft".to_string(),
+        KeyCode::SuperRight => "MetaRight".to_string(),
+        code => format!("{:?}", code),
+    }
+}
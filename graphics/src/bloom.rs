@@ -0,0 +1,369 @@
+use wgpu::util::DeviceExt;
+
+use crate::{
+    layout::uniform_layout,
+    pipeline::{MaterialDesc, MaterialId, PipelineCache, RenderTargets},
+    shader::{Shader, ShaderError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSettings {
+    /// HDR brightness above which colors start to glow
+    pub threshold: f32,
+    /// Strength of the glow added to the scene; 0 turns bloom off
+    pub intensity: f32,
+    /// Spread of the upsampling filter, in texels of each level
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            intensity: 0.15,
+            radius: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    radius: f32,
+}
+
+uniform_layout!(BloomUniform = "Settings" in "bloom.wgsl" {
+    threshold, knee, intensity, radius,
+});
+
+impl From<BloomSettings> for BloomUniform {
+    fn from(settings: BloomSettings) -> Self {
+        Self {
+            threshold: settings.threshold,
+            knee: settings.threshold * 0.5,
+            intensity: settings.intensity,
+            radius: settings.radius,
+        }
+    }
+}
+
+// Levels of the downsampling chain, the first one being half the size of the frame
+const MAX_LEVELS: usize = 6;
+const DOWNLEVEL_MAX_LEVELS: usize = 4;
+
+const ADDITIVE: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+// A texture of the chain, and the bind group that samples it
+struct BloomLevel {
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Makes the bright parts of the HDR target glow by blurring them through a chain
+/// of downsampled textures and adding the result back.
+pub struct BloomPass {
+    settings: BloomSettings,
+    // format of the HDR target, which the levels of the chain share
+    hdr_format: wgpu::TextureFormat,
+    max_levels: usize,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    prefilter: MaterialId,
+    downsample: MaterialId,
+    upsample: MaterialId,
+    composite: MaterialId,
+    // samples the HDR target
+    hdr_bind_group: wgpu::BindGroup,
+    levels: Vec<BloomLevel>,
+}
+
+impl BloomPass {
+    /// Registers the materials of the pass in `pipelines`, which rebuilds them when
+    /// `bloom.wgsl` changes.
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        hdr_format: wgpu::TextureFormat,
+        hdr_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+        settings: BloomSettings,
+    ) -> Result<Self, ShaderError> {
+        // Without compute shaders we are on the WebGL2 limits, where a shorter chain is cheaper
+        let max_levels = if device.limits().max_compute_workgroups_per_dimension > 0 {
+            MAX_LEVELS
+        } else {
+            DOWNLEVEL_MAX_LEVELS
+        };
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("bloom_uniform_buffer"),
+            contents: bytemuck::cast_slice(&[BloomUniform::from(settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("bloom_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bloom_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let mut register = |fragment_entry, blend| {
+            pipelines.register(
+                device,
+                &MaterialDesc {
+                    shader: Shader::new("bloom.wgsl"),
+                    fragment_entry,
+                    bind_group_layouts: &[&bind_group_layout],
+                    vertex_buffers: &[],
+                    blend,
+                    depth_test: None,
+                    cull_mode: None,
+                    targets: Some(RenderTargets {
+                        format: hdr_format,
+                        depth_format: None,
+                        sample_count: 1,
+                    }),
+                },
+            )
+        };
+
+        let mut bloom = Self {
+            settings,
+            hdr_format,
+            max_levels,
+            prefilter: register("fs_prefilter", wgpu::BlendState::REPLACE)?,
+            downsample: register("fs_downsample", wgpu::BlendState::REPLACE)?,
+            upsample: register("fs_upsample", ADDITIVE)?,
+            composite: register("fs_composite", ADDITIVE)?,
+            hdr_bind_group: create_bind_group(
+                device,
+                &bind_group_layout,
+                hdr_view,
+                &sampler,
+                &uniform_buffer,
+            ),
+            uniform_buffer,
+            sampler,
+            bind_group_layout,
+            levels: Vec::new(),
+        };
+        bloom.resize(device, hdr_view, width, height);
+        Ok(bloom)
+    }
+
+    /// Follows the HDR target after it is recreated.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.hdr_bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            hdr_view,
+            &self.sampler,
+            &self.uniform_buffer,
+        );
+
+        let mut size = (width / 2, height / 2);
+        self.levels.clear();
+        while self.levels.len() < self.max_levels && size.0 >= 2 && size.1 >= 2 {
+            let view = device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some("bloom_texture"),
+                    size: wgpu::Extent3d {
+                        width: size.0,
+                        height: size.1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: self.hdr_format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default());
+            let bind_group = create_bind_group(
+                device,
+                &self.bind_group_layout,
+                &view,
+                &self.sampler,
+                &self.uniform_buffer,
+            );
+            self.levels.push(BloomLevel { view, bind_group });
+            size = (size.0 / 2, size.1 / 2);
+        }
+    }
+
+    pub fn settings(&self) -> BloomSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: BloomSettings) {
+        self.settings = settings;
+    }
+
+    pub fn enque_update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[BloomUniform::from(self.settings)]),
+        );
+    }
+
+    /// Adds the glow to `hdr_view`, which has to be resolved already. `pipelines` is the
+    /// cache the pass was created with.
+    pub fn render(
+        &self,
+        pipelines: &PipelineCache,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr_view: &wgpu::TextureView,
+    ) {
+        if self.settings.intensity <= 0.0 || self.levels.is_empty() {
+            return;
+        }
+
+        let mut source = &self.hdr_bind_group;
+        for (i, level) in self.levels.iter().enumerate() {
+            let material = if i == 0 {
+                self.prefilter
+            } else {
+                self.downsample
+            };
+            draw(
+                encoder,
+                &level.view,
+                pipelines.pipeline(material),
+                source,
+                true,
+            );
+            source = &level.bind_group;
+        }
+        for pair in self.levels.windows(2).rev() {
+            draw(
+                encoder,
+                &pair[0].view,
+                pipelines.pipeline(self.upsample),
+                &pair[1].bind_group,
+                false,
+            );
+        }
+        draw(
+            encoder,
+            hdr_view,
+            pipelines.pipeline(self.composite),
+            &self.levels[0].bind_group,
+            false,
+        );
+    }
+}
+
+// Draws a fullscreen triangle into `view`, sampling the texture of `source`
+fn draw(
+    encoder: &mut wgpu::CommandEncoder,
+    view: &wgpu::TextureView,
+    pipeline: &wgpu::RenderPipeline,
+    source: &wgpu::BindGroup,
+    clear: bool,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Bloom Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: if clear {
+                    wgpu::LoadOp::Clear(wgpu::Color::BLACK)
+                } else {
+                    wgpu::LoadOp::Load
+                },
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, source, &[]);
+    render_pass.draw(0..3, 0..1);
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+    uniform_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("bloom_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
@@ -0,0 +1,440 @@
+use std::fmt;
+
+use cgmath::{EuclideanSpace, InnerSpace, Rotation, Rotation3};
+#[cfg(feature = "web")]
+use wasm_bindgen::prelude::*;
+
+use crate::action::{Action, ActionMap};
+
+use super::geometry::CameraGeometry;
+
+// Degrees turned per second by the orbit controller
+const ORBIT_ANGLE_SPEED: f32 = 30.0;
+// Factor the range changes by per second when zooming
+const ORBIT_ZOOM_SPEED: f32 = 3.28;
+// Short of the horizon, where the camera would look along the surface
+const ORBIT_MAX_TILT: f32 = 85.0;
+const ORBIT_MIN_RANGE: f32 = 1.0;
+
+/// How the camera is moved by the input.
+#[cfg_attr(feature = "web", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CameraMode {
+    /// Translates and rotates the eye freely
+    #[default]
+    FreeFly = 0,
+    /// Orbits a point above the Earth, staying above its surface
+    Orbit = 1,
+}
+
+/// Turns the input into the geometry the camera eases towards.
+pub trait CameraController: fmt::Debug {
+    /// Moves by the held actions for the `dt` seconds they were held.
+    fn process_events(&mut self, actions: &ActionMap, dt: f32);
+
+    fn scroll_to_left(&mut self, dt: f32);
+
+    fn scroll_to_right(&mut self, dt: f32);
+
+    /// Turns the view so that what was seen along `from` is seen along `to`, like
+    /// grabbing the scene; both are unit directions from the eye.
+    fn drag(&mut self, from: cgmath::Vector3<f32>, to: cgmath::Vector3<f32>);
+
+    /// Moves closer to what is seen along the unit direction `direction` by `factor`,
+    /// or away from it if `factor` is below 1.
+    fn zoom(&mut self, direction: cgmath::Vector3<f32>, factor: f32);
+
+    /// Turns the heading as the scene is twisted clockwise on the screen by `angle` radians.
+    fn twist(&mut self, angle: f32);
+
+    /// Where the camera should be.
+    fn geometry(&self) -> CameraGeometry;
+}
+
+/// Moves the eye and the target together, like flying through the scene.
+/// The camera stays level with the up vector of the geometry it starts from.
+#[derive(Debug, Clone)]
+pub struct FreeFlyController {
+    geom: CameraGeometry,
+    up_axis: cgmath::Vector3<f32>,
+    speed: f32,
+}
+
+impl FreeFlyController {
+    pub fn new(geom: CameraGeometry, speed: f32) -> Self {
+        Self {
+            geom,
+            up_axis: geom.up(),
+            speed,
+        }
+    }
+
+    // Turns unless that would look straight along the up axis, where the roll is undefined
+    fn turn(&mut self, turn: impl FnOnce(&mut CameraGeometry)) {
+        let mut geom = self.geom;
+        turn(&mut geom);
+        if geom.level(self.up_axis) {
+            self.geom = geom;
+        }
+    }
+}
+
+impl CameraController for FreeFlyController {
+    fn process_events(&mut self, actions: &ActionMap, dt: f32) {
+        actions.values().for_each(|(action, value)| {
+            let speed = self.speed * value * dt;
+            match action {
+                Action::MoveForward => self.geom.move_forward(speed),
+                Action::MoveBackward => self.geom.move_backward(speed),
+                Action::MoveUp => self.geom.move_up(speed),
+                Action::MoveDown => self.geom.move_down(speed),
+                Action::MoveRight => self.geom.move_right(speed),
+                Action::MoveLeft => self.geom.move_left(speed),
+                Action::RotateRight => self.turn(|geom| geom.rotate_right(speed)),
+                Action::RotateLeft => self.turn(|geom| geom.rotate_left(speed)),
+                Action::RotateUp => self.turn(|geom| geom.rotate_up(speed)),
+                Action::RotateDown => self.turn(|geom| geom.rotate_down(speed)),
+                Action::ToggleCameraMode => {}
+            }
+        });
+    }
+
+    fn scroll_to_left(&mut self, dt: f32) {
+        let speed = self.speed * dt;
+        self.geom.move_left(speed);
+        self.turn(|geom| geom.rotate_right(speed));
+    }
+
+    fn scroll_to_right(&mut self, dt: f32) {
+        let speed = self.speed * dt;
+        self.geom.move_right(speed);
+        self.turn(|geom| geom.rotate_left(speed));
+    }
+
+    fn drag(&mut self, from: cgmath::Vector3<f32>, to: cgmath::Vector3<f32>) {
+        self.turn(|geom| geom.turn(cgmath::Quaternion::between_vectors(to, from)));
+    }
+
+    // Covers the same part of the distance to the target as the orbit controller
+    // does of its range
+    fn zoom(&mut self, direction: cgmath::Vector3<f32>, factor: f32) {
+        self.geom
+            .move_towards(direction, self.geom.distance() * (1.0 - 1.0 / factor));
+    }
+
+    fn twist(&mut self, angle: f32) {
+        let up_axis = self.up_axis;
+        self.turn(|geom| {
+            geom.turn(cgmath::Quaternion::from_axis_angle(
+                up_axis,
+                cgmath::Rad(angle),
+            ))
+        });
+    }
+
+    fn geometry(&self) -> CameraGeometry {
+        self.geom
+    }
+}
+
+/// Where an orbiting camera is around the centre of the Earth, with +Y to the north pole
+/// and longitude 0 towards +Z. Angles are in degrees.
+#[cfg_attr(feature = "web", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrbitView {
+    pub latitude: f32,
+    pub longitude: f32,
+    /// Height of the looked at point above the surface
+    pub altitude: f32,
+    /// Direction the camera faces, clockwise from north
+    pub heading: f32,
+    /// Angle from looking straight down; 90 would look at the horizon
+    pub tilt: f32,
+    /// Distance from the looked at point to the camera
+    pub range: f32,
+}
+
+#[cfg_attr(feature = "web", wasm_bindgen)]
+impl OrbitView {
+    #[cfg_attr(feature = "web", wasm_bindgen(constructor))]
+    pub fn new(
+        latitude: f32,
+        longitude: f32,
+        altitude: f32,
+        heading: f32,
+        tilt: f32,
+        range: f32,
+    ) -> Self {
+        Self {
+            latitude,
+            longitude,
+            altitude,
+            heading,
+            tilt,
+            range,
+        }
+    }
+}
+
+/// Orbits a point above the Earth, keeping the looked at point, and so the camera,
+/// at least `min_altitude` above the surface.
+#[derive(Debug, Clone)]
+pub struct OrbitController {
+    view: OrbitView,
+    radius: f32,
+    min_altitude: f32,
+}
+
+impl OrbitController {
+    /// `radius` is the radius of the Earth.
+    pub fn new(view: OrbitView, radius: f32, min_altitude: f32) -> Self {
+        let mut controller = Self {
+            view,
+            radius,
+            min_altitude,
+        };
+        controller.clamp();
+        controller
+    }
+
+    /// Looks straight down from the eye of `geom`, so taking over from another
+    /// controller does not move the eye.
+    pub fn from_geometry(geom: &CameraGeometry, radius: f32, min_altitude: f32) -> Self {
+        let eye = geom.eye().to_vec();
+        let direction = eye.normalize();
+        let view = OrbitView {
+            latitude: direction.y.asin().to_degrees(),
+            longitude: direction.x.atan2(direction.z).to_degrees(),
+            altitude: min_altitude,
+            heading: 0.0,
+            tilt: 0.0,
+            range: eye.magnitude() - radius - min_altitude,
+        };
+        Self::new(view, radius, min_altitude)
+    }
+
+    // The unit vector up at the looked at point and the horizontal one the camera faces
+    fn frame(&self) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+        let view = &self.view;
+        let (lat, lon) = (view.latitude.to_radians(), view.longitude.to_radians());
+        let heading = view.heading.to_radians();
+
+        let up = cgmath::Vector3::new(lat.cos() * lon.sin(), lat.sin(), lat.cos() * lon.cos());
+        let east = cgmath::Vector3::new(lon.cos(), 0.0, -lon.sin());
+        let north = up.cross(east);
+        (up, north * heading.cos() + east * heading.sin())
+    }
+
+    // Inverse of `frame`, keeping the altitude, tilt and range
+    fn set_frame(&mut self, up: cgmath::Vector3<f32>, forward: cgmath::Vector3<f32>) {
+        let up = up.normalize();
+        let lon = up.x.atan2(up.z);
+        let east = cgmath::Vector3::new(lon.cos(), 0.0, -lon.sin());
+        let north = up.cross(east);
+        self.view.latitude = up.y.clamp(-1.0, 1.0).asin().to_degrees();
+        self.view.longitude = lon.to_degrees();
+        self.view.heading = forward.dot(east).atan2(forward.dot(north)).to_degrees();
+        self.clamp();
+    }
+
+    // The unit vector towards where a ray from the eye meets the sphere through the looked
+    // at point, or towards the point of the sphere closest to the ray if it misses
+    fn grab(&self, direction: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        let eye = self.geometry().eye().to_vec();
+        let radius = self.radius + self.view.altitude;
+        let b = eye.dot(direction);
+        let discriminant = b * b - (eye.magnitude2() - radius * radius);
+        let t = if discriminant >= 0.0 && b < 0.0 {
+            -b - discriminant.sqrt()
+        } else {
+            (-b).max(0.0)
+        };
+        (eye + direction * t).normalize()
+    }
+
+    fn clamp(&mut self) {
+        let view = &mut self.view;
+        view.latitude = view.latitude.clamp(-90.0, 90.0);
+        view.longitude = (view.longitude + 180.0).rem_euclid(360.0) - 180.0;
+        view.altitude = view.altitude.max(self.min_altitude);
+        view.heading = view.heading.rem_euclid(360.0);
+        view.tilt = view.tilt.clamp(0.0, ORBIT_MAX_TILT);
+        view.range = view.range.max(ORBIT_MIN_RANGE);
+    }
+}
+
+impl CameraController for OrbitController {
+    fn process_events(&mut self, actions: &ActionMap, dt: f32) {
+        let view = &mut self.view;
+        actions.values().for_each(|(action, value)| {
+            let angle = ORBIT_ANGLE_SPEED * value * dt;
+            match action {
+                Action::MoveForward => view.range /= ORBIT_ZOOM_SPEED.powf(value * dt),
+                Action::MoveBackward => view.range *= ORBIT_ZOOM_SPEED.powf(value * dt),
+                Action::MoveUp => view.latitude += angle,
+                Action::MoveDown => view.latitude -= angle,
+                Action::MoveRight => view.longitude += angle,
+                Action::MoveLeft => view.longitude -= angle,
+                Action::RotateRight => view.heading += angle,
+                Action::RotateLeft => view.heading -= angle,
+                Action::RotateUp => view.tilt += angle,
+                Action::RotateDown => view.tilt -= angle,
+                Action::ToggleCameraMode => {}
+            }
+        });
+        self.clamp();
+    }
+
+    fn scroll_to_left(&mut self, dt: f32) {
+        self.view.longitude -= ORBIT_ANGLE_SPEED * dt;
+        self.clamp();
+    }
+
+    fn scroll_to_right(&mut self, dt: f32) {
+        self.view.longitude += ORBIT_ANGLE_SPEED * dt;
+        self.clamp();
+    }
+
+    // Turns the Earth under the camera so that the grabbed point follows the pointer
+    fn drag(&mut self, from: cgmath::Vector3<f32>, to: cgmath::Vector3<f32>) {
+        let rotation = cgmath::Quaternion::between_vectors(self.grab(to), self.grab(from));
+        let (up, forward) = self.frame();
+        self.set_frame(rotation.rotate_vector(up), rotation.rotate_vector(forward));
+    }
+
+    // Moves the looked at point towards the pointed at one while closing the range,
+    // which keeps that point roughly under the pointer
+    fn zoom(&mut self, direction: cgmath::Vector3<f32>, factor: f32) {
+        let point = self.grab(direction);
+        let (up, forward) = self.frame();
+        self.view.range /= factor;
+        self.set_frame(up + (point - up) * (1.0 - 1.0 / factor), forward);
+    }
+
+    fn twist(&mut self, angle: f32) {
+        self.view.heading -= angle.to_degrees();
+        self.clamp();
+    }
+
+    fn geometry(&self) -> CameraGeometry {
+        let view = &self.view;
+        let tilt = view.tilt.to_radians();
+        let (up, forward) = self.frame();
+
+        // The eye is above the target whenever the tilt is below 90 degrees,
+        // so it never gets closer to the centre than the target
+        let target = up * (self.radius + view.altitude);
+        let eye = target + (up * tilt.cos() - forward * tilt.sin()) * view.range;
+        let up_axis = up * tilt.sin() + forward * tilt.cos();
+        CameraGeometry::new(
+            cgmath::Point3::from_vec(eye),
+            cgmath::Point3::from_vec(target),
+            up_axis,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{action::InputProfile, gamepad::GamepadState};
+
+    fn orbit() -> OrbitController {
+        OrbitController::new(
+            OrbitView::new(10.0, 20.0, 0.0, 30.0, 40.0, 100.0),
+            50.0,
+            0.0,
+        )
+    }
+
+    fn gamepad(axes: Vec<f32>, buttons: Vec<f32>) -> GamepadState {
+        GamepadState {
+            id: "Gamepad".to_string(),
+            axes,
+            buttons,
+        }
+    }
+
+    #[test]
+    fn orbit_moves_by_the_strength_of_actions() {
+        let mut actions = ActionMap::new(InputProfile::default());
+        let mut buttons = vec![0.0; 8];
+        buttons[7] = 0.5;
+        actions.gamepad_input(&[gamepad(vec![0.6, 0.0, 0.0, -0.8], buttons)]);
+        let (right, up, forward) = (
+            actions.value(Action::MoveRight),
+            actions.value(Action::RotateUp),
+            actions.value(Action::MoveForward),
+        );
+        assert!(right > 0.0 && right < 1.0, "{}", right);
+        assert!(up > 0.0 && up < 1.0, "{}", up);
+        assert_eq!(forward, 0.5);
+
+        let dt = 0.1;
+        let mut controller = orbit();
+        controller.process_events(&actions, dt);
+        let (before, after) = (orbit().view, controller.view);
+        assert!((after.longitude - before.longitude - ORBIT_ANGLE_SPEED * right * dt).abs() < 1e-4);
+        assert!((after.tilt - before.tilt - ORBIT_ANGLE_SPEED * up * dt).abs() < 1e-4);
+        assert!((before.range / after.range - ORBIT_ZOOM_SPEED.powf(0.5 * dt)).abs() < 1e-5);
+        assert_eq!(after.latitude, before.latitude);
+        assert_eq!(after.heading, before.heading);
+    }
+
+    #[test]
+    fn orbit_moves_by_full_steps_for_keys() {
+        let mut actions = ActionMap::new(InputProfile::default());
+        actions.key_input("KeyD", false);
+        actions.key_input("KeyE", false);
+
+        let dt = 0.1;
+        let mut controller = orbit();
+        controller.process_events(&actions, dt);
+        let (before, after) = (orbit().view, controller.view);
+        assert!((after.longitude - before.longitude - ORBIT_ANGLE_SPEED * dt).abs() < 1e-4);
+        assert!((before.range / after.range - ORBIT_ZOOM_SPEED.powf(dt)).abs() < 1e-5);
+
+        // two updates of half the time move as far as one
+        let mut halves = orbit();
+        halves.process_events(&actions, dt / 2.0);
+        halves.process_events(&actions, dt / 2.0);
+        assert!((halves.view.longitude - after.longitude).abs() < 1e-4);
+        assert!((halves.view.range - after.range).abs() < 1e-3);
+    }
+
+    #[test]
+    fn orbit_scrolls_by_the_time_passed() {
+        let mut controller = orbit();
+        controller.scroll_to_right(0.2);
+        assert!(
+            (controller.view.longitude - orbit().view.longitude - ORBIT_ANGLE_SPEED * 0.2).abs()
+                < 1e-4
+        );
+        controller.scroll_to_left(0.1);
+        controller.scroll_to_left(0.1);
+        assert!((controller.view.longitude - orbit().view.longitude).abs() < 1e-4);
+    }
+
+    #[test]
+    fn free_fly_moves_by_the_time_passed() {
+        let geom = CameraGeometry::new(
+            cgmath::Point3::new(0.0, 0.0, 100.0),
+            cgmath::Point3::new(0.0, 0.0, 0.0),
+            cgmath::Vector3::unit_y(),
+        );
+        let mut actions = ActionMap::new(InputProfile::default());
+        actions.key_input("KeyE", false);
+        actions.key_input("KeyD", false);
+
+        let mut once = FreeFlyController::new(geom, 10.0);
+        once.process_events(&actions, 0.2);
+        let mut twice = FreeFlyController::new(geom, 10.0);
+        twice.process_events(&actions, 0.1);
+        twice.process_events(&actions, 0.1);
+
+        let (once, twice) = (once.geometry(), twice.geometry());
+        assert!((once.build_pos_vec() - geom.build_pos_vec()).magnitude() > 1.0);
+        assert!((once.build_pos_vec() - twice.build_pos_vec()).magnitude() < 1e-4);
+        assert!((once.build_target_vec() - twice.build_target_vec()).magnitude() < 1e-4);
+    }
+}
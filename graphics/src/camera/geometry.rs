@@ -1,45 +1,79 @@
-use cgmath::InnerSpace;
+use cgmath::{EuclideanSpace, InnerSpace, Rotation, Rotation3};
 
+// Below this the forward or right vector has no usable direction
+const EPSILON: f32 = 1e-6;
+// sin of the smallest angle kept between the forward vector and the up axis when leveling
+const MIN_UP_ANGLE_SIN: f32 = 0.0175;
+
+/// Where the camera is and where it looks. The orientation rotates the camera space,
+/// looking along -Z with +Y up, into the world.
 #[derive(Debug, Clone, Copy)]
 pub struct CameraGeometry {
     eye: cgmath::Point3<f32>,
-    target: cgmath::Point3<f32>,
-    up_axis: cgmath::Vector3<f32>,
+    orientation: cgmath::Quaternion<f32>,
+    // from the eye to the target
+    distance: f32,
 }
 
 impl CameraGeometry {
+    /// Looks from `eye` at `target` with `up_axis` up. If the target is at the eye, the camera
+    /// looks along -Z; if it is straight along `up_axis`, any perpendicular is taken as up.
     pub fn new(
         eye: cgmath::Point3<f32>,
         target: cgmath::Point3<f32>,
         up_axis: cgmath::Vector3<f32>,
     ) -> Self {
+        let forward = target - eye;
         Self {
             eye,
-            target,
-            up_axis,
+            orientation: look_rotation(forward, up_axis),
+            distance: forward.magnitude(),
         }
     }
 
-    pub fn tween(&mut self, goal: &CameraGeometry, prop: f32) {
-        self.eye = cgmath::Point3::new(
-            self.eye.x + (goal.eye.x - self.eye.x) * prop,
-            self.eye.y + (goal.eye.y - self.eye.y) * prop,
-            self.eye.z + (goal.eye.z - self.eye.z) * prop,
-        );
-        self.target = cgmath::Point3::new(
-            self.target.x + (goal.target.x - self.target.x) * prop,
-            self.target.y + (goal.target.y - self.target.y) * prop,
-            self.target.z + (goal.target.z - self.target.z) * prop,
-        );
-        self.up_axis = cgmath::Vector3::new(
-            self.up_axis.x + (goal.up_axis.x - self.up_axis.x) * prop,
-            self.up_axis.y + (goal.up_axis.y - self.up_axis.y) * prop,
-            self.up_axis.z + (goal.up_axis.z - self.up_axis.z) * prop,
-        );
+    pub fn from_orientation(
+        eye: cgmath::Point3<f32>,
+        orientation: cgmath::Quaternion<f32>,
+        distance: f32,
+    ) -> Self {
+        Self {
+            eye,
+            orientation: orientation.normalize(),
+            distance,
+        }
+    }
+
+    pub fn eye(&self) -> cgmath::Point3<f32> {
+        self.eye
+    }
+
+    pub fn target(&self) -> cgmath::Point3<f32> {
+        self.eye + self.forward() * self.distance
+    }
+
+    pub fn orientation(&self) -> cgmath::Quaternion<f32> {
+        self.orientation
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    pub fn forward(&self) -> cgmath::Vector3<f32> {
+        self.orientation.rotate_vector(-cgmath::Vector3::unit_z())
+    }
+
+    pub fn right(&self) -> cgmath::Vector3<f32> {
+        self.orientation.rotate_vector(cgmath::Vector3::unit_x())
+    }
+
+    pub fn up(&self) -> cgmath::Vector3<f32> {
+        self.orientation.rotate_vector(cgmath::Vector3::unit_y())
     }
 
     pub fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
-        cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up_axis)
+        cgmath::Matrix4::from(self.orientation.invert())
+            * cgmath::Matrix4::from_translation(-self.eye.to_vec())
     }
 
     pub fn build_pos_vec(&self) -> cgmath::Vector4<f32> {
@@ -47,82 +81,201 @@ impl CameraGeometry {
     }
 
     pub fn build_target_vec(&self) -> cgmath::Vector4<f32> {
-        self.target.to_homogeneous()
-    }
-
-    // forward, forward_norm, right, right_norm, up, up_norm
-    fn axis(
-        &self,
-    ) -> (
-        cgmath::Vector3<f32>,
-        cgmath::Vector3<f32>,
-        cgmath::Vector3<f32>,
-        cgmath::Vector3<f32>,
-        cgmath::Vector3<f32>,
-        cgmath::Vector3<f32>,
-    ) {
-        let forward = self.target - self.eye;
-        let forward_norm = forward.normalize();
-        let right = forward_norm.cross(self.up_axis);
-        let right_norm = right.normalize();
-        let up = right_norm.cross(forward_norm);
-        let up_norm = up.normalize();
-        (forward, forward_norm, right, right_norm, up, up_norm)
+        self.target().to_homogeneous()
+    }
+
+    /// Removes the roll around the forward vector relative to `up_axis`. Returns false
+    /// without changing anything when looking almost straight along `up_axis`.
+    pub fn level(&mut self, up_axis: cgmath::Vector3<f32>) -> bool {
+        let forward = self.forward();
+        if forward.cross(up_axis).magnitude() < MIN_UP_ANGLE_SIN * up_axis.magnitude() {
+            return false;
+        }
+        self.orientation = look_rotation(forward, up_axis);
+        true
+    }
+
+    pub fn move_towards(&mut self, direction: cgmath::Vector3<f32>, distance: f32) {
+        self.eye += direction * distance;
     }
 
     pub fn move_forward(&mut self, speed: f32) {
-        let (_, forward_norm, _, _, _, _) = self.axis();
-        self.eye += forward_norm * speed;
-        self.target += forward_norm * speed;
+        self.eye += self.forward() * speed;
     }
 
     pub fn move_backward(&mut self, speed: f32) {
-        let (_, forward_norm, _, _, _, _) = self.axis();
-        self.eye -= forward_norm * speed;
-        self.target -= forward_norm * speed;
+        self.eye -= self.forward() * speed;
     }
 
     pub fn move_up(&mut self, speed: f32) {
-        let (_, _, _, _, _, up_norm) = self.axis();
-        self.eye += up_norm * speed;
-        self.target += up_norm * speed;
+        self.eye += self.up() * speed;
     }
 
     pub fn move_down(&mut self, speed: f32) {
-        let (_, _, _, _, _, up_norm) = self.axis();
-        self.eye -= up_norm * speed;
-        self.target -= up_norm * speed;
+        self.eye -= self.up() * speed;
     }
 
     pub fn move_right(&mut self, speed: f32) {
-        let (_, _, _, right_norm, _, _) = self.axis();
-        self.eye += right_norm * speed;
-        self.target += right_norm * speed;
+        self.eye += self.right() * speed;
     }
 
     pub fn move_left(&mut self, speed: f32) {
-        let (_, _, _, right_norm, _, _) = self.axis();
-        self.eye -= right_norm * speed;
-        self.target -= right_norm * speed;
+        self.eye -= self.right() * speed;
+    }
+
+    /// Turns the camera around the eye by `rotation` in world space.
+    pub fn turn(&mut self, rotation: cgmath::Quaternion<f32>) {
+        self.orientation = (rotation * self.orientation).normalize();
+    }
+
+    // Turns the target by `speed` towards `axis` at its distance, as if it were moved aside
+    fn rotate(&mut self, axis: cgmath::Vector3<f32>, speed: f32) {
+        let angle = cgmath::Rad(speed.atan2(self.distance.max(EPSILON)));
+        self.turn(cgmath::Quaternion::from_axis_angle(axis, -angle));
     }
 
     pub fn rotate_right(&mut self, speed: f32) {
-        let (forward, _, right, _, _, _) = self.axis();
-        self.target = self.eye + (forward + right * speed).normalize() * forward.magnitude();
+        self.rotate(self.up(), speed);
     }
 
     pub fn rotate_left(&mut self, speed: f32) {
-        let (forward, _, right, _, _, _) = self.axis();
-        self.target = self.eye + (forward - right * speed).normalize() * forward.magnitude();
+        self.rotate(self.up(), -speed);
     }
 
     pub fn rotate_up(&mut self, speed: f32) {
-        let (forward, _, _, _, up, _) = self.axis();
-        self.target = self.eye + (forward + up * speed).normalize() * forward.magnitude();
+        self.rotate(self.right(), -speed);
     }
 
     pub fn rotate_down(&mut self, speed: f32) {
-        let (forward, _, _, _, up, _) = self.axis();
-        self.target = self.eye + (forward - up * speed).normalize() * forward.magnitude();
+        self.rotate(self.right(), speed);
+    }
+}
+
+// The orientation looking along `forward` with `up_axis` as close to up as possible
+fn look_rotation(
+    forward: cgmath::Vector3<f32>,
+    up_axis: cgmath::Vector3<f32>,
+) -> cgmath::Quaternion<f32> {
+    let forward = if forward.magnitude() < EPSILON {
+        -cgmath::Vector3::unit_z()
+    } else {
+        forward.normalize()
+    };
+    let mut right = forward.cross(up_axis);
+    if right.magnitude() < EPSILON {
+        // any axis not along `forward` gives a perpendicular
+        let other = if forward.x.abs() < 0.9 {
+            cgmath::Vector3::unit_x()
+        } else {
+            cgmath::Vector3::unit_z()
+        };
+        right = forward.cross(other);
+    }
+    let right = right.normalize();
+    let up = right.cross(forward);
+    cgmath::Quaternion::from(cgmath::Matrix3::from_cols(right, up, -forward)).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Point3, Vector3};
+
+    use super::*;
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    // The camera axes are unit length, perpendicular and right-handed
+    fn assert_orthonormal(geometry: &CameraGeometry) {
+        let (forward, right, up) = (geometry.forward(), geometry.right(), geometry.up());
+        for axis in [forward, right, up] {
+            assert!((axis.magnitude() - 1.0).abs() < 1e-4, "{:?}", axis);
+        }
+        assert!(forward.dot(right).abs() < 1e-4);
+        assert!(forward.dot(up).abs() < 1e-4);
+        assert_near(right.cross(up), -forward);
+    }
+
+    #[test]
+    fn looks_at_target() {
+        let geometry = CameraGeometry::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Point3::new(3.0, 0.0, 1.0),
+            Vector3::unit_y(),
+        );
+        assert_orthonormal(&geometry);
+        assert_near(geometry.forward(), Vector3::new(0.6, 0.0, -0.8));
+        assert_near(geometry.up(), Vector3::unit_y());
+        assert!((geometry.distance() - 5.0).abs() < 1e-5);
+        assert_near(geometry.target().to_vec(), Vector3::new(3.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn looks_straight_up_and_down() {
+        for direction in [1.0, -1.0] {
+            let target = Point3::new(0.0, 2.0 * direction, 0.0);
+            let geometry = CameraGeometry::new(Point3::origin(), target, Vector3::unit_y());
+            assert_orthonormal(&geometry);
+            assert_near(geometry.forward(), Vector3::unit_y() * direction);
+            assert_near(geometry.target().to_vec(), target.to_vec());
+        }
+    }
+
+    #[test]
+    fn target_at_eye_looks_along_negative_z() {
+        let eye = Point3::new(1.0, 2.0, 3.0);
+        let geometry = CameraGeometry::new(eye, eye, Vector3::unit_y());
+        assert_orthonormal(&geometry);
+        assert_near(geometry.forward(), -Vector3::unit_z());
+        assert_near(geometry.up(), Vector3::unit_y());
+        assert_eq!(geometry.distance(), 0.0);
+        assert_eq!(geometry.target(), eye);
+    }
+
+    #[test]
+    fn level_removes_roll() {
+        let mut geometry = CameraGeometry::new(
+            Point3::new(0.0, 1.0, 5.0),
+            Point3::origin(),
+            Vector3::unit_y(),
+        );
+        let forward = geometry.forward();
+        geometry.turn(cgmath::Quaternion::from_axis_angle(forward, Deg(30.0)));
+        assert!(geometry.right().dot(Vector3::unit_y()).abs() > 0.1);
+
+        assert!(geometry.level(Vector3::unit_y()));
+        assert_orthonormal(&geometry);
+        assert_near(geometry.forward(), forward);
+        assert!(geometry.right().dot(Vector3::unit_y()).abs() < 1e-5);
+        assert!(geometry.up().dot(Vector3::unit_y()) > 0.0);
+    }
+
+    #[test]
+    fn level_keeps_orientation_along_up_axis() {
+        for direction in [1.0, -1.0] {
+            let mut geometry = CameraGeometry::new(
+                Point3::origin(),
+                Point3::new(0.0, direction, 0.0),
+                Vector3::unit_y(),
+            );
+            let orientation = geometry.orientation();
+            assert!(!geometry.level(Vector3::unit_y()));
+            assert_eq!(geometry.orientation(), orientation);
+        }
+    }
+
+    #[test]
+    fn view_matrix_puts_target_ahead() {
+        let geometry = CameraGeometry::new(
+            Point3::new(4.0, 3.0, -2.0),
+            Point3::new(-1.0, 0.5, 2.0),
+            Vector3::unit_y(),
+        );
+        let target = geometry.build_view_matrix() * geometry.build_target_vec();
+        assert_near(
+            target.truncate(),
+            Vector3::new(0.0, 0.0, -geometry.distance()),
+        );
     }
 }
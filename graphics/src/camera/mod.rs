@@ -1,8 +1,12 @@
 use perspective::CameraPerspective;
 use wgpu::util::DeviceExt;
 
+use crate::layout::uniform_layout;
+
+pub mod controller;
 pub mod geometry;
 pub mod perspective;
+pub mod spring;
 
 pub struct Camera {
     pub buffer: wgpu::Buffer,
@@ -18,9 +22,13 @@ pub struct CameraUniform {
     target_pos: [f32; 4],
     view_proj: [[f32; 4]; 4],
     aspect: f32,
-    _padding: [f32; 7],
+    _padding: [f32; 3],
 }
 
+uniform_layout!(CameraUniform = "CameraUniform" in "include/camera.wgsl" {
+    view_pos, target_pos, view_proj, aspect, _padding,
+});
+
 impl Camera {
     pub fn new(device: &wgpu::Device, perspective: CameraPerspective) -> Self {
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -1,104 +1,127 @@
-use crate::key::KeyStateMap;
-
-use super::{geometry::CameraGeometry, CameraUniform};
-
-#[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
-    1.0, 0.0, 0.0, 0.0,
-    0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
-);
-
-const KEY_MOVE_UP: &str = "w";
-const KEY_MOVE_DOWN: &str = "s";
-const KEY_MOVE_FORWARD: &str = "e";
-const KEY_MOVE_BACKWARD: &str = "q";
-const KEY_MOVE_RIGHT: &str = "d";
-const KEY_MOVE_LEFT: &str = "a";
-
-const KEY_ROTATE_UP: &str = "i";
-const KEY_ROTATE_DOWN: &str = "k";
-const KEY_ROTATE_RIGHT: &str = "l";
-const KEY_ROTATE_LEFT: &str = "j";
+use cgmath::{InnerSpace, Rotation};
 
+use crate::{action::ActionMap, gesture::Gesture};
+
+use super::{
+    controller::CameraController, geometry::CameraGeometry, spring::CameraSpring, CameraUniform,
+};
+
+// Seconds the camera takes to roughly catch up with its controller
+const SMOOTH_TIME: f32 = 0.2;
+
+/// Perspective projection with reversed Z and an infinite far plane:
+/// depth is 1 at `znear` and approaches 0 at infinity,
+/// which keeps the precision across the distance between the Earth and the sun.
+pub fn reversed_infinite_perspective(
+    fovy: cgmath::Deg<f32>,
+    aspect: f32,
+    znear: f32,
+) -> cgmath::Matrix4<f32> {
+    let f = 1.0 / (cgmath::Rad::from(fovy).0 / 2.0).tan();
+    cgmath::Matrix4::from_cols(
+        cgmath::Vector4::new(f / aspect, 0.0, 0.0, 0.0),
+        cgmath::Vector4::new(0.0, f, 0.0, 0.0),
+        cgmath::Vector4::new(0.0, 0.0, 0.0, -1.0),
+        cgmath::Vector4::new(0.0, 0.0, znear, 0.0),
+    )
+}
+
+/// Eases the camera towards the geometry of its controller.
+#[derive(Debug)]
 pub struct CameraPerspective {
     geom_current: CameraGeometry,
-    geom_goal: CameraGeometry,
-    speed: f32,
+    spring: CameraSpring,
+    controller: Box<dyn CameraController>,
     aspect: f32,
     fovy: f32,
     znear: f32,
-    zfar: f32,
 }
 
 impl CameraPerspective {
-    pub fn new(
-        geom: CameraGeometry,
-        speed: f32,
-        aspect: f32,
-        fovy: f32,
-        znear: f32,
-        zfar: f32,
-    ) -> Self {
+    pub fn new(controller: Box<dyn CameraController>, aspect: f32, fovy: f32, znear: f32) -> Self {
         Self {
-            geom_current: geom,
-            geom_goal: geom,
-            speed,
+            geom_current: controller.geometry(),
+            spring: CameraSpring::new(SMOOTH_TIME),
+            controller,
             aspect,
             fovy,
             znear,
-            zfar,
         }
     }
 
+    /// The geometry the camera eases towards, e.g. to start another controller from.
+    pub fn goal(&self) -> CameraGeometry {
+        self.controller.geometry()
+    }
+
+    /// The camera eases from where it is to the geometry of `controller`.
+    pub fn set_controller(&mut self, controller: Box<dyn CameraController>) {
+        self.controller = controller;
+    }
+
     pub fn update_aspect(&mut self, aspect: f32) {
         self.aspect = aspect;
     }
 
     pub fn build_uniform(&self) -> CameraUniform {
         let view = self.geom_current.build_view_matrix();
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
-        let view_proj = OPENGL_TO_WGPU_MATRIX * proj * view;
+        let proj = reversed_infinite_perspective(cgmath::Deg(self.fovy), self.aspect, self.znear);
+        let view_proj = proj * view;
         CameraUniform {
             view_pos: self.geom_current.build_pos_vec().into(),
             target_pos: self.geom_current.build_target_vec().into(),
             view_proj: view_proj.into(),
             aspect: self.aspect,
-            _padding: [0.0; 7],
+            _padding: [0.0; 3],
+        }
+    }
+
+    /// Moves the controller by the actions held for `dt` seconds.
+    pub fn process_events(&mut self, actions: &ActionMap, dt: f32) {
+        self.controller.process_events(actions, dt);
+    }
+
+    /// Passes a gesture on to the controller; its positions are in a viewport of
+    /// `width` by `height` pixels.
+    pub fn apply_gesture(&mut self, gesture: Gesture, width: f32, height: f32) {
+        let ray = |position| self.ray(position, width, height);
+        match gesture {
+            Gesture::Drag { from, to } => {
+                let (from, to) = (ray(from), ray(to));
+                self.controller.drag(from, to);
+            }
+            Gesture::Zoom { center, factor } => {
+                let direction = ray(center);
+                self.controller.zoom(direction, factor);
+            }
+            Gesture::Twist { angle, .. } => self.controller.twist(angle),
         }
     }
 
-    pub fn process_events(&mut self, key_states: &KeyStateMap) {
-        key_states
-            .iter()
-            .filter(|(_, state)| state.is_pressing())
-            .for_each(|(key, _)| match key.as_str() {
-                KEY_MOVE_FORWARD => self.geom_goal.move_forward(self.speed),
-                KEY_MOVE_BACKWARD => self.geom_goal.move_backward(self.speed),
-                KEY_MOVE_UP => self.geom_goal.move_up(self.speed),
-                KEY_MOVE_DOWN => self.geom_goal.move_down(self.speed),
-                KEY_MOVE_RIGHT => self.geom_goal.move_right(self.speed),
-                KEY_MOVE_LEFT => self.geom_goal.move_left(self.speed),
-                KEY_ROTATE_RIGHT => self.geom_goal.rotate_right(self.speed),
-                KEY_ROTATE_LEFT => self.geom_goal.rotate_left(self.speed),
-                KEY_ROTATE_UP => self.geom_goal.rotate_up(self.speed),
-                KEY_ROTATE_DOWN => self.geom_goal.rotate_down(self.speed),
-                _ => {}
-            });
+    // The unit direction from the eye through a pixel, as the controller has the camera
+    fn ray(&self, position: cgmath::Vector2<f32>, width: f32, height: f32) -> cgmath::Vector3<f32> {
+        let x = 2.0 * position.x / width.max(1.0) - 1.0;
+        let y = 1.0 - 2.0 * position.y / height.max(1.0);
+        let scale = (cgmath::Rad::from(cgmath::Deg(self.fovy)).0 / 2.0).tan();
+        let direction = cgmath::Vector3::new(x * scale * self.aspect, y * scale, -1.0);
+        self.controller
+            .geometry()
+            .orientation()
+            .rotate_vector(direction)
+            .normalize()
     }
 
-    pub fn scroll_to_left(&mut self) {
-        self.geom_goal.move_left(self.speed);
-        self.geom_goal.rotate_right(self.speed);
+    pub fn scroll_to_left(&mut self, dt: f32) {
+        self.controller.scroll_to_left(dt);
     }
 
-    pub fn scroll_to_right(&mut self) {
-        self.geom_goal.move_right(self.speed);
-        self.geom_goal.rotate_left(self.speed);
+    pub fn scroll_to_right(&mut self, dt: f32) {
+        self.controller.scroll_to_right(dt);
     }
 
-    pub fn tween(&mut self, prop: f32) {
-        self.geom_current.tween(&self.geom_goal, prop);
+    /// Moves the camera `dt` seconds closer to the geometry of the controller.
+    pub fn tween(&mut self, dt: f32) {
+        let goal = self.controller.geometry();
+        self.geom_current = self.spring.step(&self.geom_current, &goal, dt);
     }
 }
@@ -0,0 +1,170 @@
+use std::ops::{Add, Mul, Sub};
+
+use cgmath::{InnerSpace, Zero};
+
+use super::geometry::CameraGeometry;
+
+// Below this angle in radians the orientation snaps to the goal
+const ANGLE_EPSILON: f32 = 1e-5;
+
+/// Moves a camera geometry towards a goal as a critically damped spring, which arrives
+/// without overshooting. Steps of any length give the same motion.
+#[derive(Debug, Clone)]
+pub struct CameraSpring {
+    // angular frequency; the spring is mostly settled after 4 / omega seconds
+    omega: f32,
+    eye_velocity: cgmath::Vector3<f32>,
+    distance_velocity: f32,
+    // change of the angle to the goal orientation, in radians per second
+    angular_velocity: f32,
+}
+
+impl CameraSpring {
+    /// `smooth_time` is roughly how long in seconds the camera takes to catch up.
+    pub fn new(smooth_time: f32) -> Self {
+        Self {
+            omega: 2.0 / smooth_time.max(f32::EPSILON),
+            eye_velocity: cgmath::Vector3::zero(),
+            distance_velocity: 0.0,
+            angular_velocity: 0.0,
+        }
+    }
+
+    /// Advances `current` by `dt` seconds, interpolating the orientation with slerp.
+    pub fn step(
+        &mut self,
+        current: &CameraGeometry,
+        goal: &CameraGeometry,
+        dt: f32,
+    ) -> CameraGeometry {
+        let dt = dt.max(0.0);
+        let eye = goal.eye()
+            + damp(
+                current.eye() - goal.eye(),
+                &mut self.eye_velocity,
+                self.omega,
+                dt,
+            );
+        let distance = goal.distance()
+            + damp(
+                current.distance() - goal.distance(),
+                &mut self.distance_velocity,
+                self.omega,
+                dt,
+            );
+
+        // the angle is closed along the shortest arc, so the velocity is a scalar
+        let from = current.orientation();
+        let mut to = goal.orientation();
+        if from.dot(to) < 0.0 {
+            to = -to;
+        }
+        let angle = 2.0 * from.dot(to).clamp(-1.0, 1.0).acos();
+        let orientation = if angle < ANGLE_EPSILON {
+            self.angular_velocity = 0.0;
+            to
+        } else {
+            let remaining = damp(angle, &mut self.angular_velocity, self.omega, dt);
+            from.slerp(to, (1.0 - remaining / angle).clamp(0.0, 1.0))
+        };
+
+        CameraGeometry::from_orientation(eye, orientation, distance)
+    }
+}
+
+// Exact step of a critically damped spring pulling `offset` from its rest position to zero
+fn damp<V>(offset: V, velocity: &mut V, omega: f32, dt: f32) -> V
+where
+    V: Copy + Add<Output = V> + Sub<Output = V> + Mul<f32, Output = V>,
+{
+    let decay = (-omega * dt).exp();
+    let temp = (*velocity + offset * omega) * dt;
+    *velocity = (*velocity - temp * omega) * decay;
+    (offset + temp) * decay
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{EuclideanSpace, Point3, Vector3};
+
+    use super::*;
+
+    fn start() -> CameraGeometry {
+        CameraGeometry::new(
+            Point3::new(0.0, 0.0, 10.0),
+            Point3::origin(),
+            Vector3::unit_y(),
+        )
+    }
+
+    fn goal() -> CameraGeometry {
+        CameraGeometry::new(
+            Point3::new(6.0, 2.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            Vector3::unit_y(),
+        )
+    }
+
+    // Steps `frames` times over `seconds`
+    fn run(seconds: f32, frames: u32) -> CameraGeometry {
+        let mut spring = CameraSpring::new(0.25);
+        let (mut current, goal) = (start(), goal());
+        for _ in 0..frames {
+            current = spring.step(&current, &goal, seconds / frames as f32);
+        }
+        current
+    }
+
+    fn angle(a: &CameraGeometry, b: &CameraGeometry) -> f32 {
+        2.0 * a.orientation().dot(b.orientation()).abs().min(1.0).acos()
+    }
+
+    #[test]
+    fn converges_to_goal() {
+        let current = run(2.0, 120);
+        let goal = goal();
+        assert!((current.eye() - goal.eye()).magnitude() < 1e-3);
+        assert!((current.distance() - goal.distance()).abs() < 1e-3);
+        assert!(angle(&current, &goal) < 1e-3);
+    }
+
+    #[test]
+    fn motion_does_not_depend_on_frame_rate() {
+        let reference = run(0.3, 144);
+        for frames in [1, 3, 18, 30] {
+            let current = run(0.3, frames);
+            assert!(
+                (current.eye() - reference.eye()).magnitude() < 1e-3,
+                "{} frames: {:?} != {:?}",
+                frames,
+                current.eye(),
+                reference.eye()
+            );
+            assert!((current.distance() - reference.distance()).abs() < 1e-3);
+            assert!(angle(&current, &reference) < 1e-3, "{} frames", frames);
+        }
+    }
+
+    #[test]
+    fn arrives_without_overshooting() {
+        let mut spring = CameraSpring::new(0.25);
+        let (mut current, goal) = (start(), goal());
+        let mut previous = f32::INFINITY;
+        for _ in 0..120 {
+            current = spring.step(&current, &goal, 1.0 / 60.0);
+            let offset = current.eye() - goal.eye();
+            // the eye stays on the side it started from, getting closer
+            assert!(offset.dot(start().eye() - goal.eye()) >= 0.0);
+            assert!(offset.magnitude() <= previous);
+            previous = offset.magnitude();
+        }
+    }
+
+    #[test]
+    fn zero_step_stays() {
+        let mut spring = CameraSpring::new(0.25);
+        let current = spring.step(&start(), &goal(), 0.0);
+        assert!((current.eye() - start().eye()).magnitude() < 1e-6);
+        assert!(angle(&current, &start()) < 1e-3);
+    }
+}
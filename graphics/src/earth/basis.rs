@@ -0,0 +1,503 @@
+// Transcodes Basis Universal KTX2 textures with the basis-universal crate. It only reads
+// `.basis` files, which hold the same ETC1S and UASTC data as KTX2 in another container,
+// so the levels are repacked into one before transcoding.
+
+use basis_universal::{TranscodeParameters, Transcoder, TranscoderTextureFormat};
+use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+
+use super::texture::{TextureCompression, TextureError};
+
+const BASIS_HEADER_SIZE: usize = 77;
+const BASIS_SLICE_DESC_SIZE: usize = 23;
+const BASIS_VERSION: u16 = 0x13;
+const BASIS_FLAG_ETC1S: u16 = 1;
+const BASIS_FLAG_HAS_ALPHA_SLICES: u16 = 4;
+const BASIS_FLAG_SRGB: u16 = 16;
+const BASIS_SLICE_HAS_ALPHA: u8 = 1;
+
+const ETC1S_GLOBAL_HEADER_SIZE: usize = 20;
+const ETC1S_IMAGE_DESC_SIZE: usize = 20;
+const ETC1S_IMAGE_IS_P_FRAME: u32 = 2;
+
+// KHR_DF_CHANNEL_UASTC_RGBA and KHR_DF_CHANNEL_UASTC_RRRG
+const UASTC_ALPHA_CHANNELS: [u8; 2] = [3, 5];
+
+/// Uncompressed levels in `format`, largest first.
+pub struct Transcoded {
+    pub format: TextureFormat,
+    pub levels: Vec<Vec<u8>>,
+}
+
+/// Transcodes an ETC1S or UASTC texture to the best format `features` allow: BC7, ASTC 4x4
+/// or ETC2, or RGBA8 without any of them. `levels` are the level data without zstd
+/// supercompression, largest first.
+pub fn transcode(
+    reader: &ktx2::Reader<&[u8]>,
+    levels: &[Vec<u8>],
+    features: wgpu::Features,
+) -> anyhow::Result<Transcoded> {
+    let header = reader.header();
+    let srgb = reader.transfer_function() == Some(ktx2::TransferFunction::SRGB);
+    let width = header.pixel_width;
+    let height = header.pixel_height.max(1);
+
+    let basis = match reader.color_model() {
+        Some(ktx2::ColorModel::ETC1S) => repack_etc1s(
+            reader.supercompression_global_data(),
+            levels,
+            width,
+            height,
+            srgb,
+        )?,
+        Some(ktx2::ColorModel::UASTC) => {
+            let has_alpha = reader.basic_dfd().is_some_and(|dfd| {
+                dfd.sample_information
+                    .first()
+                    .is_some_and(|sample| UASTC_ALPHA_CHANNELS.contains(&sample.channel_type))
+            });
+            repack_uastc(levels, width, height, has_alpha, srgb)
+        }
+        model => return Err(TextureError::Transcode(format!("Color model {:?}", model)).into()),
+    };
+
+    let mut transcoder = Transcoder::new();
+    transcoder
+        .prepare_transcoding(&basis.data)
+        .map_err(|()| TextureError::Transcode("Invalid Basis Universal data".to_string()))?;
+    let (target, format) = target_format(features, basis.has_alpha, srgb);
+    let levels = (0..levels.len() as u32)
+        .map(|level_index| {
+            transcoder
+                .transcode_image_level(
+                    &basis.data,
+                    target,
+                    TranscodeParameters {
+                        image_index: 0,
+                        level_index,
+                        ..Default::default()
+                    },
+                )
+                .map_err(|e| TextureError::Transcode(format!("Level {}: {:?}", level_index, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    transcoder.end_transcoding();
+
+    Ok(Transcoded { format, levels })
+}
+
+// The transcoder output and matching texture format for the first supported compression
+fn target_format(
+    features: wgpu::Features,
+    has_alpha: bool,
+    srgb: bool,
+) -> (TranscoderTextureFormat, TextureFormat) {
+    let compression = [
+        TextureCompression::Bc,
+        TextureCompression::Astc,
+        TextureCompression::Etc2,
+    ]
+    .into_iter()
+    .find(|compression| features.contains(compression.feature()));
+    let (target, format) = match compression {
+        Some(TextureCompression::Bc) => (
+            TranscoderTextureFormat::BC7_RGBA,
+            TextureFormat::Bc7RgbaUnorm,
+        ),
+        Some(TextureCompression::Astc) => (
+            TranscoderTextureFormat::ASTC_4x4_RGBA,
+            TextureFormat::Astc {
+                block: AstcBlock::B4x4,
+                channel: AstcChannel::Unorm,
+            },
+        ),
+        // ETC1 is a subset of ETC2 RGB
+        Some(TextureCompression::Etc2) if !has_alpha => (
+            TranscoderTextureFormat::ETC1_RGB,
+            TextureFormat::Etc2Rgb8Unorm,
+        ),
+        Some(TextureCompression::Etc2) => (
+            TranscoderTextureFormat::ETC2_RGBA,
+            TextureFormat::Etc2Rgba8Unorm,
+        ),
+        None => (TranscoderTextureFormat::RGBA32, TextureFormat::Rgba8Unorm),
+    };
+    if srgb {
+        (target, format.add_srgb_suffix())
+    } else {
+        (target, format)
+    }
+}
+
+// A `.basis` file with one image
+struct BasisFile {
+    data: Vec<u8>,
+    has_alpha: bool,
+}
+
+// The compressed data of a slice, i.e. of a level or its alpha
+struct Slice<'a> {
+    level: u32,
+    alpha: bool,
+    data: &'a [u8],
+}
+
+// ETC1S levels share the codebooks and Huffman tables of the supercompression global data,
+// which starts with their sizes and a description of each image.
+fn repack_etc1s(
+    global_data: &[u8],
+    levels: &[Vec<u8>],
+    width: u32,
+    height: u32,
+    srgb: bool,
+) -> anyhow::Result<BasisFile> {
+    let invalid = || TextureError::Transcode("Truncated BasisLZ global data".to_string());
+    let u16_at = |offset: usize| -> Result<u16, TextureError> {
+        let bytes = global_data.get(offset..offset + 2).ok_or_else(invalid)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+    let u32_at = |offset: usize| -> Result<u32, TextureError> {
+        let bytes = global_data.get(offset..offset + 4).ok_or_else(invalid)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+
+    let endpoint_count = u16_at(0)?;
+    let selector_count = u16_at(2)?;
+    let endpoints_length = u32_at(4)? as usize;
+    let selectors_length = u32_at(8)? as usize;
+    let tables_length = u32_at(12)? as usize;
+
+    let mut slices = Vec::new();
+    let mut has_alpha = false;
+    for (level, data) in levels.iter().enumerate() {
+        let desc = ETC1S_GLOBAL_HEADER_SIZE + level * ETC1S_IMAGE_DESC_SIZE;
+        if u32_at(desc)? & ETC1S_IMAGE_IS_P_FRAME != 0 {
+            return Err(TextureError::UnsupportedLayout.into());
+        }
+        let slice = |offset: u32, length: u32| {
+            data.get(offset as usize..offset as usize + length as usize)
+                .ok_or_else(invalid)
+        };
+        slices.push(Slice {
+            level: level as u32,
+            alpha: false,
+            data: slice(u32_at(desc + 4)?, u32_at(desc + 8)?)?,
+        });
+        let alpha_length = u32_at(desc + 16)?;
+        if alpha_length > 0 {
+            has_alpha = true;
+            slices.push(Slice {
+                level: level as u32,
+                alpha: true,
+                data: slice(u32_at(desc + 12)?, alpha_length)?,
+            });
+        }
+    }
+
+    let codebooks = ETC1S_GLOBAL_HEADER_SIZE + levels.len() * ETC1S_IMAGE_DESC_SIZE;
+    let endpoints = global_data
+        .get(codebooks..codebooks + endpoints_length)
+        .ok_or_else(invalid)?;
+    let selectors_start = codebooks + endpoints_length;
+    let selectors = global_data
+        .get(selectors_start..selectors_start + selectors_length)
+        .ok_or_else(invalid)?;
+    let tables_start = selectors_start + selectors_length;
+    let tables = global_data
+        .get(tables_start..tables_start + tables_length)
+        .ok_or_else(invalid)?;
+
+    let data = write_basis(
+        0,
+        BASIS_FLAG_ETC1S,
+        width,
+        height,
+        srgb,
+        &slices,
+        Codebooks {
+            endpoint_count,
+            endpoints,
+            selector_count,
+            selectors,
+            tables,
+        },
+    );
+    Ok(BasisFile { data, has_alpha })
+}
+
+// UASTC levels are plain 4x4 blocks, with alpha in the same slice
+fn repack_uastc(
+    levels: &[Vec<u8>],
+    width: u32,
+    height: u32,
+    has_alpha: bool,
+    srgb: bool,
+) -> BasisFile {
+    let slices = levels
+        .iter()
+        .enumerate()
+        .map(|(level, data)| Slice {
+            level: level as u32,
+            alpha: has_alpha,
+            data,
+        })
+        .collect::<Vec<_>>();
+    let data = write_basis(1, 0, width, height, srgb, &slices, Codebooks::default());
+    BasisFile { data, has_alpha }
+}
+
+#[derive(Default)]
+struct Codebooks<'a> {
+    endpoint_count: u16,
+    endpoints: &'a [u8],
+    selector_count: u16,
+    selectors: &'a [u8],
+    tables: &'a [u8],
+}
+
+// Lays out the header, the slice descriptions, the codebooks and then the slices,
+// following `basisu_file_headers.h`: packed little-endian integers of 1 to 4 bytes.
+fn write_basis(
+    tex_format: u8,
+    mut flags: u16,
+    width: u32,
+    height: u32,
+    srgb: bool,
+    slices: &[Slice],
+    codebooks: Codebooks,
+) -> Vec<u8> {
+    if slices.iter().any(|slice| slice.alpha) {
+        flags |= BASIS_FLAG_HAS_ALPHA_SLICES;
+    }
+    if srgb {
+        flags |= BASIS_FLAG_SRGB;
+    }
+    let slice_descs = BASIS_HEADER_SIZE;
+    let endpoints = slice_descs + slices.len() * BASIS_SLICE_DESC_SIZE;
+    let selectors = endpoints + codebooks.endpoints.len();
+    let tables = selectors + codebooks.selectors.len();
+    let mut offset = tables + codebooks.tables.len();
+
+    let mut body = Vec::new();
+    for slice in slices {
+        let level_width = (width >> slice.level).max(1);
+        let level_height = (height >> slice.level).max(1);
+        put(&mut body, 0, 3);
+        put(&mut body, slice.level, 1);
+        put(
+            &mut body,
+            if slice.alpha {
+                BASIS_SLICE_HAS_ALPHA
+            } else {
+                0
+            } as u32,
+            1,
+        );
+        put(&mut body, level_width, 2);
+        put(&mut body, level_height, 2);
+        put(&mut body, level_width.div_ceil(4), 2);
+        put(&mut body, level_height.div_ceil(4), 2);
+        put(&mut body, offset as u32, 4);
+        put(&mut body, slice.data.len() as u32, 4);
+        put(&mut body, crc16(slice.data) as u32, 2);
+        offset += slice.data.len();
+    }
+    body.extend_from_slice(codebooks.endpoints);
+    body.extend_from_slice(codebooks.selectors);
+    body.extend_from_slice(codebooks.tables);
+    for slice in slices {
+        body.extend_from_slice(slice.data);
+    }
+
+    let mut header = Vec::with_capacity(BASIS_HEADER_SIZE);
+    put(&mut header, (b'B' as u32) << 8 | b's' as u32, 2);
+    put(&mut header, BASIS_VERSION as u32, 2);
+    put(&mut header, BASIS_HEADER_SIZE as u32, 2);
+    // header CRC, filled in below
+    put(&mut header, 0, 2);
+    put(&mut header, body.len() as u32, 4);
+    put(&mut header, crc16(&body) as u32, 2);
+    put(&mut header, slices.len() as u32, 3);
+    // one image
+    put(&mut header, 1, 3);
+    put(&mut header, tex_format as u32, 1);
+    put(&mut header, flags as u32, 2);
+    // 2D
+    put(&mut header, 0, 1);
+    // microseconds per frame, reserved and user data
+    put(&mut header, 0, 3);
+    put(&mut header, 0, 4);
+    put(&mut header, 0, 4);
+    put(&mut header, 0, 4);
+    put(&mut header, codebooks.endpoint_count as u32, 2);
+    put(&mut header, endpoints as u32, 4);
+    put(&mut header, codebooks.endpoints.len() as u32, 3);
+    put(&mut header, codebooks.selector_count as u32, 2);
+    put(&mut header, selectors as u32, 4);
+    put(&mut header, codebooks.selectors.len() as u32, 3);
+    put(&mut header, tables as u32, 4);
+    put(&mut header, codebooks.tables.len() as u32, 4);
+    put(&mut header, slice_descs as u32, 4);
+    // no extended data
+    put(&mut header, 0, 4);
+    put(&mut header, 0, 4);
+    debug_assert_eq!(header.len(), BASIS_HEADER_SIZE);
+
+    // covers everything after itself, from the data size on
+    let header_crc = crc16(&header[8..]);
+    header[6..8].copy_from_slice(&header_crc.to_le_bytes());
+    header.extend_from_slice(&body);
+    header
+}
+
+fn put(out: &mut Vec<u8>, value: u32, bytes: usize) {
+    out.extend_from_slice(&value.to_le_bytes()[..bytes]);
+}
+
+// The CRC-16 of `basisu_transcoder.cpp`
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = !0u16;
+    for &byte in data {
+        let q = byte as u16 ^ (crc >> 8);
+        let k = (q >> 4) ^ q;
+        crc = (crc << 8) ^ k ^ (k << 5) ^ (k << 12);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETC1S: &[u8] = include_bytes!("../../testdata/quadrants_etc1s.ktx2");
+    const ETC1S_OPAQUE: &[u8] = include_bytes!("../../testdata/quadrants_etc1s_opaque.ktx2");
+    const UASTC: &[u8] = include_bytes!("../../testdata/quadrants_uastc.ktx2");
+
+    fn levels(reader: &ktx2::Reader<&[u8]>) -> Vec<Vec<u8>> {
+        reader
+            .levels()
+            .map(|level| match reader.header().supercompression_scheme {
+                Some(ktx2::SupercompressionScheme::Zstandard) => {
+                    let mut decoded = Vec::new();
+                    std::io::Read::read_to_end(
+                        &mut ruzstd::decoding::StreamingDecoder::new(level.data).unwrap(),
+                        &mut decoded,
+                    )
+                    .unwrap();
+                    decoded
+                }
+                _ => level.data.to_vec(),
+            })
+            .collect()
+    }
+
+    fn transcode_file(data: &[u8], features: wgpu::Features) -> anyhow::Result<Transcoded> {
+        let reader = ktx2::Reader::new(data).unwrap();
+        transcode(&reader, &levels(&reader), features)
+    }
+
+    // The largest difference of any channel from the source image of the fixtures
+    fn max_error(rgba: &[u8], alpha: bool) -> u8 {
+        let source = image::load_from_memory(include_bytes!("../../testdata/quadrants.png"))
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(rgba.len(), source.as_raw().len());
+        rgba.chunks_exact(4)
+            .zip(source.pixels())
+            .flat_map(|(transcoded, source)| {
+                let channels = if alpha { 4 } else { 3 };
+                (0..channels).map(move |c| transcoded[c].abs_diff(source[c]))
+            })
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn transcodes_to_rgba_without_compression_features() {
+        for (data, alpha) in [(ETC1S, true), (ETC1S_OPAQUE, false), (UASTC, true)] {
+            let transcoded = transcode_file(data, wgpu::Features::empty()).unwrap();
+            assert_eq!(transcoded.format, TextureFormat::Rgba8UnormSrgb);
+            assert_eq!(transcoded.levels.len(), 7);
+            for (level, data) in transcoded.levels.iter().enumerate() {
+                let width = (64 >> level).max(1);
+                let height = (32 >> level).max(1);
+                assert_eq!(data.len(), width * height * 4);
+            }
+            assert!(max_error(&transcoded.levels[0], alpha) < 32);
+            if !alpha {
+                assert!(transcoded.levels[0].chunks(4).all(|pixel| pixel[3] == 255));
+            }
+        }
+    }
+
+    #[test]
+    fn prefers_block_compression() {
+        let cases = [
+            (wgpu::Features::all(), TextureFormat::Bc7RgbaUnormSrgb, 16),
+            (
+                wgpu::Features::TEXTURE_COMPRESSION_ASTC | wgpu::Features::TEXTURE_COMPRESSION_ETC2,
+                TextureFormat::Astc {
+                    block: AstcBlock::B4x4,
+                    channel: AstcChannel::UnormSrgb,
+                },
+                16,
+            ),
+            (
+                wgpu::Features::TEXTURE_COMPRESSION_ETC2,
+                TextureFormat::Etc2Rgba8UnormSrgb,
+                16,
+            ),
+        ];
+        for data in [ETC1S, UASTC] {
+            for (features, format, block_size) in cases {
+                let transcoded = transcode_file(data, features).unwrap();
+                assert_eq!(transcoded.format, format);
+                for (level, data) in transcoded.levels.iter().enumerate() {
+                    let blocks_x = (64usize >> level).max(1).div_ceil(4);
+                    let blocks_y = (32usize >> level).max(1).div_ceil(4);
+                    assert_eq!(data.len(), blocks_x * blocks_y * block_size);
+                }
+            }
+        }
+
+        // opaque textures fit ETC1 blocks of 8 bytes
+        let transcoded =
+            transcode_file(ETC1S_OPAQUE, wgpu::Features::TEXTURE_COMPRESSION_ETC2).unwrap();
+        assert_eq!(transcoded.format, TextureFormat::Etc2Rgb8UnormSrgb);
+        assert_eq!(transcoded.levels[0].len(), 16 * 8 * 8);
+    }
+
+    #[test]
+    fn repacked_files_pass_the_checksums() {
+        for data in [ETC1S, ETC1S_OPAQUE] {
+            let reader = ktx2::Reader::new(data).unwrap();
+            let header = reader.header();
+            let basis = repack_etc1s(
+                reader.supercompression_global_data(),
+                &levels(&reader),
+                header.pixel_width,
+                header.pixel_height,
+                true,
+            )
+            .unwrap();
+            assert!(Transcoder::new().validate_file_checksums(&basis.data, true));
+        }
+
+        let reader = ktx2::Reader::new(UASTC).unwrap();
+        let basis = repack_uastc(&levels(&reader), 64, 32, true, true);
+        assert!(Transcoder::new().validate_file_checksums(&basis.data, true));
+    }
+
+    #[test]
+    fn rejects_truncated_global_data() {
+        let reader = ktx2::Reader::new(ETC1S).unwrap();
+        let global_data = reader.supercompression_global_data();
+        let truncated = &global_data[..global_data.len() - 1];
+        let error = repack_etc1s(truncated, &levels(&reader), 64, 32, true)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(TextureError::Transcode(_))
+        ));
+    }
+}
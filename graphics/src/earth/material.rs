@@ -0,0 +1,56 @@
+use crate::layout::uniform_layout;
+
+/// Metallic-roughness material factors and textures, as defined by glTF 2.0.
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub base_color_factor: [f32; 4],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub emissive_factor: [f32; 3],
+    pub base_color_texture: Option<image::DynamicImage>,
+    /// Roughness in the green channel and metalness in the blue one, scaling the factors
+    pub metallic_roughness_texture: Option<image::DynamicImage>,
+    pub emissive_texture: Option<image::DynamicImage>,
+}
+
+impl Default for Material {
+    // Non-metallic, with the roughness that gives the specular exponent
+    // the Earth has always been shaded with (18).
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.0; 4],
+            metallic_factor: 0.0,
+            roughness_factor: 0.5623,
+            emissive_factor: [0.0; 3],
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            emissive_texture: None,
+        }
+    }
+}
+
+impl Material {
+    pub fn build_uniform(&self) -> MaterialUniform {
+        MaterialUniform {
+            base_color_factor: self.base_color_factor,
+            emissive_factor: self.emissive_factor,
+            metallic: self.metallic_factor,
+            roughness: self.roughness_factor,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub base_color_factor: [f32; 4],
+    pub emissive_factor: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub _padding: [f32; 3],
+}
+
+uniform_layout!(MaterialUniform = "MaterialUniform" in "earth.wgsl" {
+    base_color_factor, emissive_factor, metallic, roughness, _padding,
+});
@@ -0,0 +1,461 @@
+use std::{collections::HashMap, fmt, io::BufReader, ops::Range};
+
+use base64::Engine;
+use cgmath::{InnerSpace, Matrix, SquareMatrix};
+
+use crate::asset::AssetSource;
+
+use super::{material::Material, vertex::ModelVertex};
+
+/// Geometry and materials of a model, before it is uploaded to the GPU.
+pub struct MeshData {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    /// One per material, together covering all of `indices`
+    pub parts: Vec<MeshPart>,
+}
+
+/// The triangles of a mesh drawn with one material.
+pub struct MeshPart {
+    pub indices: Range<u32>,
+    pub material: Material,
+}
+
+impl MeshData {
+    /// A mesh drawn entirely with `material`.
+    pub fn new(vertices: Vec<ModelVertex>, indices: Vec<u32>, material: Material) -> Self {
+        let part = MeshPart {
+            indices: 0..indices.len() as u32,
+            material,
+        };
+        Self {
+            vertices,
+            indices,
+            parts: vec![part],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ModelError {
+    UnsupportedFormat(String),
+    UnsupportedExtension(String),
+    UnsupportedPrimitive(gltf::mesh::Mode),
+    UnsupportedUri(String),
+    MissingAttribute(&'static str),
+    /// A texture reads this set of texture coordinates; only the first one is loaded
+    UnsupportedTexCoord(u32),
+    NoMesh,
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::UnsupportedFormat(path) => write!(f, "Unsupported model format: {}", path),
+            ModelError::UnsupportedExtension(name) => {
+                write!(f, "Unsupported glTF extension: {}", name)
+            }
+            ModelError::UnsupportedPrimitive(mode) => {
+                write!(f, "Unsupported primitive mode: {:?}", mode)
+            }
+            ModelError::UnsupportedUri(uri) => write!(f, "Unsupported URI: {}", uri),
+            ModelError::MissingAttribute(name) => write!(f, "Missing vertex attribute: {}", name),
+            ModelError::UnsupportedTexCoord(set) => {
+                write!(f, "Unsupported texture coordinate set: TEXCOORD_{}", set)
+            }
+            ModelError::NoMesh => write!(f, "No mesh found"),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+/// Loads an OBJ (with its MTL libraries) or a glTF/GLB model, depending on the extension of `path`.
+/// Every mesh and primitive is merged into one, with one part per glTF material.
+pub async fn load_mesh(source: &dyn AssetSource, path: &str) -> anyhow::Result<MeshData> {
+    let extension = path
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("obj") => load_obj(source, path).await,
+        Some("gltf") | Some("glb") => load_gltf(source, path).await,
+        _ => Err(ModelError::UnsupportedFormat(path.to_string()).into()),
+    }
+}
+
+// Resolves `uri` relative to the directory of `path`
+fn sibling(path: &str, uri: &str) -> String {
+    match path.rsplit_once('/') {
+        Some((dir, _)) => format!("{}/{}", dir, uri),
+        None => uri.to_string(),
+    }
+}
+
+async fn load_obj(source: &dyn AssetSource, path: &str) -> anyhow::Result<MeshData> {
+    let obj = source.fetch(path).await?;
+
+    let mtllibs = String::from_utf8_lossy(&obj)
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("mtllib "))
+        .map(|name| name.trim().to_string())
+        .collect::<Vec<_>>();
+    let mut materials = HashMap::new();
+    for name in mtllibs {
+        let mtl = source.fetch(&sibling(path, &name)).await?;
+        materials.insert(name, tobj::load_mtl_buf(&mut BufReader::new(&mtl as &[u8])));
+    }
+
+    let (raw_models, raw_materials) = tobj::load_obj_buf(
+        &mut BufReader::new(&obj as &[u8]),
+        &tobj::LoadOptions {
+            single_index: true,
+            triangulate: true,
+            ..Default::default()
+        },
+        |name| match materials.get(&name.to_string_lossy().to_string()) {
+            Some(result) => result.clone(),
+            None => Err(tobj::LoadError::OpenFileFailed),
+        },
+    )?;
+    if raw_models.is_empty() {
+        return Err(ModelError::NoMesh.into());
+    }
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for raw_model in &raw_models {
+        let raw_mesh = &raw_model.mesh;
+        if raw_mesh.texcoords.is_empty() {
+            return Err(ModelError::MissingAttribute("vt").into());
+        }
+        if raw_mesh.normals.is_empty() {
+            return Err(ModelError::MissingAttribute("vn").into());
+        }
+
+        let offset = vertices.len() as u32;
+        for i in 0..raw_mesh.positions.len() / 3 {
+            vertices.push(ModelVertex {
+                position: [
+                    raw_mesh.positions[i * 3],
+                    raw_mesh.positions[i * 3 + 1],
+                    raw_mesh.positions[i * 3 + 2],
+                ],
+                tex_coords: [
+                    raw_mesh.texcoords[i * 2],
+                    1.0 - raw_mesh.texcoords[i * 2 + 1],
+                ],
+                normal: [
+                    raw_mesh.normals[i * 3],
+                    raw_mesh.normals[i * 3 + 1],
+                    raw_mesh.normals[i * 3 + 2],
+                ],
+            });
+        }
+        indices.extend(raw_mesh.indices.iter().map(|index| index + offset));
+    }
+
+    let mut material = Material::default();
+    let diffuse_texture = raw_materials
+        .unwrap_or_default()
+        .into_iter()
+        .find_map(|raw_material| raw_material.diffuse_texture);
+    if let Some(texture) = diffuse_texture {
+        let bytes = source.fetch(&sibling(path, &texture)).await?;
+        material.base_color_texture = Some(image::load_from_memory(&bytes)?);
+    }
+
+    Ok(MeshData::new(vertices, indices, material))
+}
+
+async fn load_uri(source: &dyn AssetSource, path: &str, uri: &str) -> anyhow::Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data
+            .split_once(";base64,")
+            .ok_or_else(|| ModelError::UnsupportedUri(uri.to_string()))?;
+        Ok(base64::engine::general_purpose::STANDARD.decode(encoded)?)
+    } else if uri.contains("://") {
+        Err(ModelError::UnsupportedUri(uri.to_string()).into())
+    } else {
+        source.fetch(&sibling(path, uri)).await
+    }
+}
+
+async fn load_gltf(source: &dyn AssetSource, path: &str) -> anyhow::Result<MeshData> {
+    let bytes = source.fetch(path).await?;
+    let gltf::Gltf { document, mut blob } = gltf::Gltf::from_slice(&bytes)?;
+
+    if let Some(extension) = document.extensions_required().next() {
+        return Err(ModelError::UnsupportedExtension(extension.to_string()).into());
+    }
+
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => blob
+                .take()
+                .ok_or_else(|| anyhow::anyhow!("{} has no binary chunk", path))?,
+            gltf::buffer::Source::Uri(uri) => load_uri(source, path, uri).await?,
+        };
+        buffers.push(data);
+    }
+
+    // (mesh, world transform) of every node that has a mesh,
+    // or every mesh as-is if the document has no scene
+    let mut instances = Vec::new();
+    match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => {
+            let mut stack = scene
+                .nodes()
+                .map(|node| (node, cgmath::Matrix4::identity()))
+                .collect::<Vec<_>>();
+            while let Some((node, parent)) = stack.pop() {
+                let transform = parent * cgmath::Matrix4::from(node.transform().matrix());
+                if let Some(mesh) = node.mesh() {
+                    instances.push((mesh, transform));
+                }
+                stack.extend(node.children().map(|child| (child, transform)));
+            }
+        }
+        None => instances.extend(
+            document
+                .meshes()
+                .map(|mesh| (mesh, cgmath::Matrix4::identity())),
+        ),
+    }
+
+    let mut vertices = Vec::new();
+    // the indices of each material, in the order the materials first appear
+    let mut groups = Vec::<(gltf::Material, Vec<u32>)>::new();
+    for (mesh, transform) in instances {
+        let normal_matrix = {
+            let m = cgmath::Matrix3::from_cols(
+                transform.x.truncate(),
+                transform.y.truncate(),
+                transform.z.truncate(),
+            );
+            m.invert().unwrap_or(m).transpose()
+        };
+
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                return Err(ModelError::UnsupportedPrimitive(primitive.mode()).into());
+            }
+
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let positions = reader
+                .read_positions()
+                .ok_or(ModelError::MissingAttribute("POSITION"))?;
+            let normals = reader
+                .read_normals()
+                .ok_or(ModelError::MissingAttribute("NORMAL"))?;
+            let tex_coords = reader
+                .read_tex_coords(0)
+                .ok_or(ModelError::MissingAttribute("TEXCOORD_0"))?
+                .into_f32();
+
+            let offset = vertices.len() as u32;
+            for ((position, normal), tex_coords) in positions.zip(normals).zip(tex_coords) {
+                let position = transform * cgmath::Vector3::from(position).extend(1.0);
+                let normal = (normal_matrix * cgmath::Vector3::from(normal)).normalize();
+                vertices.push(ModelVertex {
+                    position: position.truncate().into(),
+                    tex_coords,
+                    normal: normal.into(),
+                });
+            }
+            let count = vertices.len() as u32 - offset;
+
+            let material = primitive.material();
+            let group = match groups
+                .iter()
+                .position(|(known, _)| known.index() == material.index())
+            {
+                Some(group) => group,
+                None => {
+                    groups.push((material, Vec::new()));
+                    groups.len() - 1
+                }
+            };
+            let indices = &mut groups[group].1;
+            match reader.read_indices() {
+                Some(read) => indices.extend(read.into_u32().map(|index| index + offset)),
+                None => indices.extend(offset..offset + count),
+            }
+        }
+    }
+    if vertices.is_empty() {
+        return Err(ModelError::NoMesh.into());
+    }
+
+    let mut indices = Vec::new();
+    let mut parts = Vec::new();
+    for (gltf_material, group) in groups {
+        let start = indices.len() as u32;
+        indices.extend(group);
+        parts.push(MeshPart {
+            indices: start..indices.len() as u32,
+            material: load_gltf_material(source, path, &buffers, &gltf_material).await?,
+        });
+    }
+
+    Ok(MeshData {
+        vertices,
+        indices,
+        parts,
+    })
+}
+
+async fn load_gltf_material(
+    source: &dyn AssetSource,
+    path: &str,
+    buffers: &[Vec<u8>],
+    gltf_material: &gltf::Material<'_>,
+) -> anyhow::Result<Material> {
+    let pbr = gltf_material.pbr_metallic_roughness();
+    let mut material = Material {
+        base_color_factor: pbr.base_color_factor(),
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        emissive_factor: gltf_material.emissive_factor(),
+        ..Material::default()
+    };
+    if let Some(info) = pbr.base_color_texture() {
+        material.base_color_texture = Some(load_gltf_image(source, path, buffers, &info).await?);
+    }
+    if let Some(info) = pbr.metallic_roughness_texture() {
+        material.metallic_roughness_texture =
+            Some(load_gltf_image(source, path, buffers, &info).await?);
+    }
+    if let Some(info) = gltf_material.emissive_texture() {
+        material.emissive_texture = Some(load_gltf_image(source, path, buffers, &info).await?);
+    }
+    Ok(material)
+}
+
+async fn load_gltf_image(
+    source: &dyn AssetSource,
+    path: &str,
+    buffers: &[Vec<u8>],
+    info: &gltf::texture::Info<'_>,
+) -> anyhow::Result<image::DynamicImage> {
+    if info.tex_coord() != 0 {
+        return Err(ModelError::UnsupportedTexCoord(info.tex_coord()).into());
+    }
+    let bytes = match info.texture().source().source() {
+        gltf::image::Source::View { view, .. } => {
+            let buffer = &buffers[view.buffer().index()];
+            buffer[view.offset()..view.offset() + view.length()].to_vec()
+        }
+        gltf::image::Source::Uri { uri, .. } => load_uri(source, path, uri).await?,
+    };
+    Ok(image::load_from_memory(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::{
+        asset::MemorySource,
+        earth::model::EARTH_MODEL_PATH,
+        testing::{earth_resources, gltf_with_materials},
+    };
+
+    #[test]
+    fn loads_the_committed_obj() {
+        let mesh = block_on(load_mesh(&earth_resources(), EARTH_MODEL_PATH)).unwrap();
+        assert!(!mesh.vertices.is_empty());
+        assert_eq!(mesh.indices.len() % 3, 0);
+        assert!(mesh
+            .indices
+            .iter()
+            .all(|&index| (index as usize) < mesh.vertices.len()));
+        assert!(mesh.vertices.iter().all(|vertex| {
+            let length = cgmath::Vector3::from(vertex.normal).magnitude();
+            (length - 1.0).abs() < 1e-2
+        }));
+    }
+
+    #[test]
+    fn fails_without_the_material_library() {
+        let source = MemorySource::new().with(
+            EARTH_MODEL_PATH,
+            &include_bytes!("../../resources/earth/earth.obj")[..],
+        );
+        let error = block_on(load_mesh(&source, EARTH_MODEL_PATH))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("earth.mtl"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_obj_without_texcoords() {
+        let obj = b"v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\n";
+        let source = MemorySource::new().with("plain.obj", &obj[..]);
+        let error = block_on(load_mesh(&source, "plain.obj")).err().unwrap();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(ModelError::MissingAttribute("vt"))
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_formats() {
+        let source = MemorySource::new().with("earth.fbx", &b""[..]);
+        let error = block_on(load_mesh(&source, "earth.fbx")).err().unwrap();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(ModelError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn merges_primitives_sharing_a_material() {
+        let source =
+            MemorySource::new().with("earth.gltf", gltf_with_materials(&[1, 1]).into_bytes());
+        let mesh = block_on(load_mesh(&source, "earth.gltf")).unwrap();
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.indices, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(mesh.parts.len(), 1);
+        assert_eq!(mesh.parts[0].indices, 0..6);
+        assert_eq!(
+            mesh.parts[0].material.base_color_factor,
+            [0.0, 0.0, 1.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn splits_primitives_by_material() {
+        let source =
+            MemorySource::new().with("earth.gltf", gltf_with_materials(&[1, 0, 1]).into_bytes());
+        let mesh = block_on(load_mesh(&source, "earth.gltf")).unwrap();
+        assert_eq!(mesh.vertices.len(), 9);
+        // the primitives of each material are drawn together
+        assert_eq!(mesh.indices, [0, 1, 2, 6, 7, 8, 3, 4, 5]);
+        let parts = mesh
+            .parts
+            .iter()
+            .map(|part| (part.indices.clone(), part.material.base_color_factor))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parts,
+            [(0..6, [0.0, 0.0, 1.0, 1.0]), (6..9, [1.0, 0.0, 0.0, 1.0])]
+        );
+    }
+
+    #[test]
+    fn reads_metallic_roughness_and_emissive_factors() {
+        let source = MemorySource::new().with("earth.gltf", gltf_with_materials(&[0]).into_bytes());
+        let mesh = block_on(load_mesh(&source, "earth.gltf")).unwrap();
+        let material = &mesh.parts[0].material;
+        assert_eq!(material.metallic_factor, 0.25);
+        assert_eq!(material.roughness_factor, 0.75);
+        assert_eq!(material.emissive_factor, [0.5, 0.5, 0.0]);
+        assert!(material.metallic_roughness_texture.is_none());
+        assert!(material.emissive_texture.is_none());
+    }
+}
@@ -1,6 +1,11 @@
+#[cfg(not(target_arch = "wasm32"))]
+mod basis;
+pub mod material;
+pub mod mesh;
 pub mod model;
 pub mod property;
-mod texture;
+pub mod sphere;
+pub mod texture;
 pub mod vertex;
 
 use model::EarthModel;
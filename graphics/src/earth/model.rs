@@ -1,170 +1,284 @@
 // code from: https://github.com/sotrh/learn-wgpu
 
-use std::io::BufReader;
+use std::{ops::Range, sync::Arc};
 
-use crate::fetch::Fetcher;
+use crate::{
+    asset::AssetSource,
+    resource::{GpuMesh, MemoryTracker, MeshKey, MeshRegistry},
+    scene::SceneOptions,
+};
 
-use super::{texture, vertex::ModelVertex};
+use super::{
+    mesh::load_mesh,
+    sphere::SphereKind,
+    texture::{TextureOptions, TextureSet},
+};
 use wgpu::{
     util::DeviceExt, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
     BindGroupLayoutEntry, BindingResource, BindingType, SamplerBindingType, ShaderStages,
 };
 
+/// The Earth model; either OBJ or glTF/GLB.
+pub const EARTH_MODEL_PATH: &str = "resources/earth/earth.obj";
+/// Used when the model does not come with a base color texture,
+/// and no compressed variant is supported.
+pub const EARTH_TEXTURE_PATH: &str = "resources/earth/earth_diff.png";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EarthGeometry {
+    /// OBJ or glTF/GLB model
+    Model(String),
+    /// Generated sphere, which needs an equirectangular texture
+    Sphere(SphereKind),
+}
+
+impl Default for EarthGeometry {
+    fn default() -> Self {
+        EarthGeometry::Model(EARTH_MODEL_PATH.to_string())
+    }
+}
+
 pub struct EarthModel {
-    pub mesh: EarthMesh,
-    pub texture_bind_group: wgpu::BindGroup,
+    pub mesh: Arc<GpuMesh>,
+    pub parts: Vec<EarthPart>,
     pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    _textures: Vec<TextureSet>,
 }
 
-pub struct AtmosphereModel {
-    pub mesh: EarthMesh,
+/// The indices of the Earth mesh drawn with one material, and its textures and factors.
+pub struct EarthPart {
+    pub indices: Range<u32>,
+    pub bind_group: wgpu::BindGroup,
 }
 
-pub struct EarthMesh {
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
-    pub num_elements: u32,
+pub struct AtmosphereModel {
+    pub mesh: Arc<GpuMesh>,
 }
 
-pub async fn create_earth_and_atmosphere_model<'a>(
+pub async fn create_earth_and_atmosphere_model(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
-    fetcher: &Fetcher<'a>,
+    meshes: &mut MeshRegistry,
+    source: &dyn AssetSource,
+    options: &SceneOptions,
+    radius: f32,
 ) -> anyhow::Result<(EarthModel, AtmosphereModel)> {
-    let (earth_obj, earth_mtl, earth_texture_diffuse) = futures::join!(
-        fetcher.fetch_as_bytes("resources/earth/earth.obj"),
-        fetcher.fetch_as_bytes("resources/earth/earth.mtl"),
-        fetcher.fetch_as_bytes("resources/earth/earth_diff.png"),
-    );
-
-    let (earth_obj, earth_mtl, earth_texture_diffuse) = futures::join!(
-        earth_obj?.bytes(),
-        earth_mtl?.bytes(),
-        earth_texture_diffuse?.bytes(),
-    );
-
-    let (earth_obj, earth_mtl, earth_texture_diffuse) = (
-        &earth_obj? as &[u8],
-        &earth_mtl? as &[u8],
-        &earth_texture_diffuse? as &[u8],
-    );
-
-    let mut bufreader = BufReader::new(earth_obj);
+    let mesh_data = match &options.earth_geometry {
+        EarthGeometry::Model(path) => load_mesh(source, path).await?,
+        EarthGeometry::Sphere(kind) => kind.generate(radius),
+    };
 
     let texture_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
         label: Some("texture_bind_group_layout"),
         entries: &[
+            texture_layout_entry(0),
             BindGroupLayoutEntry {
-                binding: 0,
+                binding: 1,
                 visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Texture {
-                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    multisampled: false,
-                },
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
                 count: None,
             },
             BindGroupLayoutEntry {
-                binding: 1,
+                binding: 2,
                 visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
                 count: None,
             },
+            texture_layout_entry(3),
+            texture_layout_entry(4),
         ],
     });
 
-    let raw_mtl = tobj::load_mtl_buf(&mut BufReader::new(earth_mtl))?;
+    let key = match &options.earth_geometry {
+        EarthGeometry::Model(path) => MeshKey::Named(path.clone()),
+        EarthGeometry::Sphere(_) => MeshKey::from_content(&mesh_data),
+    };
+    let mesh = meshes.insert(device, key, "earth", &mesh_data);
 
-    let (raw_models, _) = tobj::load_obj_buf(
-        &mut bufreader,
-        &tobj::LoadOptions {
-            single_index: true,
-            triangulate: true,
-            ..Default::default()
-        },
-        |_| tobj::MTLLoadResult::Ok(raw_mtl.clone()),
-    )?;
-
-    let raw_model = &raw_models.get(0).expect("No model loaded");
-    let raw_mesh = &raw_model.mesh;
-
-    let mut vertices = Vec::new();
-    for i in 0..raw_mesh.positions.len() / 3 {
-        vertices.push(ModelVertex {
-            position: [
-                raw_mesh.positions[i * 3],
-                raw_mesh.positions[i * 3 + 1],
-                raw_mesh.positions[i * 3 + 2],
-            ],
-            tex_coords: [
-                raw_mesh.texcoords[i * 2],
-                1.0 - raw_mesh.texcoords[i * 2 + 1],
-            ],
-            normal: [
-                raw_mesh.normals[i * 3],
-                raw_mesh.normals[i * 3 + 1],
-                raw_mesh.normals[i * 3 + 2],
-            ],
-        });
-    }
-    let (earth_mesh, atmosphere_mesh) = {
-        let mut meshes_iter = (0..2).map(|i| {
-            let label = match i {
-                0 => "earth",
-                1 => "atmosphere",
-                _ => unreachable!(),
+    // stands in for the textures a material does not have, as they scale its factors
+    let white =
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4])));
+    let white_options = TextureOptions {
+        mipmaps: false,
+        ..options.earth_texture_options
+    };
+    let mut textures = vec![TextureSet::from_linear_image(
+        device,
+        queue,
+        meshes.tracker(),
+        &white,
+        Some("white_texture"),
+        &white_options,
+    )?];
+    let base_color_fallback = if mesh_data
+        .parts
+        .iter()
+        .all(|part| part.material.base_color_texture.is_none())
+    {
+        textures.push(load_earth_texture(device, queue, meshes.tracker(), source, options).await?);
+        textures.len() - 1
+    } else {
+        0
+    };
+
+    let mut parts = Vec::new();
+    for part in &mesh_data.parts {
+        let material = &part.material;
+        // base color, metallic-roughness and emissive, as indices into `textures`
+        let mut indices = [0; 3];
+        let slots = [
+            (&material.base_color_texture, false, base_color_fallback),
+            (&material.metallic_roughness_texture, true, 0),
+            (&material.emissive_texture, false, 0),
+        ];
+        for (index, (image, linear, fallback)) in indices.iter_mut().zip(slots) {
+            *index = match image {
+                Some(image) => {
+                    let upload = if linear {
+                        TextureSet::from_linear_image
+                    } else {
+                        TextureSet::from_image
+                    };
+                    textures.push(upload(
+                        device,
+                        queue,
+                        meshes.tracker(),
+                        image,
+                        Some("texture"),
+                        &options.earth_texture_options,
+                    )?);
+                    textures.len() - 1
+                }
+                None => fallback,
             };
+        }
+        let [base_color, metallic_roughness, emissive] = indices.map(|index| &textures[index]);
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&(label.to_string() + " vertex buffer")),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&(label.to_string() + " index buffer")),
-                contents: bytemuck::cast_slice(&raw_mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            EarthMesh {
-                vertex_buffer,
-                index_buffer,
-                num_elements: raw_mesh.indices.len() as u32,
-            }
+        let material_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("material_buffer"),
+            contents: bytemuck::cast_slice(&[material.build_uniform()]),
+            usage: wgpu::BufferUsages::UNIFORM,
         });
-        (meshes_iter.next().unwrap(), meshes_iter.next().unwrap())
-    };
 
-    let texture = texture::TextureSet::from_bytes(device, queue, earth_texture_diffuse, "texture")?;
-
-    let texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
-        label: Some("material_bind_group"),
-        layout: &texture_bind_group_layout,
-        entries: &[
-            BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::TextureView(&texture.view()),
-            },
-            BindGroupEntry {
-                binding: 1,
-                resource: BindingResource::Sampler(&texture.sampler()),
-            },
-        ],
-    });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("material_bind_group"),
+            layout: &texture_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(base_color.view()),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(base_color.sampler()),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: material_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(metallic_roughness.view()),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(emissive.view()),
+                },
+            ],
+        });
+        parts.push(EarthPart {
+            indices: part.indices.clone(),
+            bind_group,
+        });
+    }
 
     Ok((
         EarthModel {
-            mesh: earth_mesh,
-            texture_bind_group,
+            mesh: mesh.clone(),
+            parts,
             texture_bind_group_layout,
+            _textures: textures,
         },
-        AtmosphereModel {
-            mesh: atmosphere_mesh,
-        },
+        AtmosphereModel { mesh },
     ))
 }
 
+fn texture_layout_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+// Uses the first compressed variant the device supports and loads,
+// falling back to the uncompressed texture
+async fn load_earth_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    tracker: &MemoryTracker,
+    source: &dyn AssetSource,
+    options: &SceneOptions,
+) -> anyhow::Result<TextureSet> {
+    let texture_options = &options.earth_texture_options;
+    let variants = options
+        .earth_texture_variants
+        .iter()
+        .filter(|(compression, _)| device.features().contains(compression.feature()));
+    for (compression, path) in variants {
+        let result = match source.fetch(path).await {
+            Ok(data) => TextureSet::from_ktx2(
+                device,
+                queue,
+                tracker,
+                &data,
+                Some("texture"),
+                texture_options,
+            ),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(texture) => return Ok(texture),
+            Err(e) => log::warn!("Failed to load {:?} texture {}: {:?}", compression, path, e),
+        }
+    }
+
+    let data = source.fetch(&options.earth_texture).await?;
+    if options
+        .earth_texture
+        .to_ascii_lowercase()
+        .ends_with(".ktx2")
+    {
+        TextureSet::from_ktx2(
+            device,
+            queue,
+            tracker,
+            &data,
+            Some("texture"),
+            texture_options,
+        )
+    } else {
+        let image = image::load_from_memory(&data)?;
+        TextureSet::from_image(
+            device,
+            queue,
+            tracker,
+            &image,
+            Some("texture"),
+            texture_options,
+        )
+    }
+}
+
 pub trait DrawModel<'a> {
     fn draw_earth_model(
         &mut self,
@@ -194,10 +308,12 @@ impl<'a> DrawModel<'a> for wgpu::RenderPass<'a> {
         self.set_vertex_buffer(0, model.mesh.vertex_buffer.slice(..));
         self.set_index_buffer(model.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         self.set_bind_group(0, camera_bind_group, &[]);
-        self.set_bind_group(1, &model.texture_bind_group, &[]);
-        self.set_bind_group(2, &sun_bind_group, &[]);
+        self.set_bind_group(2, sun_bind_group, &[]);
         self.set_bind_group(3, earth_property_bind_group, &[]);
-        self.draw_indexed(0..model.mesh.num_elements, 0, 0..1);
+        for part in &model.parts {
+            self.set_bind_group(1, &part.bind_group, &[]);
+            self.draw_indexed(part.indices.clone(), 0, 0..1);
+        }
     }
 
     fn draw_atmosphere_model(
@@ -215,3 +331,92 @@ impl<'a> DrawModel<'a> for wgpu::RenderPass<'a> {
         self.draw_indexed(0..model.mesh.num_elements, 0, 0..1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::{
+        resource::MemoryTracker,
+        testing::{device, earth_resources, gltf_with_materials},
+    };
+
+    #[test]
+    fn loads_the_committed_model() {
+        let Some((device, queue)) = device() else {
+            return;
+        };
+        let mut meshes = MeshRegistry::new(MemoryTracker::new());
+        let (earth, atmosphere) = block_on(create_earth_and_atmosphere_model(
+            &device,
+            &queue,
+            &mut meshes,
+            &earth_resources(),
+            &SceneOptions::default(),
+            500.0,
+        ))
+        .unwrap();
+
+        // the atmosphere shares the mesh of the Earth
+        assert!(Arc::ptr_eq(&earth.mesh, &atmosphere.mesh));
+        assert!(earth.mesh.num_elements > 0);
+        let report = meshes.tracker().report();
+        assert!(report.total_bytes() > 0);
+    }
+
+    #[test]
+    fn fails_on_a_corrupt_texture() {
+        let Some((device, queue)) = device() else {
+            return;
+        };
+        let source = earth_resources().with(EARTH_TEXTURE_PATH, &b"not a png"[..]);
+        let mut meshes = MeshRegistry::new(MemoryTracker::new());
+        let result = block_on(create_earth_and_atmosphere_model(
+            &device,
+            &queue,
+            &mut meshes,
+            &source,
+            &SceneOptions::default(),
+            500.0,
+        ));
+        let error = result.err().unwrap();
+        assert!(
+            error.downcast_ref::<image::ImageError>().is_some(),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn draws_each_material_separately() {
+        let Some((device, queue)) = device() else {
+            return;
+        };
+        let source =
+            earth_resources().with("earth.gltf", gltf_with_materials(&[0, 1, 0]).into_bytes());
+        let options = SceneOptions {
+            earth_geometry: EarthGeometry::Model("earth.gltf".to_string()),
+            ..SceneOptions::default()
+        };
+        let mut meshes = MeshRegistry::new(MemoryTracker::new());
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let (earth, _) = block_on(create_earth_and_atmosphere_model(
+            &device,
+            &queue,
+            &mut meshes,
+            &source,
+            &options,
+            500.0,
+        ))
+        .unwrap();
+        assert!(block_on(device.pop_error_scope()).is_none());
+
+        let parts = earth
+            .parts
+            .iter()
+            .map(|part| part.indices.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(parts, [0..6, 6..9]);
+    }
+}
@@ -1,5 +1,7 @@
 use cgmath::InnerSpace;
 
+use crate::layout::uniform_layout;
+
 #[derive(Debug, Clone)]
 pub struct EarthProperty {
     pub radius: f32,
@@ -51,3 +53,7 @@ pub struct EarthUniform {
     pub axis: [f32; 3],
     pub _padding1: f32,
 }
+
+uniform_layout!(EarthUniform = "EarthUniform" in "include/earth.wgsl" {
+    radius, atmosphere_radius, rotation, _padding0, axis, _padding1,
+});
@@ -0,0 +1,479 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use cgmath::InnerSpace;
+
+use super::{material::Material, mesh::MeshData, vertex::ModelVertex};
+
+type Vector = cgmath::Vector3<f32>;
+
+/// Tessellations of a sphere with equirectangular texture coordinates:
+/// `u` grows eastward from the antimeridian (`u = 0.5` at `+x`), `v` grows southward from the north pole (`+y`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SphereKind {
+    /// Latitude/longitude grid; `segments` around the equator and `rings` from pole to pole.
+    Uv { segments: u32, rings: u32 },
+    /// Icosahedron whose triangles are split in four `subdivisions` times.
+    Icosphere { subdivisions: u32 },
+    /// Cube whose faces are split into `subdivisions` x `subdivisions` quads.
+    CubeSphere { subdivisions: u32 },
+}
+
+impl SphereKind {
+    pub fn generate(self, radius: f32) -> MeshData {
+        let (vertices, indices) = match self {
+            SphereKind::Uv { segments, rings } => uv_sphere(segments.max(3), rings.max(2)),
+            SphereKind::Icosphere { subdivisions } => {
+                let (positions, triangles) = icosphere(subdivisions);
+                equirectangular(&positions, &triangles)
+            }
+            SphereKind::CubeSphere { subdivisions } => {
+                let (positions, triangles) = cube_sphere(subdivisions.max(1));
+                equirectangular(&positions, &triangles)
+            }
+        };
+
+        let vertices = vertices
+            .into_iter()
+            .map(|vertex| ModelVertex {
+                position: (Vector::from(vertex.normal) * radius).into(),
+                ..vertex
+            })
+            .collect();
+        MeshData::new(vertices, indices, Material::default())
+    }
+}
+
+fn tex_coords(p: Vector) -> [f32; 2] {
+    let longitude = (-p.z).atan2(p.x);
+    let latitude = p.y.clamp(-1.0, 1.0).asin();
+    [0.5 + longitude / (2.0 * PI), 0.5 - latitude / PI]
+}
+
+fn unit_vertex(p: Vector, tex_coords: [f32; 2]) -> ModelVertex {
+    ModelVertex {
+        position: p.into(),
+        tex_coords,
+        normal: p.into(),
+    }
+}
+
+fn uv_sphere(segments: u32, rings: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let columns = segments + 1;
+    let mut vertices = Vec::with_capacity((columns * (rings + 1)) as usize);
+    for ring in 0..=rings {
+        let v = ring as f32 / rings as f32;
+        let latitude = PI * (0.5 - v);
+        for segment in 0..columns {
+            // pole vertices sit in the middle of their segment; the last one is unused
+            let u = if ring == 0 || ring == rings {
+                ((segment as f32 + 0.5) / segments as f32).min(1.0)
+            } else {
+                segment as f32 / segments as f32
+            };
+            let longitude = 2.0 * PI * (u - 0.5);
+            let p = Vector::new(
+                latitude.cos() * longitude.cos(),
+                latitude.sin(),
+                -latitude.cos() * longitude.sin(),
+            );
+            vertices.push(unit_vertex(p, [u, v]));
+        }
+    }
+
+    let mut indices = Vec::new();
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let top_left = ring * columns + segment;
+            let bottom_left = top_left + columns;
+            if ring == 0 {
+                indices.extend([top_left, bottom_left, bottom_left + 1]);
+                continue;
+            }
+            indices.extend([top_left, bottom_left, top_left + 1]);
+            if ring != rings - 1 {
+                indices.extend([top_left + 1, bottom_left, bottom_left + 1]);
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+fn icosphere(subdivisions: u32) -> (Vec<Vector>, Vec<[u32; 3]>) {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut positions = [
+        (-1.0, t, 0.0),
+        (1.0, t, 0.0),
+        (-1.0, -t, 0.0),
+        (1.0, -t, 0.0),
+        (0.0, -1.0, t),
+        (0.0, 1.0, t),
+        (0.0, -1.0, -t),
+        (0.0, 1.0, -t),
+        (t, 0.0, -1.0),
+        (t, 0.0, 1.0),
+        (-t, 0.0, -1.0),
+        (-t, 0.0, 1.0),
+    ]
+    .into_iter()
+    .map(|p| Vector::from(p).normalize())
+    .collect::<Vec<_>>();
+    #[rustfmt::skip]
+    let mut triangles = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let p = (positions[a as usize] + positions[b as usize]).normalize();
+                positions.push(p);
+                positions.len() as u32 - 1
+            })
+        };
+        triangles = triangles
+            .into_iter()
+            .flat_map(|[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+    (positions, triangles)
+}
+
+fn cube_sphere(subdivisions: u32) -> (Vec<Vector>, Vec<[u32; 3]>) {
+    // (normal, u axis, v axis) of each face, with u x v = normal
+    let faces = [
+        (Vector::unit_x(), -Vector::unit_z(), Vector::unit_y()),
+        (-Vector::unit_x(), Vector::unit_z(), Vector::unit_y()),
+        (Vector::unit_y(), Vector::unit_x(), -Vector::unit_z()),
+        (-Vector::unit_y(), Vector::unit_x(), Vector::unit_z()),
+        (Vector::unit_z(), Vector::unit_x(), Vector::unit_y()),
+        (-Vector::unit_z(), -Vector::unit_x(), Vector::unit_y()),
+    ];
+
+    let n = subdivisions;
+    let mut positions = Vec::new();
+    let mut triangles = Vec::new();
+    for (normal, u_axis, v_axis) in faces {
+        let base = positions.len() as u32;
+        for j in 0..=n {
+            for i in 0..=n {
+                let a = 2.0 * i as f32 / n as f32 - 1.0;
+                let b = 2.0 * j as f32 / n as f32 - 1.0;
+                let p = normal + u_axis * a + v_axis * b;
+                // spherified cube: spreads the vertices more evenly than normalizing
+                let (x2, y2, z2) = (p.x * p.x, p.y * p.y, p.z * p.z);
+                positions.push(Vector::new(
+                    p.x * (1.0 - y2 / 2.0 - z2 / 2.0 + y2 * z2 / 3.0).sqrt(),
+                    p.y * (1.0 - z2 / 2.0 - x2 / 2.0 + z2 * x2 / 3.0).sqrt(),
+                    p.z * (1.0 - x2 / 2.0 - y2 / 2.0 + x2 * y2 / 3.0).sqrt(),
+                ));
+            }
+        }
+        for j in 0..n {
+            for i in 0..n {
+                let k = base + j * (n + 1) + i;
+                triangles.push([k, k + 1, k + n + 2]);
+                triangles.push([k, k + n + 2, k + n + 1]);
+            }
+        }
+    }
+    (positions, triangles)
+}
+
+// Assigns equirectangular texture coordinates to a unit sphere mesh, with `u` in 0..=1 so
+// that any address mode works. Triangles crossing the antimeridian are split along it, into
+// a part whose seam vertices have `u = 1` and one where they have `u = 0`. Vertices on a pole
+// are copied for every triangle with the average `u` of the other corners.
+fn equirectangular(positions: &[Vector], triangles: &[[u32; 3]]) -> (Vec<ModelVertex>, Vec<u32>) {
+    const POLE_EPSILON: f32 = 1e-6;
+
+    let mut vertices = positions
+        .iter()
+        .map(|&p| unit_vertex(p, tex_coords(p)))
+        .collect::<Vec<_>>();
+    let is_pole = |p: Vector| p.x.abs() < POLE_EPSILON && p.z.abs() < POLE_EPSILON;
+
+    let mut seam = Seam::default();
+    let mut indices = Vec::with_capacity(triangles.len() * 3);
+    for &[a, b, c] in triangles {
+        let mut corners = [a, b, c];
+        let (pa, pb, pc) = (
+            positions[a as usize],
+            positions[b as usize],
+            positions[c as usize],
+        );
+        if (pb - pa).cross(pc - pa).dot(pa + pb + pc) < 0.0 {
+            corners.swap(1, 2);
+        }
+
+        let poles = corners.map(|i| is_pole(positions[i as usize]));
+        let mut us = corners.map(|i| vertices[i as usize].tex_coords[0]);
+        let (min, max) = (0..3)
+            .filter(|&k| !poles[k])
+            .fold((f32::MAX, f32::MIN), |(min, max), k| {
+                (min.min(us[k]), max.max(us[k]))
+            });
+        if max - min <= 0.5 {
+            let corners = (0..3).map(|k| (Corner::Vertex(corners[k]), us[k]));
+            seam.emit(&mut vertices, &mut indices, positions, &poles, corners);
+            continue;
+        }
+
+        // Unwraps the western corners past `u = 1`, where the poles sit too since the
+        // seam meets them, then keeps the parts east and west of it
+        for k in 0..3 {
+            if poles[k] {
+                us[k] = 1.0;
+            } else if us[k] < 0.5 {
+                us[k] += 1.0;
+            }
+        }
+        for east in [true, false] {
+            let side = |k: usize| if east { 1.0 - us[k] } else { us[k] - 1.0 };
+            if (0..3).all(|k| side(k) <= 0.0) {
+                continue;
+            }
+            let mut polygon = Vec::with_capacity(4);
+            for k in 0..3 {
+                let next = (k + 1) % 3;
+                let u = if east { us[k] } else { us[k] - 1.0 };
+                if side(k) >= 0.0 {
+                    polygon.push((Corner::Vertex(corners[k]), u));
+                }
+                if side(k) * side(next) < 0.0 {
+                    let edge = (corners[k], corners[next]);
+                    polygon.push((Corner::Seam(edge), if east { 1.0 } else { 0.0 }));
+                }
+            }
+            // a quad when one corner was cut off, split into a fan
+            for fan in 1..polygon.len() - 1 {
+                let triangle = [polygon[0], polygon[fan], polygon[fan + 1]];
+                let poles = triangle.map(|(corner, _)| {
+                    matches!(corner, Corner::Vertex(i) if is_pole(positions[i as usize]))
+                });
+                let corners = triangle.into_iter();
+                seam.emit(&mut vertices, &mut indices, positions, &poles, corners);
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+#[derive(Clone, Copy)]
+enum Corner {
+    Vertex(u32),
+    // where the edge between two vertices crosses the antimeridian
+    Seam((u32, u32)),
+}
+
+// Copies of vertices with another `u`, and the points where edges cross the antimeridian
+#[derive(Default)]
+struct Seam {
+    copies: HashMap<(u32, u32), u32>,
+    crossings: HashMap<((u32, u32), u32), u32>,
+}
+
+impl Seam {
+    fn emit(
+        &mut self,
+        vertices: &mut Vec<ModelVertex>,
+        indices: &mut Vec<u32>,
+        positions: &[Vector],
+        poles: &[bool; 3],
+        corners: impl Iterator<Item = (Corner, f32)>,
+    ) {
+        let corners = corners.collect::<Vec<_>>();
+        let pole_u = {
+            let others = (0..3).filter(|&k| !poles[k]).collect::<Vec<_>>();
+            others.iter().map(|&k| corners[k].1).sum::<f32>() / others.len().max(1) as f32
+        };
+
+        for (k, &(corner, u)) in corners.iter().enumerate() {
+            let u = if poles[k] { pole_u } else { u };
+            let index = match corner {
+                Corner::Vertex(index) if vertices[index as usize].tex_coords[0] == u => index,
+                Corner::Vertex(index) => {
+                    *self.copies.entry((index, u.to_bits())).or_insert_with(|| {
+                        let mut vertex = vertices[index as usize];
+                        vertex.tex_coords[0] = u;
+                        vertices.push(vertex);
+                        vertices.len() as u32 - 1
+                    })
+                }
+                Corner::Seam((a, b)) => {
+                    // the same point for both triangles sharing the edge
+                    let (a, b) = (a.min(b), a.max(b));
+                    let key = ((a, b), u.to_bits());
+                    *self.crossings.entry(key).or_insert_with(|| {
+                        let (pa, pb) = (positions[a as usize], positions[b as usize]);
+                        let t = pa.z / (pa.z - pb.z);
+                        let p = (pa + (pb - pa) * t).normalize();
+                        vertices.push(unit_vertex(p, [u, tex_coords(p)[1]]));
+                        vertices.len() as u32 - 1
+                    })
+                }
+            };
+            indices.push(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SphereKind; 5] = [
+        SphereKind::Uv {
+            segments: 32,
+            rings: 16,
+        },
+        SphereKind::Uv {
+            segments: 3,
+            rings: 2,
+        },
+        SphereKind::Icosphere { subdivisions: 0 },
+        SphereKind::Icosphere { subdivisions: 3 },
+        SphereKind::CubeSphere { subdivisions: 8 },
+    ];
+
+    fn triangles(mesh: &MeshData) -> impl Iterator<Item = [ModelVertex; 3]> + '_ {
+        mesh.indices
+            .chunks_exact(3)
+            .map(|triangle| [0, 1, 2].map(|k| mesh.vertices[triangle[k] as usize]))
+    }
+
+    #[test]
+    fn vertex_counts() {
+        let mesh = SphereKind::Uv {
+            segments: 32,
+            rings: 16,
+        }
+        .generate(1.0);
+        assert_eq!(mesh.vertices.len(), 33 * 17);
+        // one triangle per segment at each pole, two elsewhere
+        assert_eq!(mesh.indices.len(), 3 * 32 * (2 + 2 * 14));
+
+        for subdivisions in 0..4 {
+            let (positions, triangles) = icosphere(subdivisions);
+            assert_eq!(positions.len(), 10 * 4usize.pow(subdivisions) + 2);
+            assert_eq!(triangles.len(), 20 * 4usize.pow(subdivisions));
+        }
+        for subdivisions in 1..4 {
+            let (positions, triangles) = cube_sphere(subdivisions);
+            let n = subdivisions as usize;
+            assert_eq!(positions.len(), 6 * (n + 1) * (n + 1));
+            assert_eq!(triangles.len(), 12 * n * n);
+        }
+
+        for kind in KINDS {
+            let mesh = kind.generate(1.0);
+            assert_eq!(mesh.indices.len() % 3, 0, "{:?}", kind);
+            assert!(
+                mesh.indices
+                    .iter()
+                    .all(|&index| (index as usize) < mesh.vertices.len()),
+                "{:?}",
+                kind
+            );
+        }
+    }
+
+    #[test]
+    fn vertices_lie_on_the_sphere() {
+        for kind in KINDS {
+            for radius in [1.0, 500.0] {
+                let mesh = kind.generate(radius);
+                for vertex in &mesh.vertices {
+                    let position = Vector::from(vertex.position);
+                    let normal = Vector::from(vertex.normal);
+                    assert!((position.magnitude() - radius).abs() < radius * 1e-5);
+                    assert!((normal.magnitude() - 1.0).abs() < 1e-5);
+                    assert!((position / radius - normal).magnitude() < 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn texture_coordinates_stay_in_range() {
+        for kind in KINDS {
+            let mesh = kind.generate(1.0);
+            for vertex in &mesh.vertices {
+                let [u, v] = vertex.tex_coords;
+                assert!((0.0..=1.0).contains(&u), "{:?}: u = {}", kind, u);
+                assert!((0.0..=1.0).contains(&v), "{:?}: v = {}", kind, v);
+                // v follows the latitude everywhere, also on the split edges
+                let expected = 0.5 - vertex.normal[1].clamp(-1.0, 1.0).asin() / PI;
+                assert!((v - expected).abs() < 1e-5);
+            }
+            // no triangle stretches across the texture instead of meeting the seam
+            for triangle in triangles(&mesh) {
+                let us = triangle.map(|vertex| vertex.tex_coords[0]);
+                let span = us.iter().cloned().fold(f32::MIN, f32::max)
+                    - us.iter().cloned().fold(f32::MAX, f32::min);
+                assert!(span <= 0.5, "{:?}: {:?}", kind, us);
+            }
+        }
+    }
+
+    #[test]
+    fn seam_vertices_are_duplicated_at_both_edges() {
+        for kind in KINDS {
+            let mesh = kind.generate(1.0);
+            let at = |u: f32| {
+                mesh.vertices
+                    .iter()
+                    .filter(|vertex| vertex.tex_coords[0] == u && vertex.normal[1].abs() < 0.99)
+                    .map(|vertex| (vertex.normal[1] * 1e4).round() as i32)
+                    .collect::<std::collections::BTreeSet<_>>()
+            };
+            let (west, east) = (at(0.0), at(1.0));
+            assert!(!east.is_empty(), "{:?}", kind);
+            assert_eq!(west, east, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn triangles_face_outward() {
+        for kind in KINDS {
+            let mesh = kind.generate(1.0);
+            for triangle in triangles(&mesh) {
+                let [a, b, c] = triangle.map(|vertex| Vector::from(vertex.position));
+                let normal = (b - a).cross(c - a);
+                assert!(normal.dot(a + b + c) >= 0.0, "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn covers_the_texture_once() {
+        // the triangles tile the texture without overlapping, except for the
+        // corners cut off at the poles, so their areas add up to less than 1
+        for kind in KINDS {
+            let mesh = kind.generate(1.0);
+            let area = triangles(&mesh)
+                .map(|triangle| {
+                    let [a, b, c] = triangle.map(|vertex| cgmath::Vector2::from(vertex.tex_coords));
+                    // v points down the texture, so outward triangles are clockwise in it
+                    -((b - a).perp_dot(c - a)) / 2.0
+                })
+                .sum::<f32>();
+            assert!(
+                area > 0.5 - 1e-4 && area <= 1.0 + 1e-4,
+                "{:?}: {}",
+                kind,
+                area
+            );
+            if let SphereKind::Uv { rings, .. } = kind {
+                // half of each polar ring
+                assert!((area - (1.0 - 1.0 / rings as f32)).abs() < 1e-4);
+            }
+        }
+    }
+}
@@ -1,42 +1,177 @@
 // code from: https://github.com/sotrh/learn-wgpu
 
-use image::GenericImageView;
+use std::{fmt, io::Read};
+
+use image::{imageops::FilterType, GenericImageView, RgbaImage};
+use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+
+use crate::resource::{MemoryTracker, ResourceKind, TrackedAllocation};
+
+/// Families of block-compressed formats, each behind its own `wgpu::Features` flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureCompression {
+    /// BC1-BC7, mostly on desktop
+    Bc,
+    /// ETC2 and EAC, mostly on mobile and WebGL
+    Etc2,
+    /// ASTC LDR, on recent mobile GPUs
+    Astc,
+}
+
+impl TextureCompression {
+    pub const ALL: [TextureCompression; 3] = [Self::Bc, Self::Etc2, Self::Astc];
+
+    pub fn feature(self) -> wgpu::Features {
+        match self {
+            Self::Bc => wgpu::Features::TEXTURE_COMPRESSION_BC,
+            Self::Etc2 => wgpu::Features::TEXTURE_COMPRESSION_ETC2,
+            Self::Astc => wgpu::Features::TEXTURE_COMPRESSION_ASTC,
+        }
+    }
+}
+
+/// The compression features of `adapter_features`, to be requested when creating the device.
+pub fn compression_features(adapter_features: wgpu::Features) -> wgpu::Features {
+    TextureCompression::ALL
+        .into_iter()
+        .map(TextureCompression::feature)
+        .filter(|&feature| adapter_features.contains(feature))
+        .fold(wgpu::Features::empty(), |features, feature| {
+            features | feature
+        })
+}
+
+#[derive(Debug)]
+pub enum TextureError {
+    UnsupportedFormat(Option<ktx2::Format>),
+    UnsupportedLayout,
+    UnsupportedSupercompression(ktx2::SupercompressionScheme),
+    /// Basis Universal (ETC1S/UASTC) textures can only be transcoded natively
+    #[cfg(target_arch = "wasm32")]
+    BasisUniversal,
+    MissingFeature(wgpu::Features),
+    Decompression(String),
+    /// The Basis Universal transcoder rejected the texture
+    #[cfg(not(target_arch = "wasm32"))]
+    Transcode(String),
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::UnsupportedFormat(format) => {
+                write!(f, "Unsupported KTX2 format: {:?}", format)
+            }
+            TextureError::UnsupportedLayout => {
+                write!(
+                    f,
+                    "Only 2D KTX2 textures without layers or faces are supported"
+                )
+            }
+            TextureError::UnsupportedSupercompression(scheme) => {
+                write!(f, "Unsupported KTX2 supercompression: {:?}", scheme)
+            }
+            #[cfg(target_arch = "wasm32")]
+            TextureError::BasisUniversal => {
+                write!(
+                    f,
+                    "Basis Universal textures can not be transcoded on the web; transcode them offline"
+                )
+            }
+            TextureError::MissingFeature(feature) => {
+                write!(f, "The device does not support {:?}", feature)
+            }
+            TextureError::Decompression(e) => write!(f, "Failed to decompress KTX2 level: {}", e),
+            #[cfg(not(target_arch = "wasm32"))]
+            TextureError::Transcode(e) => {
+                write!(f, "Failed to transcode Basis Universal texture: {}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureOptions {
+    /// Generates the full mip chain on upload
+    pub mipmaps: bool,
+    /// 1 disables anisotropic filtering; clamped to 16
+    pub max_anisotropy: u16,
+    /// `Repeat` also filters across the antimeridian of equirectangular textures
+    pub address_mode_u: wgpu::AddressMode,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            mipmaps: true,
+            max_anisotropy: 16,
+            address_mode_u: wgpu::AddressMode::Repeat,
+        }
+    }
+}
 
 pub struct TextureSet {
     view: wgpu::TextureView,
     sampler: wgpu::Sampler,
+    _allocation: TrackedAllocation,
 }
 
 impl TextureSet {
-    pub fn from_bytes(
+    /// Uploads a color image, which is sRGB-encoded.
+    pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        bytes: &[u8],
-        label: &str,
+        tracker: &MemoryTracker,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        options: &TextureOptions,
     ) -> anyhow::Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        Self::from_rgba(device, queue, tracker, img, label, options, format)
     }
 
-    pub fn from_image(
+    /// Uploads an image of values other than colors, e.g. metalness and roughness,
+    /// which are stored linearly.
+    pub fn from_linear_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        tracker: &MemoryTracker,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        options: &TextureOptions,
+    ) -> anyhow::Result<Self> {
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        Self::from_rgba(device, queue, tracker, img, label, options, format)
+    }
+
+    fn from_rgba(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        tracker: &MemoryTracker,
         img: &image::DynamicImage,
         label: Option<&str>,
+        options: &TextureOptions,
+        format: wgpu::TextureFormat,
     ) -> anyhow::Result<Self> {
         let dimensions = img.dimensions();
         let rgba = img.to_rgba8();
+        let mip_level_count = if options.mipmaps {
+            dimensions.0.max(dimensions.1).max(1).ilog2() + 1
+        } else {
+            1
+        };
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
             depth_or_array_layers: 1,
         };
-        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
@@ -44,34 +179,147 @@ impl TextureSet {
             view_formats: &[],
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
+        let mut bytes = 0;
+        let mut level = rgba;
+        for mip_level in 0..mip_level_count {
+            if mip_level > 0 {
+                // each level is downsampled from the previous one
+                let width = (level.width() / 2).max(1);
+                let height = (level.height() / 2).max(1);
+                level = image::imageops::resize(&level, width, height, FilterType::Triangle);
+            }
+            write_level(queue, &texture, mip_level, &level);
+            bytes += level.as_raw().len() as u64;
+        }
+
+        Ok(Self::with_texture(
+            device, tracker, &texture, label, options, bytes,
+        ))
+    }
+
+    /// Uploads a KTX2 texture as it is, including its mip levels.
+    /// Block-compressed formats need the corresponding feature enabled on `device`.
+    /// Basis Universal textures are transcoded to a format `device` supports, except on the web.
+    pub fn from_ktx2(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        tracker: &MemoryTracker,
+        data: &[u8],
+        label: Option<&str>,
+        options: &TextureOptions,
+    ) -> anyhow::Result<Self> {
+        let reader = ktx2::Reader::new(data)?;
+        let header = reader.header();
+        if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+            return Err(TextureError::UnsupportedLayout.into());
+        }
+
+        let levels = reader
+            .levels()
+            .map(|level| decompress(level, header.supercompression_scheme))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let is_basis = header.supercompression_scheme
+            == Some(ktx2::SupercompressionScheme::BasisLZ)
+            || matches!(
+                reader.color_model(),
+                Some(ktx2::ColorModel::ETC1S) | Some(ktx2::ColorModel::UASTC)
+            );
+        let (format, levels) = if is_basis {
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                let transcoded = super::basis::transcode(&reader, &levels, device.features())?;
+                (transcoded.format, transcoded.levels)
+            }
+            #[cfg(target_arch = "wasm32")]
+            return Err(TextureError::BasisUniversal.into());
+        } else {
+            let format = header
+                .format
+                .and_then(ktx2_format)
+                .ok_or(TextureError::UnsupportedFormat(header.format))?;
+            (format, levels)
+        };
+        let required = format.required_features();
+        if !device.features().contains(required) {
+            return Err(TextureError::MissingFeature(required).into());
+        }
+
+        let size = wgpu::Extent3d {
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
             size,
-        );
+            mip_level_count: levels.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_copy_size(None).unwrap_or(4);
+        let mut bytes = 0;
+        for (mip_level, level_data) in levels.iter().enumerate() {
+            let mip_level = mip_level as u32;
+            let physical_size = size
+                .mip_level_size(mip_level, wgpu::TextureDimension::D2)
+                .physical_size(format);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                level_data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(physical_size.width / block_width * block_size),
+                    rows_per_image: Some(physical_size.height / block_height),
+                },
+                physical_size,
+            );
+            bytes += level_data.len() as u64;
+        }
 
+        Ok(Self::with_texture(
+            device, tracker, &texture, label, options, bytes,
+        ))
+    }
+
+    fn with_texture(
+        device: &wgpu::Device,
+        tracker: &MemoryTracker,
+        texture: &wgpu::Texture,
+        label: Option<&str>,
+        options: &TextureOptions,
+        bytes: u64,
+    ) -> Self {
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_u: options.address_mode_u,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
+            // anisotropic filtering requires every filter to be linear
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy_clamp: options.max_anisotropy.clamp(1, 16),
             ..Default::default()
         });
 
-        Ok(Self { view, sampler })
+        let allocation = tracker.track(label.unwrap_or("texture"), ResourceKind::Texture, bytes);
+
+        Self {
+            view,
+            sampler,
+            _allocation: allocation,
+        }
     }
 
     pub fn view(&self) -> &wgpu::TextureView {
@@ -82,3 +330,144 @@ impl TextureSet {
         &self.sampler
     }
 }
+
+fn write_level(queue: &wgpu::Queue, texture: &wgpu::Texture, mip_level: u32, image: &RgbaImage) {
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture,
+            mip_level,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        image.as_raw(),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * image.width()),
+            rows_per_image: Some(image.height()),
+        },
+        wgpu::Extent3d {
+            width: image.width(),
+            height: image.height(),
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+// The data of `level` without supercompression; BasisLZ is left to the transcoder
+fn decompress(
+    level: ktx2::Level,
+    scheme: Option<ktx2::SupercompressionScheme>,
+) -> anyhow::Result<Vec<u8>> {
+    Ok(match scheme {
+        None | Some(ktx2::SupercompressionScheme::BasisLZ) => level.data.to_vec(),
+        Some(ktx2::SupercompressionScheme::Zstandard) => {
+            let mut decoded = Vec::with_capacity(level.uncompressed_byte_length as usize);
+            ruzstd::decoding::StreamingDecoder::new(level.data)
+                .map_err(|e| TextureError::Decompression(e.to_string()))?
+                .read_to_end(&mut decoded)?;
+            decoded
+        }
+        Some(ktx2::SupercompressionScheme::ZLIB) => {
+            miniz_oxide::inflate::decompress_to_vec_zlib(level.data)
+                .map_err(|e| TextureError::Decompression(format!("{:?}", e)))?
+        }
+        Some(scheme) => return Err(TextureError::UnsupportedSupercompression(scheme).into()),
+    })
+}
+
+fn ktx2_format(format: ktx2::Format) -> Option<TextureFormat> {
+    use ktx2::Format as F;
+
+    let astc = |block, srgb| {
+        let channel = if srgb {
+            AstcChannel::UnormSrgb
+        } else {
+            AstcChannel::Unorm
+        };
+        TextureFormat::Astc { block, channel }
+    };
+    Some(match format {
+        F::R8G8B8A8_UNORM => TextureFormat::Rgba8Unorm,
+        F::R8G8B8A8_SRGB => TextureFormat::Rgba8UnormSrgb,
+        F::BC1_RGBA_UNORM_BLOCK | F::BC1_RGB_UNORM_BLOCK => TextureFormat::Bc1RgbaUnorm,
+        F::BC1_RGBA_SRGB_BLOCK | F::BC1_RGB_SRGB_BLOCK => TextureFormat::Bc1RgbaUnormSrgb,
+        F::BC2_UNORM_BLOCK => TextureFormat::Bc2RgbaUnorm,
+        F::BC2_SRGB_BLOCK => TextureFormat::Bc2RgbaUnormSrgb,
+        F::BC3_UNORM_BLOCK => TextureFormat::Bc3RgbaUnorm,
+        F::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnormSrgb,
+        F::BC7_UNORM_BLOCK => TextureFormat::Bc7RgbaUnorm,
+        F::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnormSrgb,
+        F::ETC2_R8G8B8_UNORM_BLOCK => TextureFormat::Etc2Rgb8Unorm,
+        F::ETC2_R8G8B8_SRGB_BLOCK => TextureFormat::Etc2Rgb8UnormSrgb,
+        F::ETC2_R8G8B8A1_UNORM_BLOCK => TextureFormat::Etc2Rgb8A1Unorm,
+        F::ETC2_R8G8B8A1_SRGB_BLOCK => TextureFormat::Etc2Rgb8A1UnormSrgb,
+        F::ETC2_R8G8B8A8_UNORM_BLOCK => TextureFormat::Etc2Rgba8Unorm,
+        F::ETC2_R8G8B8A8_SRGB_BLOCK => TextureFormat::Etc2Rgba8UnormSrgb,
+        F::ASTC_4x4_UNORM_BLOCK => astc(AstcBlock::B4x4, false),
+        F::ASTC_4x4_SRGB_BLOCK => astc(AstcBlock::B4x4, true),
+        F::ASTC_5x5_UNORM_BLOCK => astc(AstcBlock::B5x5, false),
+        F::ASTC_5x5_SRGB_BLOCK => astc(AstcBlock::B5x5, true),
+        F::ASTC_6x6_UNORM_BLOCK => astc(AstcBlock::B6x6, false),
+        F::ASTC_6x6_SRGB_BLOCK => astc(AstcBlock::B6x6, true),
+        F::ASTC_8x8_UNORM_BLOCK => astc(AstcBlock::B8x8, false),
+        F::ASTC_8x8_SRGB_BLOCK => astc(AstcBlock::B8x8, true),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    #[test]
+    fn uploads_basis_universal_textures() {
+        let Some((device, queue)) = crate::testing::device() else {
+            return;
+        };
+        let tracker = MemoryTracker::new();
+        for data in [
+            &include_bytes!("../../testdata/quadrants_etc1s.ktx2")[..],
+            &include_bytes!("../../testdata/quadrants_etc1s_opaque.ktx2")[..],
+            &include_bytes!("../../testdata/quadrants_uastc.ktx2")[..],
+        ] {
+            device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let _texture = TextureSet::from_ktx2(
+                &device,
+                &queue,
+                &tracker,
+                data,
+                Some("quadrants"),
+                &TextureOptions::default(),
+            )
+            .unwrap();
+            queue.submit([]);
+            assert!(block_on(device.pop_error_scope()).is_none());
+        }
+    }
+
+    #[test]
+    fn rejects_layered_textures() {
+        let Some((device, queue)) = crate::testing::device() else {
+            return;
+        };
+        let mut data = include_bytes!("../../testdata/quadrants_uastc.ktx2").to_vec();
+        // layerCount follows the identifier and five other fields
+        data[12 + 4 * 5..12 + 4 * 6].copy_from_slice(&2u32.to_le_bytes());
+        let error = TextureSet::from_ktx2(
+            &device,
+            &queue,
+            &MemoryTracker::new(),
+            &data,
+            None,
+            &TextureOptions::default(),
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(TextureError::UnsupportedLayout)
+        ));
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+
+#[cfg(feature = "web")]
+use wasm_bindgen::prelude::*;
+
+use crate::{asset::LoadError, shader::ShaderError};
+
+/// Why a `State` or a scene could not be created.
+#[derive(Debug)]
+pub enum GraphicsError {
+    /// No adapter supports the surface, e.g. WebGPU and WebGL are both unavailable
+    AdapterUnavailable,
+    DeviceRequest(wgpu::RequestDeviceError),
+    /// The surface cannot be created or has no usable format or mode
+    UnsupportedSurface(String),
+    /// A resource could not be read from its source
+    AssetFetch {
+        path: String,
+        message: String,
+    },
+    /// A resource was read but is not a valid model or texture
+    AssetDecode(String),
+    /// Loading was cancelled through the `CancelToken` of the loader
+    Cancelled,
+    Shader(ShaderError),
+}
+
+impl GraphicsError {
+    /// A stable identifier of the variant, such as `adapter-unavailable`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            GraphicsError::AdapterUnavailable => "adapter-unavailable",
+            GraphicsError::DeviceRequest(_) => "device-request-failed",
+            GraphicsError::UnsupportedSurface(_) => "unsupported-surface",
+            GraphicsError::AssetFetch { .. } => "asset-fetch-failed",
+            GraphicsError::AssetDecode(_) => "asset-decode-failed",
+            GraphicsError::Cancelled => "cancelled",
+            GraphicsError::Shader(_) => "shader-error",
+        }
+    }
+
+    // Errors returned by an `AssetSource` are fetch errors; the loader tells cancelling apart
+    pub(crate) fn fetch(path: &str, error: anyhow::Error) -> Self {
+        match error.downcast_ref::<LoadError>() {
+            Some(LoadError::Cancelled) => GraphicsError::Cancelled,
+            _ => GraphicsError::AssetFetch {
+                path: path.to_string(),
+                message: format!("{:#}", error),
+            },
+        }
+    }
+}
+
+impl fmt::Display for GraphicsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphicsError::AdapterUnavailable => write!(f, "No graphics adapter is available"),
+            GraphicsError::DeviceRequest(e) => write!(f, "Failed to request a device: {}", e),
+            GraphicsError::UnsupportedSurface(message) => {
+                write!(f, "Unsupported surface: {}", message)
+            }
+            GraphicsError::AssetFetch { path, message } => {
+                write!(f, "Failed to fetch {}: {}", path, message)
+            }
+            GraphicsError::AssetDecode(message) => {
+                write!(f, "Failed to decode assets: {}", message)
+            }
+            GraphicsError::Cancelled => write!(f, "Loading was cancelled"),
+            GraphicsError::Shader(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for GraphicsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GraphicsError::DeviceRequest(e) => Some(e),
+            GraphicsError::Shader(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for GraphicsError {
+    fn from(e: wgpu::RequestDeviceError) -> Self {
+        GraphicsError::DeviceRequest(e)
+    }
+}
+
+impl From<wgpu::CreateSurfaceError> for GraphicsError {
+    fn from(e: wgpu::CreateSurfaceError) -> Self {
+        GraphicsError::UnsupportedSurface(e.to_string())
+    }
+}
+
+impl From<ShaderError> for GraphicsError {
+    fn from(e: ShaderError) -> Self {
+        GraphicsError::Shader(e)
+    }
+}
+
+/// Fetch errors tagged by `GraphicsError::fetch` keep their variant; anything else
+/// went wrong while decoding what was fetched.
+impl From<anyhow::Error> for GraphicsError {
+    fn from(e: anyhow::Error) -> Self {
+        e.downcast::<GraphicsError>()
+            .unwrap_or_else(|e| GraphicsError::AssetDecode(format!("{:#}", e)))
+    }
+}
+
+/// A JS `Error` with the message, and `code` set to `GraphicsError::code`.
+#[cfg(feature = "web")]
+impl From<GraphicsError> for JsValue {
+    fn from(e: GraphicsError) -> Self {
+        let error = js_sys::Error::new(&e.to_string());
+        error.set_name("GraphicsError");
+        let _ = js_sys::Reflect::set(&error, &"code".into(), &e.code().into());
+        error.into()
+    }
+}
@@ -0,0 +1,322 @@
+use serde::{Deserialize, Serialize};
+
+use crate::action::Action;
+
+// Buttons pressed less than this, e.g. a resting analog trigger, count as released
+const BUTTON_THRESHOLD: f32 = 0.1;
+
+/// A snapshot of a gamepad or other controller, laid out like the browser Gamepad API:
+/// axes from -1 to 1 and buttons from 0 to 1. With the standard mapping, axes 0 and 1 are
+/// the left stick and 2 and 3 the right stick, positive to the right and down.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GamepadState {
+    /// Names the device, e.g. `Xbox Wireless Controller` or `3Dconnexion SpaceMouse`
+    pub id: String,
+    pub axes: Vec<f32>,
+    pub buttons: Vec<f32>,
+}
+
+/// How the deflection of an axis becomes the strength of an action.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AxisResponse {
+    /// Deflections below this count as centered; the range above starts from zero
+    pub dead_zone: f32,
+    /// 1 is linear; larger values give finer control near the center
+    pub exponent: f32,
+    /// Strength at full deflection, where 1 matches holding a key
+    pub sensitivity: f32,
+}
+
+impl Default for AxisResponse {
+    fn default() -> Self {
+        Self {
+            dead_zone: 0.15,
+            exponent: 2.0,
+            sensitivity: 1.0,
+        }
+    }
+}
+
+impl AxisResponse {
+    /// The strength for a deflection towards the bound direction; 0 in the dead zone.
+    pub fn apply(&self, deflection: f32) -> f32 {
+        let dead_zone = self.dead_zone.clamp(0.0, 0.99);
+        if deflection <= dead_zone {
+            return 0.0;
+        }
+        let t = ((deflection - dead_zone) / (1.0 - dead_zone)).min(1.0);
+        t.powf(self.exponent.max(0.1)) * self.sensitivity
+    }
+}
+
+/// An axis that triggers `action` when deflected to the positive side, or to the
+/// negative side with `negative`. Without `device`, it applies to every gamepad.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AxisBinding {
+    pub action: Action,
+    pub axis: usize,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub negative: bool,
+    #[serde(default)]
+    pub response: AxisResponse,
+    /// Only gamepads whose id contains this, ignoring case
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+impl AxisBinding {
+    pub fn new(action: Action, axis: usize, negative: bool) -> Self {
+        Self {
+            action,
+            axis,
+            negative,
+            response: AxisResponse::default(),
+            device: None,
+        }
+    }
+
+    /// The strength of the action from `gamepad`, 0 if the binding does not apply.
+    pub fn value(&self, gamepad: &GamepadState) -> f32 {
+        if !matches_device(&self.device, gamepad) {
+            return 0.0;
+        }
+        let deflection = gamepad.axes.get(self.axis).copied().unwrap_or(0.0);
+        let deflection = if self.negative {
+            -deflection
+        } else {
+            deflection
+        };
+        self.response.apply(deflection)
+    }
+}
+
+/// A button that triggers `action`, as strongly as it is pressed for analog triggers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ButtonBinding {
+    pub action: Action,
+    pub button: usize,
+    /// Only gamepads whose id contains this, ignoring case
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+impl ButtonBinding {
+    pub fn new(action: Action, button: usize) -> Self {
+        Self {
+            action,
+            button,
+            device: None,
+        }
+    }
+
+    /// The strength of the action from `gamepad`, 0 if the binding does not apply.
+    pub fn value(&self, gamepad: &GamepadState) -> f32 {
+        if !matches_device(&self.device, gamepad) {
+            return 0.0;
+        }
+        let value = gamepad.buttons.get(self.button).copied().unwrap_or(0.0);
+        if value < BUTTON_THRESHOLD {
+            0.0
+        } else {
+            value
+        }
+    }
+}
+
+fn matches_device(device: &Option<String>, gamepad: &GamepadState) -> bool {
+    device
+        .as_ref()
+        .is_none_or(|device| gamepad.id.to_lowercase().contains(&device.to_lowercase()))
+}
+
+// Sticks like the keyboard: the left one moves, the right one turns, and the
+// triggers move forward and backward. Button 3 is the top face button. Six-axis
+// controllers also move vertically with axis 4 and twist with axis 5.
+pub(crate) fn default_axis_bindings() -> Vec<AxisBinding> {
+    [
+        (Action::MoveLeft, 0, true),
+        (Action::MoveRight, 0, false),
+        (Action::MoveUp, 1, true),
+        (Action::MoveDown, 1, false),
+        (Action::RotateLeft, 2, true),
+        (Action::RotateRight, 2, false),
+        (Action::RotateUp, 3, true),
+        (Action::RotateDown, 3, false),
+        (Action::MoveUp, 4, true),
+        (Action::MoveDown, 4, false),
+        (Action::RotateLeft, 5, true),
+        (Action::RotateRight, 5, false),
+    ]
+    .into_iter()
+    .map(|(action, axis, negative)| AxisBinding::new(action, axis, negative))
+    .collect()
+}
+
+pub(crate) fn default_button_bindings() -> Vec<ButtonBinding> {
+    [
+        (Action::MoveBackward, 6),
+        (Action::MoveForward, 7),
+        (Action::ToggleCameraMode, 3),
+    ]
+    .into_iter()
+    .map(|(action, button)| ButtonBinding::new(action, button))
+    .collect()
+}
+
+// The connected gamepads from `navigator.getGamepads()`
+#[cfg(feature = "web")]
+pub(crate) fn read_gamepads() -> Vec<GamepadState> {
+    use wasm_bindgen::JsCast;
+
+    let Some(gamepads) =
+        web_sys::window().and_then(|window| window.navigator().get_gamepads().ok())
+    else {
+        return Vec::new();
+    };
+    gamepads
+        .iter()
+        .filter_map(|gamepad| gamepad.dyn_into::<web_sys::Gamepad>().ok())
+        .filter(|gamepad| gamepad.connected())
+        .map(|gamepad| GamepadState {
+            id: gamepad.id(),
+            axes: gamepad
+                .axes()
+                .iter()
+                .map(|axis| axis.as_f64().unwrap_or(0.0) as f32)
+                .collect(),
+            buttons: gamepad
+                .buttons()
+                .iter()
+                .map(|button| {
+                    button
+                        .dyn_into::<web_sys::GamepadButton>()
+                        .map_or(0.0, |button| button.value() as f32)
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gamepad(id: &str, axes: Vec<f32>) -> GamepadState {
+        GamepadState {
+            id: id.to_string(),
+            axes,
+            buttons: vec![0.0, 0.05, 0.6],
+        }
+    }
+
+    #[test]
+    fn dead_zone_is_centered() {
+        let response = AxisResponse::default();
+        assert_eq!(response.dead_zone, 0.15);
+        assert_eq!(response.apply(0.0), 0.0);
+        assert_eq!(response.apply(0.1), 0.0);
+        assert_eq!(response.apply(0.15), 0.0);
+    }
+
+    #[test]
+    fn response_is_continuous_at_the_dead_zone() {
+        let response = AxisResponse {
+            exponent: 1.0,
+            ..AxisResponse::default()
+        };
+        let above = response.apply(0.15 + 1e-4);
+        assert!(above > 0.0 && above < 1e-3, "{}", above);
+    }
+
+    #[test]
+    fn full_deflection_gives_the_sensitivity() {
+        let response = AxisResponse {
+            sensitivity: 2.5,
+            ..AxisResponse::default()
+        };
+        assert_eq!(response.apply(1.0), 2.5);
+        // some sticks report slightly more than 1 on the diagonals
+        assert_eq!(response.apply(1.2), 2.5);
+    }
+
+    #[test]
+    fn bindings_take_one_side_of_an_axis() {
+        let negative = AxisBinding::new(Action::MoveLeft, 0, true);
+        let positive = AxisBinding::new(Action::MoveRight, 0, false);
+        let right = gamepad("Gamepad", vec![1.0]);
+        assert_eq!(negative.value(&right), 0.0);
+        assert_eq!(positive.value(&right), 1.0);
+        let left = gamepad("Gamepad", vec![-1.0]);
+        assert_eq!(negative.value(&left), 1.0);
+        assert_eq!(positive.value(&left), 0.0);
+        // missing axes are centered
+        assert_eq!(
+            AxisBinding::new(Action::MoveUp, 4, false).value(&right),
+            0.0
+        );
+    }
+
+    #[test]
+    fn bindings_only_apply_to_their_device() {
+        let axis = AxisBinding {
+            device: Some("spacemouse".to_string()),
+            ..AxisBinding::new(Action::RotateLeft, 0, false)
+        };
+        let button = ButtonBinding {
+            device: Some("SpaceMouse".to_string()),
+            ..ButtonBinding::new(Action::MoveForward, 2)
+        };
+        let space_mouse = gamepad("3Dconnexion SpaceMouse Pro", vec![1.0]);
+        let other = gamepad("Xbox Wireless Controller", vec![1.0]);
+        assert_eq!(axis.value(&space_mouse), 1.0);
+        assert_eq!(axis.value(&other), 0.0);
+        assert_eq!(button.value(&space_mouse), 0.6);
+        assert_eq!(button.value(&other), 0.0);
+    }
+
+    #[test]
+    fn buttons_below_the_threshold_are_released() {
+        let pad = gamepad("Gamepad", Vec::new());
+        assert_eq!(ButtonBinding::new(Action::MoveForward, 1).value(&pad), 0.0);
+        assert_eq!(ButtonBinding::new(Action::MoveForward, 2).value(&pad), 0.6);
+        assert_eq!(ButtonBinding::new(Action::MoveForward, 9).value(&pad), 0.0);
+    }
+
+    #[test]
+    fn bindings_round_trip_through_json() {
+        let axis = AxisBinding {
+            response: AxisResponse {
+                dead_zone: 0.05,
+                exponent: 1.5,
+                sensitivity: 2.0,
+            },
+            device: Some("SpaceMouse".to_string()),
+            ..AxisBinding::new(Action::RotateLeft, 5, true)
+        };
+        let json = serde_json::to_string(&axis).unwrap();
+        assert_eq!(serde_json::from_str::<AxisBinding>(&json).unwrap(), axis);
+
+        let button = ButtonBinding::new(Action::ToggleCameraMode, 3);
+        let json = serde_json::to_string(&button).unwrap();
+        assert_eq!(json, r#"{"action":"ToggleCameraMode","button":3}"#);
+        assert_eq!(
+            serde_json::from_str::<ButtonBinding>(&json).unwrap(),
+            button
+        );
+
+        // omitted fields take their defaults
+        let axis: AxisBinding =
+            serde_json::from_str(r#"{"action":"MoveUp","axis":1,"response":{"dead_zone":0.2}}"#)
+                .unwrap();
+        assert!(!axis.negative);
+        assert_eq!(axis.device, None);
+        assert_eq!(
+            axis.response,
+            AxisResponse {
+                dead_zone: 0.2,
+                ..AxisResponse::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use cgmath::{InnerSpace, Zero};
+
+// Wheel delta in pixels that halves or doubles the distance
+const WHEEL_STEP: f32 = 500.0;
+// Seconds for the inertia of a released drag to slow down to about a third
+const INERTIA_TIME: f32 = 0.3;
+// Below this speed in pixels per second the inertia stops
+const INERTIA_MIN_SPEED: f32 = 5.0;
+
+/// What the pointers did, in pixels from the top left corner of the viewport.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    /// A pointer moved while pressed, or kept moving after it was released
+    Drag {
+        from: cgmath::Vector2<f32>,
+        to: cgmath::Vector2<f32>,
+    },
+    /// Zooming in around `center` by `factor`, which is below 1 when zooming out
+    Zoom {
+        center: cgmath::Vector2<f32>,
+        factor: f32,
+    },
+    /// Two pointers turned clockwise on the screen around `center` by `angle` radians
+    Twist {
+        center: cgmath::Vector2<f32>,
+        angle: f32,
+    },
+}
+
+/// Turns pointer events into gestures: one pointer drags, two pointers pinch to zoom
+/// and twist while their midpoint drags, and the wheel zooms. A drag released while
+/// moving keeps going and slows down.
+#[derive(Debug)]
+pub struct GestureRecognizer {
+    // pressed pointers in the order they went down; only the first two make gestures
+    pointers: Vec<(i32, cgmath::Vector2<f32>)>,
+    gestures: Vec<Gesture>,
+    // dragged since the last update, which gives the velocity
+    dragged: cgmath::Vector2<f32>,
+    velocity: cgmath::Vector2<f32>,
+    // where the inertia of a released drag is
+    inertia: Option<cgmath::Vector2<f32>>,
+}
+
+impl GestureRecognizer {
+    pub fn new() -> Self {
+        Self {
+            pointers: Vec::new(),
+            gestures: Vec::new(),
+            dragged: cgmath::Vector2::zero(),
+            velocity: cgmath::Vector2::zero(),
+            inertia: None,
+        }
+    }
+
+    pub fn pointer_down(&mut self, id: i32, position: cgmath::Vector2<f32>) {
+        self.inertia = None;
+        self.pointers.retain(|(pointer, _)| *pointer != id);
+        self.pointers.push((id, position));
+        self.reset_velocity();
+    }
+
+    pub fn pointer_move(&mut self, id: i32, position: cgmath::Vector2<f32>) {
+        let Some(index) = self.pointers.iter().position(|(pointer, _)| *pointer == id) else {
+            return;
+        };
+        let previous = self.pointers[index].1;
+        self.pointers[index].1 = position;
+
+        match (index, self.pointers.len()) {
+            (0, 1) => {
+                self.dragged += position - previous;
+                self.push_drag(previous, position);
+            }
+            (0 | 1, _) => {
+                let other = self.pointers[1 - index].1;
+                self.pinch([previous, other], [position, other]);
+            }
+            _ => {}
+        }
+    }
+
+    /// Also for cancelled pointers. Releasing the last pointer starts the inertia.
+    pub fn pointer_up(&mut self, id: i32) {
+        let Some(index) = self.pointers.iter().position(|(pointer, _)| *pointer == id) else {
+            return;
+        };
+        let (_, position) = self.pointers.remove(index);
+        if self.pointers.is_empty() && self.velocity.magnitude() > INERTIA_MIN_SPEED {
+            self.inertia = Some(position);
+        } else {
+            // the pointer left keeps dragging from where it is, without a jump in velocity
+            self.reset_velocity();
+        }
+    }
+
+    /// `delta` is in pixels, positive when scrolling down, which zooms out.
+    pub fn wheel(&mut self, position: cgmath::Vector2<f32>, delta: f32) {
+        self.gestures.push(Gesture::Zoom {
+            center: position,
+            factor: 2.0_f32.powf(-delta / WHEEL_STEP),
+        });
+    }
+
+    /// Forgets the pressed pointers and stops the inertia, e.g. when the page loses focus.
+    pub fn cancel(&mut self) {
+        self.pointers.clear();
+        self.inertia = None;
+        self.reset_velocity();
+    }
+
+    /// The gestures since the last update, and the inertia for `dt` seconds.
+    pub fn update(&mut self, dt: f32) -> Vec<Gesture> {
+        if self.pointers.len() == 1 && dt > 0.0 {
+            self.velocity = self.dragged / dt;
+        }
+        self.dragged = cgmath::Vector2::zero();
+
+        if let Some(position) = self.inertia {
+            let next = position + self.velocity * dt;
+            self.push_drag(position, next);
+            self.velocity *= (-dt / INERTIA_TIME).exp();
+            self.inertia = (self.velocity.magnitude() > INERTIA_MIN_SPEED).then_some(next);
+        }
+        std::mem::take(&mut self.gestures)
+    }
+
+    fn reset_velocity(&mut self) {
+        self.dragged = cgmath::Vector2::zero();
+        self.velocity = cgmath::Vector2::zero();
+    }
+
+    fn push_drag(&mut self, from: cgmath::Vector2<f32>, to: cgmath::Vector2<f32>) {
+        if from != to {
+            self.gestures.push(Gesture::Drag { from, to });
+        }
+    }
+
+    fn pinch(&mut self, from: [cgmath::Vector2<f32>; 2], to: [cgmath::Vector2<f32>; 2]) {
+        let center = (to[0] + to[1]) / 2.0;
+        self.push_drag((from[0] + from[1]) / 2.0, center);
+
+        let (span_from, span_to) = (from[1] - from[0], to[1] - to[0]);
+        if span_from.magnitude() > 0.0 && span_to.magnitude() > 0.0 {
+            let factor = span_to.magnitude() / span_from.magnitude();
+            if factor != 1.0 {
+                self.gestures.push(Gesture::Zoom { center, factor });
+            }
+            let angle = span_from.perp_dot(span_to).atan2(span_from.dot(span_to));
+            if angle != 0.0 {
+                self.gestures.push(Gesture::Twist { center, angle });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use cgmath::vec2;
+
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    #[test]
+    fn one_pointer_drags() {
+        let mut gestures = GestureRecognizer::new();
+        gestures.pointer_down(1, vec2(10.0, 10.0));
+        gestures.pointer_move(1, vec2(20.0, 15.0));
+        gestures.pointer_move(1, vec2(20.0, 15.0));
+        assert_eq!(
+            gestures.update(DT),
+            [Gesture::Drag {
+                from: vec2(10.0, 10.0),
+                to: vec2(20.0, 15.0),
+            }]
+        );
+        assert!(gestures.update(DT).is_empty());
+
+        // moving a pointer that is not pressed does nothing
+        gestures.pointer_move(2, vec2(0.0, 0.0));
+        assert!(gestures.update(DT).is_empty());
+    }
+
+    #[test]
+    fn two_pointers_pinch() {
+        let mut gestures = GestureRecognizer::new();
+        gestures.pointer_down(1, vec2(0.0, 0.0));
+        gestures.pointer_down(2, vec2(100.0, 0.0));
+        assert!(gestures.update(DT).is_empty());
+
+        // spreading the pointers zooms in around their midpoint, which drags along
+        gestures.pointer_move(2, vec2(200.0, 0.0));
+        assert_eq!(
+            gestures.update(DT),
+            [
+                Gesture::Drag {
+                    from: vec2(50.0, 0.0),
+                    to: vec2(100.0, 0.0),
+                },
+                Gesture::Zoom {
+                    center: vec2(100.0, 0.0),
+                    factor: 2.0,
+                },
+            ]
+        );
+
+        // turning them a quarter clockwise on the screen, where y points down, twists
+        gestures.pointer_move(2, vec2(0.0, 200.0));
+        let moved = gestures.update(DT);
+        assert_eq!(moved.len(), 2, "{:?}", moved);
+        assert_eq!(
+            moved[0],
+            Gesture::Drag {
+                from: vec2(100.0, 0.0),
+                to: vec2(0.0, 100.0),
+            }
+        );
+        let Gesture::Twist { center, angle } = moved[1] else {
+            panic!("{:?}", moved[1]);
+        };
+        assert_eq!(center, vec2(0.0, 100.0));
+        assert!((angle - FRAC_PI_2).abs() < 1e-5, "{}", angle);
+
+        // a third pointer is ignored
+        gestures.pointer_down(3, vec2(50.0, 50.0));
+        gestures.pointer_move(3, vec2(60.0, 60.0));
+        assert!(gestures.update(DT).is_empty());
+    }
+
+    #[test]
+    fn lifting_one_pointer_mid_pinch_drags_with_the_other() {
+        let mut gestures = GestureRecognizer::new();
+        gestures.pointer_down(1, vec2(0.0, 0.0));
+        gestures.pointer_down(2, vec2(100.0, 0.0));
+        gestures.pointer_move(1, vec2(-50.0, 0.0));
+        assert!(!gestures.update(DT).is_empty());
+
+        gestures.pointer_up(1);
+        assert!(gestures.update(DT).is_empty());
+        gestures.pointer_move(2, vec2(110.0, 5.0));
+        assert_eq!(
+            gestures.update(DT),
+            [Gesture::Drag {
+                from: vec2(100.0, 0.0),
+                to: vec2(110.0, 5.0),
+            }]
+        );
+
+        // releasing the last pointer while still keeps it in place
+        gestures.update(DT);
+        gestures.pointer_up(2);
+        assert!(gestures.update(DT).is_empty());
+    }
+
+    #[test]
+    fn wheel_zooms_around_pointer() {
+        let mut gestures = GestureRecognizer::new();
+        gestures.wheel(vec2(30.0, 40.0), WHEEL_STEP);
+        gestures.wheel(vec2(30.0, 40.0), -2.0 * WHEEL_STEP);
+        assert_eq!(
+            gestures.update(DT),
+            [
+                Gesture::Zoom {
+                    center: vec2(30.0, 40.0),
+                    factor: 0.5,
+                },
+                Gesture::Zoom {
+                    center: vec2(30.0, 40.0),
+                    factor: 4.0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn released_drag_slows_down() {
+        let mut gestures = GestureRecognizer::new();
+        gestures.pointer_down(1, vec2(0.0, 0.0));
+        gestures.pointer_move(1, vec2(10.0, 0.0));
+        gestures.update(DT);
+        gestures.pointer_up(1);
+
+        let mut step = f32::INFINITY;
+        let mut frames = 0;
+        loop {
+            let moved = gestures.update(DT);
+            let [Gesture::Drag { from, to }] = moved[..] else {
+                assert!(moved.is_empty(), "{:?}", moved);
+                break;
+            };
+            assert!(to.x > from.x && to.y == from.y);
+            assert!(to.x - from.x < step);
+            step = to.x - from.x;
+            frames += 1;
+        }
+        assert!(frames > 1 && frames < 600, "{}", frames);
+
+        // cancelling stops the inertia at once
+        gestures.pointer_down(1, vec2(0.0, 0.0));
+        gestures.pointer_move(1, vec2(10.0, 0.0));
+        gestures.update(DT);
+        gestures.pointer_up(1);
+        gestures.cancel();
+        assert!(gestures.update(DT).is_empty());
+    }
+}
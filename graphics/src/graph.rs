@@ -0,0 +1,415 @@
+use std::{collections::BTreeSet, fmt};
+
+/// A texture of a `RenderGraph`, or the view the frame is rendered to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TextureId(usize);
+
+/// A texture allocated by the graph, always the size of the frame.
+/// Its usages are derived from the passes reading and writing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureDesc {
+    pub label: &'static str,
+    pub format: wgpu::TextureFormat,
+    pub sample_count: u32,
+    /// Usages beyond those of the passes, e.g. `COPY_SRC` to read it back
+    pub usage: wgpu::TextureUsages,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    /// A pass reads a texture that no pass writes
+    NotWritten {
+        pass: &'static str,
+        texture: &'static str,
+    },
+    /// The passes depend on each other
+    Cycle(Vec<&'static str>),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::NotWritten { pass, texture } => {
+                write!(f, "Pass {} reads {}, which no pass writes", pass, texture)
+            }
+            GraphError::Cycle(passes) => {
+                write!(
+                    f,
+                    "Render passes depend on each other: {}",
+                    passes.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+/// What a pass gets to draw with: the views of the graph textures and the frame size.
+pub struct PassResources<'a> {
+    views: &'a [Option<wgpu::TextureView>],
+    output: &'a wgpu::TextureView,
+    size: (u32, u32),
+}
+
+impl<'a> PassResources<'a> {
+    pub fn view(&self, id: TextureId) -> &'a wgpu::TextureView {
+        if id == OUTPUT {
+            return self.output;
+        }
+        self.views[id.0]
+            .as_ref()
+            .expect("render graph textures are allocated by resize")
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+}
+
+type RunPass<C> = Box<dyn Fn(&C, &mut wgpu::CommandEncoder, &PassResources)>;
+
+struct Pass<C> {
+    name: &'static str,
+    reads: Vec<TextureId>,
+    writes: Vec<TextureId>,
+    run: RunPass<C>,
+}
+
+const OUTPUT: TextureId = TextureId(usize::MAX);
+
+/// Declares the textures and passes of a `RenderGraph`; does not need a GPU.
+pub struct RenderGraphBuilder<C> {
+    textures: Vec<TextureDesc>,
+    passes: Vec<Pass<C>>,
+}
+
+impl<C> Default for RenderGraphBuilder<C> {
+    fn default() -> Self {
+        Self {
+            textures: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+}
+
+impl<C> RenderGraphBuilder<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn texture(&mut self, desc: TextureDesc) -> TextureId {
+        self.textures.push(desc);
+        TextureId(self.textures.len() - 1)
+    }
+
+    /// The view passed to `RenderGraph::run`.
+    pub fn output(&self) -> TextureId {
+        OUTPUT
+    }
+
+    /// A pass that reads and writes a texture modifies it: it runs after the passes that only
+    /// write it and before the ones that only read it. Passes in the same role keep the order
+    /// they are added in.
+    pub fn pass(
+        &mut self,
+        name: &'static str,
+        reads: &[TextureId],
+        writes: &[TextureId],
+        run: impl Fn(&C, &mut wgpu::CommandEncoder, &PassResources) + 'static,
+    ) {
+        self.passes.push(Pass {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            run: Box::new(run),
+        });
+    }
+
+    /// Orders the passes by their dependencies.
+    pub fn build(self) -> Result<RenderGraph<C>, GraphError> {
+        let order = self.order()?;
+        let allocated = self.textures.iter().map(|_| None).collect();
+        let views = self.textures.iter().map(|_| None).collect();
+        Ok(RenderGraph {
+            textures: self.textures,
+            passes: self.passes,
+            order,
+            allocated,
+            views,
+            size: (0, 0),
+        })
+    }
+
+    fn texture_label(&self, id: TextureId) -> &'static str {
+        if id == OUTPUT {
+            "output"
+        } else {
+            self.textures[id.0].label
+        }
+    }
+
+    fn order(&self) -> Result<Vec<usize>, GraphError> {
+        let mut ids = (0..self.textures.len()).map(TextureId).collect::<Vec<_>>();
+        ids.push(OUTPUT);
+
+        // dependencies[pass] holds the passes that have to run before it
+        let mut dependencies = vec![BTreeSet::new(); self.passes.len()];
+        for id in ids {
+            let (mut writers, mut modifiers, mut readers) = (Vec::new(), Vec::new(), Vec::new());
+            for (index, pass) in self.passes.iter().enumerate() {
+                match (pass.reads.contains(&id), pass.writes.contains(&id)) {
+                    (false, true) => writers.push(index),
+                    (true, true) => modifiers.push(index),
+                    (true, false) => readers.push(index),
+                    (false, false) => {}
+                }
+            }
+            if writers.is_empty() {
+                if let Some(&pass) = modifiers.first().or(readers.first()) {
+                    return Err(GraphError::NotWritten {
+                        pass: self.passes[pass].name,
+                        texture: self.texture_label(id),
+                    });
+                }
+            }
+
+            let chain = writers
+                .iter()
+                .chain(&modifiers)
+                .copied()
+                .collect::<Vec<_>>();
+            for pair in chain.windows(2) {
+                dependencies[pair[1]].insert(pair[0]);
+            }
+            if let Some(&last) = chain.last() {
+                for &reader in &readers {
+                    dependencies[reader].insert(last);
+                }
+            }
+        }
+
+        // Kahn's algorithm, taking the earliest added pass among the ready ones
+        let mut order = Vec::with_capacity(self.passes.len());
+        let mut ready = (0..self.passes.len())
+            .filter(|&pass| dependencies[pass].is_empty())
+            .collect::<BTreeSet<_>>();
+        while let Some(pass) = ready.pop_first() {
+            order.push(pass);
+            for (next, before) in dependencies.iter_mut().enumerate() {
+                if before.remove(&pass) && before.is_empty() {
+                    ready.insert(next);
+                }
+            }
+        }
+
+        if order.len() < self.passes.len() {
+            return Err(GraphError::Cycle(
+                (0..self.passes.len())
+                    .filter(|pass| !order.contains(pass))
+                    .map(|pass| self.passes[pass].name)
+                    .collect(),
+            ));
+        }
+        Ok(order)
+    }
+}
+
+/// Render passes ordered by the textures they read and write, with the textures
+/// allocated for them. `C` is what the passes draw, passed to `run`.
+pub struct RenderGraph<C> {
+    textures: Vec<TextureDesc>,
+    passes: Vec<Pass<C>>,
+    order: Vec<usize>,
+    allocated: Vec<Option<wgpu::Texture>>,
+    views: Vec<Option<wgpu::TextureView>>,
+    size: (u32, u32),
+}
+
+impl<C> RenderGraph<C> {
+    /// Names of the passes in the order they run.
+    pub fn pass_names(&self) -> Vec<&'static str> {
+        self.order
+            .iter()
+            .map(|&pass| self.passes[pass].name)
+            .collect()
+    }
+
+    /// (Re)allocates the textures; bind groups using them have to be recreated afterwards.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.size = (width, height);
+        for (index, desc) in self.textures.iter().enumerate() {
+            let id = TextureId(index);
+            let mut usage = desc.usage;
+            for pass in &self.passes {
+                if pass.writes.contains(&id) {
+                    usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+                }
+                if pass.reads.contains(&id) {
+                    usage |= wgpu::TextureUsages::TEXTURE_BINDING;
+                }
+            }
+
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(desc.label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: desc.sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: desc.format,
+                usage,
+                view_formats: &[],
+            });
+            self.views[index] = Some(texture.create_view(&wgpu::TextureViewDescriptor::default()));
+            self.allocated[index] = Some(texture);
+        }
+    }
+
+    pub fn texture(&self, id: TextureId) -> &wgpu::Texture {
+        self.allocated[id.0]
+            .as_ref()
+            .expect("render graph textures are allocated by resize")
+    }
+
+    pub fn view(&self, id: TextureId) -> &wgpu::TextureView {
+        self.views[id.0]
+            .as_ref()
+            .expect("render graph textures are allocated by resize")
+    }
+
+    pub fn run(&self, context: &C, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let resources = PassResources {
+            views: &self.views,
+            output,
+            size: self.size,
+        };
+        for &pass in &self.order {
+            (self.passes[pass].run)(context, encoder, &resources);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desc(label: &'static str) -> TextureDesc {
+        TextureDesc {
+            label,
+            format: wgpu::TextureFormat::Rgba16Float,
+            sample_count: 1,
+            usage: wgpu::TextureUsages::empty(),
+        }
+    }
+
+    fn pass(
+        builder: &mut RenderGraphBuilder<()>,
+        name: &'static str,
+        reads: &[TextureId],
+        writes: &[TextureId],
+    ) {
+        builder.pass(name, reads, writes, |_, _, _| {});
+    }
+
+    #[test]
+    fn orders_passes_by_the_textures_they_use() {
+        let mut builder = RenderGraphBuilder::new();
+        let hdr = builder.texture(desc("hdr"));
+        let depth = builder.texture(desc("depth"));
+        let output = builder.output();
+        // added against the order they have to run in
+        pass(&mut builder, "tonemap", &[hdr], &[output]);
+        pass(&mut builder, "atmosphere", &[hdr, depth], &[hdr]);
+        pass(&mut builder, "bloom", &[hdr], &[hdr]);
+        pass(&mut builder, "earth", &[], &[hdr, depth]);
+
+        let graph = builder.build().unwrap();
+        assert_eq!(
+            graph.pass_names(),
+            ["earth", "atmosphere", "bloom", "tonemap"]
+        );
+    }
+
+    #[test]
+    fn writers_run_before_modifiers_and_readers() {
+        let mut builder = RenderGraphBuilder::new();
+        let hdr = builder.texture(desc("hdr"));
+        let output = builder.output();
+        pass(&mut builder, "read", &[hdr], &[output]);
+        pass(&mut builder, "modify", &[hdr], &[hdr]);
+        pass(&mut builder, "write", &[], &[hdr]);
+        pass(&mut builder, "overwrite", &[], &[hdr]);
+
+        let graph = builder.build().unwrap();
+        assert_eq!(graph.pass_names(), ["write", "overwrite", "modify", "read"]);
+    }
+
+    #[test]
+    fn independent_passes_keep_their_order() {
+        let mut builder = RenderGraphBuilder::new();
+        let a = builder.texture(desc("a"));
+        let b = builder.texture(desc("b"));
+        pass(&mut builder, "second", &[], &[b]);
+        pass(&mut builder, "first", &[], &[a]);
+
+        let graph = builder.build().unwrap();
+        assert_eq!(graph.pass_names(), ["second", "first"]);
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let mut builder = RenderGraphBuilder::new();
+        let a = builder.texture(desc("a"));
+        let b = builder.texture(desc("b"));
+        let c = builder.texture(desc("c"));
+        pass(&mut builder, "independent", &[], &[c]);
+        pass(&mut builder, "ping", &[b], &[a]);
+        pass(&mut builder, "pong", &[a], &[b]);
+
+        let error = builder.build().err().unwrap();
+        assert_eq!(error, GraphError::Cycle(vec!["ping", "pong"]));
+    }
+
+    #[test]
+    fn rejects_reading_a_texture_nobody_writes() {
+        let mut builder = RenderGraphBuilder::new();
+        let hdr = builder.texture(desc("hdr"));
+        let bloom = builder.texture(desc("bloom"));
+        let output = builder.output();
+        pass(&mut builder, "earth", &[], &[hdr]);
+        pass(&mut builder, "tonemap", &[hdr, bloom], &[output]);
+
+        let error = builder.build().err().unwrap();
+        assert_eq!(
+            error,
+            GraphError::NotWritten {
+                pass: "tonemap",
+                texture: "bloom",
+            }
+        );
+    }
+
+    #[test]
+    fn modifying_needs_a_writer_too() {
+        let mut builder = RenderGraphBuilder::<()>::new();
+        let output = builder.output();
+        pass(&mut builder, "overlay", &[output], &[output]);
+
+        let error = builder.build().err().unwrap();
+        assert_eq!(
+            error,
+            GraphError::NotWritten {
+                pass: "overlay",
+                texture: "output",
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Pass overlay reads output, which no pass writes"
+        );
+    }
+}
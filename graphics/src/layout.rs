@@ -0,0 +1,344 @@
+// The Rust structs uploaded as uniforms mirror WGSL structs. `uniform_layout!` records
+// their layout, and the tests compare it with the WGSL structs reflected by naga.
+
+/// A field of a uniform struct; its type is spelled the Rust way on both sides,
+/// so `vec3<f32>` is `[f32; 3]`.
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FieldLayout {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+    pub ty: String,
+}
+
+#[cfg(test)]
+impl FieldLayout {
+    pub(crate) fn of<T, F>(name: &str, offset: usize, _field: impl Fn(&T) -> &F) -> Self {
+        Self {
+            name: name.to_string(),
+            offset: offset as u32,
+            size: std::mem::size_of::<F>() as u32,
+            ty: std::any::type_name::<F>().to_string(),
+        }
+    }
+
+    /// Fields named with a leading underscore only fill alignment gaps.
+    pub fn is_padding(&self) -> bool {
+        self.name.starts_with('_')
+    }
+}
+
+/// Size and fields of a uniform struct, in bytes.
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StructLayout {
+    pub name: String,
+    pub size: u32,
+    pub fields: Vec<FieldLayout>,
+}
+
+/// A `#[repr(C)]` struct uploaded as a uniform, mirroring a WGSL struct.
+/// Implemented with `uniform_layout!`.
+#[cfg(test)]
+pub(crate) trait Uniform: bytemuck::Pod {
+    /// Shader of the `ShaderLibrary` that declares the WGSL struct
+    const SHADER: &'static str;
+    const WGSL_NAME: &'static str;
+
+    fn layout() -> StructLayout;
+}
+
+/// Declares the WGSL struct that a uniform struct mirrors, listing all of its fields:
+/// `uniform_layout!(CameraUniform = "CameraUniform" in "include/camera.wgsl" { view_pos, ... })`
+macro_rules! uniform_layout {
+    ($ty:ident = $wgsl:literal in $shader:literal { $($field:ident),* $(,)? }) => {
+        #[cfg(test)]
+        impl $crate::layout::Uniform for $ty {
+            const SHADER: &'static str = $shader;
+            const WGSL_NAME: &'static str = $wgsl;
+
+            fn layout() -> $crate::layout::StructLayout {
+                $crate::layout::StructLayout {
+                    name: stringify!($ty).to_string(),
+                    size: std::mem::size_of::<$ty>() as u32,
+                    fields: vec![$(
+                        $crate::layout::FieldLayout::of(
+                            stringify!($field),
+                            std::mem::offset_of!($ty, $field),
+                            |uniform: &$ty| &uniform.$field,
+                        )
+                    ),*],
+                }
+            }
+        }
+    };
+}
+pub(crate) use uniform_layout;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bloom::BloomUniform,
+        camera::CameraUniform,
+        earth::{material::MaterialUniform, property::EarthUniform},
+        shader::{Shader, ShaderLibrary},
+        sun::property::SunUniform,
+        tonemap::{HistogramParams, SettingsUniform},
+    };
+
+    impl StructLayout {
+        // A `#[repr(C)]` Rust struct with this layout, with explicit padding fields
+        fn to_rust(&self) -> String {
+            let mut rust = format!(
+                "#[repr(C)]\n#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]\npub struct {} {{\n",
+                self.name
+            );
+            let mut offset = 0;
+            let mut paddings = 0;
+            let mut pad = |rust: &mut String, from: u32, to: u32| {
+                if to > from {
+                    let ty = match to - from {
+                        4 => "u32".to_string(),
+                        gap if gap % 4 == 0 => format!("[u32; {}]", gap / 4),
+                        gap => format!("[u8; {}]", gap),
+                    };
+                    rust.push_str(&format!("    _padding{}: {},\n", paddings, ty));
+                    paddings += 1;
+                }
+            };
+            for field in &self.fields {
+                pad(&mut rust, offset, field.offset);
+                rust.push_str(&format!("    {}: {},\n", field.name, field.ty));
+                offset = field.offset + field.size;
+            }
+            pad(&mut rust, offset, self.size);
+            rust.push('}');
+            rust
+        }
+    }
+
+    // A Rust uniform and the WGSL struct it mirrors
+    struct UniformCheck {
+        shader: &'static str,
+        rust: StructLayout,
+        wgsl: StructLayout,
+    }
+
+    impl UniformCheck {
+        // What does not match; padding fields only have to keep the other fields in place
+        fn differences(&self) -> Vec<String> {
+            let mut differences = Vec::new();
+            if self.rust.size != self.wgsl.size {
+                differences.push(format!(
+                    "size is {} bytes in Rust and {} in WGSL",
+                    self.rust.size, self.wgsl.size
+                ));
+            }
+            for wgsl in self.wgsl.fields.iter().filter(|field| !field.is_padding()) {
+                match self.rust.fields.iter().find(|rust| rust.name == wgsl.name) {
+                    None => differences.push(format!("`{}` is missing in Rust", wgsl.name)),
+                    Some(rust) if rust.offset != wgsl.offset => differences.push(format!(
+                        "`{}` is at offset {} in Rust and {} in WGSL",
+                        wgsl.name, rust.offset, wgsl.offset
+                    )),
+                    Some(rust) if rust.ty != wgsl.ty => differences.push(format!(
+                        "`{}` is `{}` in Rust and `{}` in WGSL",
+                        wgsl.name, rust.ty, wgsl.ty
+                    )),
+                    Some(_) => {}
+                }
+            }
+            for rust in self.rust.fields.iter().filter(|field| !field.is_padding()) {
+                if !self.wgsl.fields.iter().any(|wgsl| wgsl.name == rust.name) {
+                    differences.push(format!("`{}` is missing in WGSL", rust.name));
+                }
+            }
+            differences
+        }
+    }
+
+    // Reflects the WGSL struct of `U` with naga
+    fn check<U: Uniform>(library: &ShaderLibrary) -> UniformCheck {
+        let source = library
+            .preprocess(&Shader::new(U::SHADER))
+            .unwrap_or_else(|err| panic!("{}: {}", U::SHADER, err));
+        let module = naga::front::wgsl::parse_str(&source)
+            .unwrap_or_else(|err| panic!("{}: {}", U::SHADER, err.emit_to_string(&source)));
+        let mut layouter = naga::proc::Layouter::default();
+        layouter
+            .update(module.to_ctx())
+            .unwrap_or_else(|err| panic!("{}: {}", U::SHADER, err));
+
+        let members = module.types.iter().find_map(|(_, ty)| match &ty.inner {
+            naga::TypeInner::Struct { members, span }
+                if ty.name.as_deref() == Some(U::WGSL_NAME) =>
+            {
+                Some((members, *span))
+            }
+            _ => None,
+        });
+        let Some((members, span)) = members else {
+            panic!("{} does not declare struct {}", U::SHADER, U::WGSL_NAME);
+        };
+
+        UniformCheck {
+            shader: U::SHADER,
+            rust: U::layout(),
+            wgsl: StructLayout {
+                name: U::WGSL_NAME.to_string(),
+                size: span,
+                fields: members
+                    .iter()
+                    .map(|member| FieldLayout {
+                        name: member.name.clone().unwrap_or_default(),
+                        offset: member.offset,
+                        size: layouter[member.ty].size,
+                        ty: rust_type(&module, &layouter, member.ty),
+                    })
+                    .collect(),
+            },
+        }
+    }
+
+    // The Rust type with the size and alignment WGSL gives `ty` in a uniform buffer
+    fn rust_type(
+        module: &naga::Module,
+        layouter: &naga::proc::Layouter,
+        ty: naga::Handle<naga::Type>,
+    ) -> String {
+        match &module.types[ty].inner {
+            naga::TypeInner::Scalar(scalar) | naga::TypeInner::Atomic(scalar) => {
+                scalar_type(*scalar)
+            }
+            naga::TypeInner::Vector { size, scalar } => {
+                format!("[{}; {}]", scalar_type(*scalar), *size as u8)
+            }
+            naga::TypeInner::Matrix {
+                columns,
+                rows,
+                scalar,
+            } => {
+                // columns of three are aligned to four
+                let rows = match rows {
+                    naga::VectorSize::Tri => 4,
+                    rows => *rows as u8,
+                };
+                format!("[[{}; {}]; {}]", scalar_type(*scalar), rows, *columns as u8)
+            }
+            naga::TypeInner::Array {
+                base,
+                size: naga::ArraySize::Constant(count),
+                ..
+            } => format!("[{}; {}]", rust_type(module, layouter, *base), count),
+            naga::TypeInner::Struct { .. } if module.types[ty].name.is_some() => {
+                module.types[ty].name.clone().unwrap_or_default()
+            }
+            _ => format!("[u8; {}]", layouter[ty].size),
+        }
+    }
+
+    fn scalar_type(scalar: naga::Scalar) -> String {
+        match scalar.kind {
+            naga::ScalarKind::Float => format!("f{}", scalar.width * 8),
+            naga::ScalarKind::Sint => format!("i{}", scalar.width * 8),
+            _ => format!("u{}", scalar.width * 8),
+        }
+    }
+
+    fn field(name: &str, offset: u32, ty: &str) -> FieldLayout {
+        FieldLayout {
+            name: name.to_string(),
+            offset,
+            size: 4,
+            ty: ty.to_string(),
+        }
+    }
+
+    #[test]
+    fn uniforms_match_wgsl_structs() {
+        let library = ShaderLibrary::default();
+        let checks = [
+            check::<CameraUniform>(&library),
+            check::<SunUniform>(&library),
+            check::<EarthUniform>(&library),
+            check::<MaterialUniform>(&library),
+            check::<BloomUniform>(&library),
+            check::<SettingsUniform>(&library),
+            check::<HistogramParams>(&library),
+        ];
+
+        let mut mismatches = String::new();
+        for check in checks {
+            let differences = check.differences();
+            if !differences.is_empty() {
+                mismatches.push_str(&format!(
+                    "{} does not match {} of {}:\n    {}\nexpected:\n{}\n",
+                    check.rust.name,
+                    check.wgsl.name,
+                    check.shader,
+                    differences.join("\n    "),
+                    check.wgsl.to_rust()
+                ));
+            }
+        }
+        assert!(mismatches.is_empty(), "{}", mismatches);
+    }
+
+    #[test]
+    fn reports_differences_but_not_padding() {
+        let check = UniformCheck {
+            shader: "test.wgsl",
+            rust: StructLayout {
+                name: "Rust".to_string(),
+                size: 12,
+                fields: vec![
+                    field("a", 0, "f32"),
+                    field("_padding", 4, "u32"),
+                    field("b", 8, "u32"),
+                ],
+            },
+            wgsl: StructLayout {
+                name: "Wgsl".to_string(),
+                size: 16,
+                fields: vec![
+                    field("a", 0, "f32"),
+                    field("b", 4, "u32"),
+                    field("c", 8, "f32"),
+                ],
+            },
+        };
+        assert_eq!(
+            check.differences(),
+            [
+                "size is 12 bytes in Rust and 16 in WGSL",
+                "`b` is at offset 8 in Rust and 4 in WGSL",
+                "`c` is missing in Rust",
+            ]
+        );
+    }
+
+    #[test]
+    fn generates_padding_for_gaps() {
+        let layout = StructLayout {
+            name: "Settings".to_string(),
+            size: 32,
+            fields: vec![
+                FieldLayout {
+                    name: "position".to_string(),
+                    offset: 0,
+                    size: 12,
+                    ty: "[f32; 3]".to_string(),
+                },
+                field("intensity", 16, "f32"),
+            ],
+        };
+        assert_eq!(
+            layout.to_rust(),
+            "#[repr(C)]\n#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]\n\
+             pub struct Settings {\n    position: [f32; 3],\n    _padding0: u32,\n    \
+             intensity: f32,\n    _padding1: [u32; 3],\n}"
+        );
+    }
+}
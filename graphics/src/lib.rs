@@ -1,33 +1,120 @@
+#[cfg(feature = "web")]
 use log::error;
-use state::State;
+#[cfg(feature = "web")]
 use wasm_bindgen::prelude::*;
 
+pub use action::{Action, ActionMap, Binding, InputProfile, Modifier, ProfileError};
+pub use bloom::BloomSettings;
+pub use camera::controller::{CameraMode, OrbitView};
+pub use earth::{
+    model::EarthGeometry,
+    sphere::SphereKind,
+    texture::{TextureCompression, TextureOptions},
+};
+pub use error::GraphicsError;
+pub use gamepad::{AxisBinding, AxisResponse, ButtonBinding, GamepadState};
+pub use offscreen::OffscreenRenderer;
+pub use resource::{Allocation, MemoryReport, ResourceKind};
+pub use scene::SceneOptions;
+pub use state::{DeviceEvent, State};
+pub use tonemap::{Exposure, Tonemapper};
+
+#[cfg(feature = "web")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+mod action;
+pub mod asset;
+mod bloom;
 mod camera;
 mod earth;
-mod fetch;
-mod key;
+mod error;
+mod gamepad;
+mod gesture;
+mod graph;
+mod layout;
+mod offscreen;
+mod pipeline;
+mod resource;
+mod scene;
+mod shader;
 mod star;
 mod state;
 mod sun;
+#[cfg(test)]
+mod testing;
+mod tonemap;
 
+#[cfg(feature = "web")]
 #[wasm_bindgen(start)]
 fn start() {
     wasm_logger::init(wasm_logger::Config::default());
 }
 
+/// `on_progress(path, received, total)` is called while the resources are downloaded,
+/// where `total` is `undefined` if the size is unknown, and again while `State::restore`
+/// fetches them for a new device. Aborting `signal` cancels the loading.
+///
+/// Rejects with an `Error` whose `code` is one of the codes of `GraphicsError`,
+/// e.g. `adapter-unavailable` or `asset-fetch-failed`.
+#[cfg(feature = "web")]
 #[wasm_bindgen]
 pub async fn create_state(
     canvas: web_sys::HtmlCanvasElement,
     use_gl_instead: bool,
-) -> Option<State> {
-    match State::new(canvas, use_gl_instead).await {
-        Ok(state) => Some(state),
-        Err(e) => {
-            error!("Failed to create state: {:?}", e);
-            None
+    on_progress: Option<js_sys::Function>,
+    signal: Option<web_sys::AbortSignal>,
+) -> Result<State, JsValue> {
+    let width = canvas.width();
+    let height = canvas.height();
+    let href = web_sys::window().unwrap().location().href().unwrap();
+    let source = asset::HttpSource::new(&href).map_err(|e| {
+        error!("Failed to create state: {:?}", e);
+        GraphicsError::fetch(&href, e)
+    })?;
+
+    let cancel = asset::CancelToken::new();
+    let on_abort = Closure::<dyn FnMut()>::new({
+        let cancel = cancel.clone();
+        move || cancel.cancel()
+    });
+    if let Some(signal) = &signal {
+        if signal.aborted() {
+            cancel.cancel();
         }
+        let _ = signal.add_event_listener_with_callback("abort", on_abort.as_ref().unchecked_ref());
+    }
+
+    let mut loader = asset::Loader::new(source).with_cancel_token(cancel);
+    if let Some(on_progress) = on_progress {
+        loader = loader.on_progress(move |path, received, total| {
+            let total = total.map_or(JsValue::UNDEFINED, |total| JsValue::from(total as f64));
+            let _ = on_progress.call3(
+                &JsValue::NULL,
+                &JsValue::from(path),
+                &JsValue::from(received as f64),
+                &total,
+            );
+        });
     }
+
+    let result = State::new(
+        wgpu::SurfaceTarget::Canvas(canvas),
+        width,
+        height,
+        use_gl_instead,
+        loader,
+        &SceneOptions::default(),
+    )
+    .await;
+
+    if let Some(signal) = &signal {
+        let _ =
+            signal.remove_event_listener_with_callback("abort", on_abort.as_ref().unchecked_ref());
+    }
+
+    result.map_err(|e| {
+        error!("Failed to create state: {}", e);
+        e.into()
+    })
 }
@@ -0,0 +1,359 @@
+use std::path::Path;
+
+use anyhow::{bail, Context};
+
+use crate::{
+    asset::AssetSource,
+    bloom::BloomSettings,
+    camera::controller::{CameraMode, OrbitView},
+    earth::texture::compression_features,
+    error::GraphicsError,
+    resource::MemoryReport,
+    scene::{clamp_sample_count, supported_sample_counts, Scene, SceneOptions},
+    tonemap::{hdr_format, Exposure, Tonemapper, LDR_FORMAT},
+};
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Renders the scene into a texture instead of a window or canvas,
+/// and reads the frames back to the CPU.
+pub struct OffscreenRenderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    texture: wgpu::Texture,
+    sample_counts: Vec<u32>,
+    scene: Scene,
+}
+
+impl OffscreenRenderer {
+    /// Set `force_fallback_adapter` to render with a software adapter.
+    pub async fn new(
+        width: u32,
+        height: u32,
+        force_fallback_adapter: bool,
+        source: &dyn AssetSource,
+        options: &SceneOptions,
+    ) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter,
+            })
+            .await
+            .ok_or(GraphicsError::AdapterUnavailable)?;
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    required_features: compression_features(adapter.features()),
+                    required_limits: wgpu::Limits::downlevel_defaults()
+                        .using_resolution(adapter.limits()),
+                    label: None,
+                    memory_hints: wgpu::MemoryHints::default(),
+                },
+                None,
+            )
+            .await?;
+
+        let texture = create_target(&device, width, height)?;
+        let sample_counts = supported_sample_counts(&adapter);
+        let options = SceneOptions {
+            sample_count: clamp_sample_count(options.sample_count, &sample_counts),
+            hdr_format: hdr_format(&adapter),
+            ..options.clone()
+        };
+        let scene = Scene::new(&device, &queue, FORMAT, width, height, source, &options).await?;
+
+        Ok(Self {
+            device,
+            queue,
+            texture,
+            sample_counts,
+            scene,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.texture.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.texture.height()
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) -> anyhow::Result<()> {
+        self.texture = create_target(&self.device, width, height)?;
+        self.scene.resize(&self.device, width, height);
+        Ok(())
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.scene.sample_count()
+    }
+
+    /// Returns the sample count actually used; see `State::set_sample_count`.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> u32 {
+        let sample_count = clamp_sample_count(sample_count, &self.sample_counts);
+        self.scene.set_sample_count(&self.device, sample_count);
+        sample_count
+    }
+
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        self.scene.set_camera_mode(mode);
+    }
+
+    pub fn fly_to(&mut self, view: OrbitView) {
+        self.scene.fly_to(view);
+    }
+
+    pub fn set_tonemapper(&mut self, tonemapper: Tonemapper) {
+        self.scene.set_tonemapper(tonemapper);
+    }
+
+    /// Returns the exposure actually used; see `State::set_auto_exposure`.
+    pub fn set_exposure(&mut self, exposure: Exposure) -> Exposure {
+        self.scene.set_exposure(exposure);
+        self.scene.exposure()
+    }
+
+    pub fn bloom(&self) -> BloomSettings {
+        self.scene.bloom()
+    }
+
+    pub fn set_bloom(&mut self, bloom: BloomSettings) {
+        self.scene.set_bloom(bloom);
+    }
+
+    /// Replaces a shader such as `atmosphere.wgsl`, rebuilding the pipelines it changes;
+    /// returns how many were rebuilt. The previous pipelines are kept if it does not compile.
+    #[cfg(feature = "hot-reload")]
+    pub async fn set_shader_source(&mut self, path: &str, source: &str) -> anyhow::Result<usize> {
+        let sources = [(path.to_string(), source.to_string())];
+        Ok(self
+            .scene
+            .set_shader_sources(&self.device, &sources)
+            .await?)
+    }
+
+    pub fn memory_report(&self) -> MemoryReport {
+        self.scene.memory_report()
+    }
+
+    /// Advances the scene by a 60th of a second, the rate of the interactive frontends.
+    pub fn update(&mut self) {
+        self.scene.update(&self.queue, 1.0 / 60.0);
+    }
+
+    /// Draws a frame and waits until its pixels are copied back.
+    pub async fn render(&self) -> anyhow::Result<image::RgbaImage> {
+        let pixels = self.draw_and_read(&self.texture).await?;
+        image::RgbaImage::from_raw(self.width(), self.height(), pixels)
+            .context("Frame does not match the target size")
+    }
+
+    /// Draws a frame and reads back its linear HDR values, before exposure and tone mapping.
+    /// Where the adapter falls back to `LDR_FORMAT`, the values are clamped to [0, 1].
+    pub async fn render_hdr(&self) -> anyhow::Result<image::Rgba32FImage> {
+        let texture = self.scene.hdr_texture();
+        let pixels = self.draw_and_read(texture).await?;
+        let values = if texture.format() == LDR_FORMAT {
+            pixels.iter().map(|&byte| byte as f32 / 255.0).collect()
+        } else {
+            pixels
+                .chunks_exact(2)
+                .map(|bytes| half::f16::from_le_bytes([bytes[0], bytes[1]]).to_f32())
+                .collect()
+        };
+        image::Rgba32FImage::from_raw(self.width(), self.height(), values)
+            .context("HDR frame does not match the target size")
+    }
+
+    /// Draws a frame and saves it. The file format follows the extension of `path`;
+    /// `.exr` frames hold the HDR values of `render_hdr`, other formats the tone mapped frame.
+    pub async fn render_to_file(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let is_exr = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("exr"));
+        if is_exr {
+            self.render_hdr().await?.save(path)?;
+        } else {
+            self.render().await?.save(path)?;
+        }
+        Ok(())
+    }
+
+    // Draws a frame and copies `texture`, the target or a texture of the scene, back
+    // without the row padding
+    async fn draw_and_read(&self, texture: &wgpu::Texture) -> anyhow::Result<Vec<u8>> {
+        let (width, height) = (texture.width(), texture.height());
+        let bytes_per_pixel = texture
+            .format()
+            .block_copy_size(None)
+            .context("Texture cannot be copied")?;
+        let unpadded_bytes_per_row = bytes_per_pixel * width;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("offscreen_readback_buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let view = self
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Offscreen Encoder"),
+            });
+        self.scene.render(&mut encoder, &view);
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.await??;
+
+        let pixels = {
+            let data = slice.get_mapped_range();
+            data.chunks(padded_bytes_per_row as usize)
+                .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        buffer.unmap();
+        Ok(pixels)
+    }
+}
+
+fn create_target(device: &wgpu::Device, width: u32, height: u32) -> anyhow::Result<wgpu::Texture> {
+    let max = device.limits().max_texture_dimension_2d;
+    if width == 0 || height == 0 || width > max || height > max {
+        bail!("Unsupported resolution {}x{} (max {})", width, height, max);
+    }
+
+    Ok(device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("offscreen_target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::testing::earth_resources;
+
+    // None without an adapter, e.g. on CI machines without a GPU or software renderer
+    fn renderer(width: u32, height: u32) -> Option<OffscreenRenderer> {
+        let options = SceneOptions {
+            sample_count: 1,
+            ..SceneOptions::default()
+        };
+        match block_on(OffscreenRenderer::new(
+            width,
+            height,
+            false,
+            &earth_resources(),
+            &options,
+        )) {
+            Ok(renderer) => Some(renderer),
+            Err(e) if matches!(e.downcast_ref(), Some(GraphicsError::AdapterUnavailable)) => {
+                eprintln!("Skipping, no adapter: {}", e);
+                None
+            }
+            Err(e) => panic!("Failed to create the renderer: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn renders_the_earth_against_the_sky() {
+        let Some(renderer) = renderer(64, 48) else {
+            return;
+        };
+        let frame = block_on(renderer.render()).unwrap();
+        assert_eq!(frame.dimensions(), (64, 48));
+
+        let luminance =
+            |pixel: &image::Rgba<u8>| pixel.0[..3].iter().map(|&c| c as u32).sum::<u32>();
+        // the lit side of the Earth is in the middle, the corners show the dark sky
+        let earth = (16..48)
+            .flat_map(|x| (12..36).map(move |y| (x, y)))
+            .map(|(x, y)| luminance(frame.get_pixel(x, y)))
+            .max()
+            .unwrap();
+        let sky = luminance(frame.get_pixel(0, 0));
+        assert!(earth > sky + 150, "earth {}, sky {}", earth, sky);
+    }
+
+    #[test]
+    fn exr_holds_the_hdr_frame() {
+        let Some(renderer) = renderer(32, 24) else {
+            return;
+        };
+        let hdr = block_on(renderer.render_hdr()).unwrap();
+        assert_eq!(hdr.dimensions(), (32, 24));
+        assert!(hdr
+            .pixels()
+            .flat_map(|p| p.0)
+            .all(|c| c.is_finite() && c >= 0.0));
+        // not the 8-bit frame converted to floats
+        assert!(hdr
+            .pixels()
+            .flat_map(|p| p.0[..3].to_vec())
+            .any(|c| (c * 255.0 - (c * 255.0).round()).abs() > 0.01));
+
+        let path = std::env::temp_dir().join(format!("graphics-test-{}.exr", std::process::id()));
+        block_on(renderer.render_to_file(&path)).unwrap();
+        let saved = image::open(&path).unwrap().into_rgba32f();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(saved.dimensions(), hdr.dimensions());
+        for (saved, rendered) in saved.pixels().zip(hdr.pixels()) {
+            for (a, b) in saved.0[..3].iter().zip(&rendered.0[..3]) {
+                assert!((a - b).abs() <= b.abs() * 1e-3 + 1e-4, "{} != {}", a, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,551 @@
+use std::collections::HashMap;
+#[cfg(feature = "hot-reload")]
+use std::collections::HashSet;
+
+#[cfg(feature = "hot-reload")]
+use futures::future::{self, FutureExt, LocalBoxFuture};
+
+use crate::shader::{Shader, ShaderError, ShaderLibrary};
+
+/// How a material is depth tested against the depth target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DepthTest {
+    pub compare: wgpu::CompareFunction,
+    pub write: bool,
+}
+
+/// What a layer draws with. The vertex entry point of the shader is `vs_main`.
+pub struct MaterialDesc<'a> {
+    pub shader: Shader,
+    /// Fragment entry point, usually `fs_main`
+    pub fragment_entry: &'static str,
+    pub bind_group_layouts: &'a [&'a wgpu::BindGroupLayout],
+    pub vertex_buffers: &'a [wgpu::VertexBufferLayout<'static>],
+    pub blend: wgpu::BlendState,
+    pub depth_test: Option<DepthTest>,
+    pub cull_mode: Option<wgpu::Face>,
+    /// Fixed targets, e.g. of a post-processing pass; `None` draws into the targets
+    /// of the cache and follows `PipelineCache::set_targets`
+    pub targets: Option<RenderTargets>,
+}
+
+/// Formats and sample count of the targets a pipeline draws into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RenderTargets {
+    pub format: wgpu::TextureFormat,
+    pub depth_format: Option<wgpu::TextureFormat>,
+    pub sample_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MaterialId(usize);
+
+/// A compute shader entry point and the bind group layouts it is dispatched with.
+pub struct ComputeDesc<'a> {
+    pub shader: Shader,
+    pub entry_point: &'static str,
+    pub bind_group_layouts: &'a [&'a wgpu::BindGroupLayout],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ComputeId(usize);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ComputeKey {
+    shader: Shader,
+    entry_point: &'static str,
+    bind_group_layouts: Vec<wgpu::Id<wgpu::BindGroupLayout>>,
+}
+
+// The key and layout are only needed to rebuild the pipeline when its shader changes
+#[cfg_attr(not(feature = "hot-reload"), allow(dead_code))]
+struct Compute {
+    key: ComputeKey,
+    layout: wgpu::PipelineLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct MaterialKey {
+    shader: Shader,
+    fragment_entry: &'static str,
+    bind_group_layouts: Vec<wgpu::Id<wgpu::BindGroupLayout>>,
+    vertex_buffers: Vec<wgpu::VertexBufferLayout<'static>>,
+    blend: wgpu::BlendState,
+    depth_test: Option<DepthTest>,
+    cull_mode: Option<wgpu::Face>,
+    targets: Option<RenderTargets>,
+}
+
+struct Material {
+    key: MaterialKey,
+    layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+/// Builds a render pipeline for each registered material and rebuilds those drawing
+/// into the targets of the cache when they change. Compute pipelines are cached the
+/// same way, so that both are rebuilt when their shaders change.
+pub struct PipelineCache {
+    targets: RenderTargets,
+    library: ShaderLibrary,
+    // modules compiled once per shader variant
+    shaders: HashMap<Shader, wgpu::ShaderModule>,
+    materials: Vec<Material>,
+    ids: HashMap<MaterialKey, MaterialId>,
+    computes: Vec<Compute>,
+    compute_ids: HashMap<ComputeKey, ComputeId>,
+    // variants whose modules are older than the library, as their last change did not compile
+    #[cfg(feature = "hot-reload")]
+    stale: HashSet<Shader>,
+}
+
+impl PipelineCache {
+    pub fn new(targets: RenderTargets, library: ShaderLibrary) -> Self {
+        Self {
+            targets,
+            library,
+            shaders: HashMap::new(),
+            materials: Vec::new(),
+            ids: HashMap::new(),
+            computes: Vec::new(),
+            compute_ids: HashMap::new(),
+            #[cfg(feature = "hot-reload")]
+            stale: HashSet::new(),
+        }
+    }
+
+    pub fn targets(&self) -> RenderTargets {
+        self.targets
+    }
+
+    /// Returns the already registered material if the shader, the bind group layout
+    /// objects, vertex layouts and states are the same.
+    /// Compilation errors are reported to the error scopes of `device`.
+    pub fn register(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &MaterialDesc,
+    ) -> Result<MaterialId, ShaderError> {
+        let key = MaterialKey {
+            shader: desc.shader.clone(),
+            fragment_entry: desc.fragment_entry,
+            bind_group_layouts: desc
+                .bind_group_layouts
+                .iter()
+                .map(|layout| layout.global_id())
+                .collect(),
+            vertex_buffers: desc.vertex_buffers.to_vec(),
+            blend: desc.blend,
+            depth_test: desc.depth_test,
+            cull_mode: desc.cull_mode,
+            targets: desc.targets,
+        };
+        if let Some(&id) = self.ids.get(&key) {
+            return Ok(id);
+        }
+        self.compile(device, &key.shader)?;
+
+        let label = format!("{}_render_pipeline_layout", desc.shader.name());
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&label),
+            bind_group_layouts: desc.bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(
+            device,
+            &self.shaders[&key.shader],
+            self.targets,
+            &key,
+            &layout,
+        );
+
+        let id = MaterialId(self.materials.len());
+        self.materials.push(Material {
+            key: key.clone(),
+            layout,
+            pipeline,
+        });
+        self.ids.insert(key, id);
+        Ok(id)
+    }
+
+    pub fn pipeline(&self, id: MaterialId) -> &wgpu::RenderPipeline {
+        &self.materials[id.0].pipeline
+    }
+
+    /// Returns the already registered compute pipeline if the shader, entry point and
+    /// bind group layout objects are the same.
+    /// Compilation errors are reported to the error scopes of `device`.
+    pub fn register_compute(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &ComputeDesc,
+    ) -> Result<ComputeId, ShaderError> {
+        let key = ComputeKey {
+            shader: desc.shader.clone(),
+            entry_point: desc.entry_point,
+            bind_group_layouts: desc
+                .bind_group_layouts
+                .iter()
+                .map(|layout| layout.global_id())
+                .collect(),
+        };
+        if let Some(&id) = self.compute_ids.get(&key) {
+            return Ok(id);
+        }
+        self.compile(device, &key.shader)?;
+
+        let label = format!("{}_compute_pipeline_layout", desc.shader.name());
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&label),
+            bind_group_layouts: desc.bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_compute_pipeline(device, &self.shaders[&key.shader], &key, &layout);
+
+        let id = ComputeId(self.computes.len());
+        self.computes.push(Compute {
+            key: key.clone(),
+            layout,
+            pipeline,
+        });
+        self.compute_ids.insert(key, id);
+        Ok(id)
+    }
+
+    pub fn compute_pipeline(&self, id: ComputeId) -> &wgpu::ComputePipeline {
+        &self.computes[id.0].pipeline
+    }
+
+    // Compiles the module of `shader` unless it already is
+    fn compile(&mut self, device: &wgpu::Device, shader: &Shader) -> Result<(), ShaderError> {
+        if !self.shaders.contains_key(shader) {
+            let source = self.library.preprocess(shader)?;
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(shader.name()),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            self.shaders.insert(shader.clone(), module);
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the pipelines without fixed targets for the new ones; material ids stay valid.
+    pub fn set_targets(&mut self, device: &wgpu::Device, targets: RenderTargets) {
+        if targets == self.targets {
+            return;
+        }
+        self.targets = targets;
+        for material in self
+            .materials
+            .iter_mut()
+            .filter(|m| m.key.targets.is_none())
+        {
+            material.pipeline = create_pipeline(
+                device,
+                &self.shaders[&material.key.shader],
+                targets,
+                &material.key,
+                &material.layout,
+            );
+        }
+    }
+
+    #[cfg(feature = "hot-reload")]
+    pub fn library(&self) -> &ShaderLibrary {
+        &self.library
+    }
+
+    /// Replaces sources of the shader library and rebuilds the pipelines of the variants
+    /// they change; returns how many were rebuilt. If they do not compile, the previous
+    /// pipelines are kept until a later change fixes them.
+    #[cfg(feature = "hot-reload")]
+    pub async fn set_shader_sources(
+        &mut self,
+        device: &wgpu::Device,
+        sources: &[(String, String)],
+    ) -> Result<usize, ShaderError> {
+        let mut rebuild = self.rebuild(device, sources)?;
+        let error = (&mut rebuild.validation).await;
+        self.swap_rebuilt(rebuild.shaders, rebuild.pipelines, rebuild.computes, error)
+    }
+
+    /// Same as `set_shader_sources` without waiting for the device: the new pipelines
+    /// are swapped in by `finish_rebuild` once the device has validated them. No other
+    /// rebuild may be started in between.
+    #[cfg(feature = "hot-reload")]
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        sources: &[(String, String)],
+    ) -> Result<PipelineRebuild, ShaderError> {
+        let previous = self.library.clone();
+        for (path, source) in sources {
+            self.library.set_source(path, source.clone())?;
+        }
+
+        let mut changed = HashMap::new();
+        let mut error = None;
+        for shader in self.shaders.keys() {
+            let source = self.library.preprocess(shader);
+            let previous = previous.preprocess(shader);
+            if self.stale.contains(shader) || source.as_ref().ok() != previous.as_ref().ok() {
+                match source {
+                    Ok(source) => {
+                        changed.insert(shader.clone(), source);
+                    }
+                    Err(err) => {
+                        self.stale.insert(shader.clone());
+                        error = Some(err);
+                    }
+                }
+            }
+        }
+        if let Some(err) = error {
+            self.stale.extend(changed.into_keys());
+            return Err(err);
+        }
+        if changed.is_empty() {
+            return Ok(PipelineRebuild {
+                shaders: HashMap::new(),
+                pipelines: Vec::new(),
+                computes: Vec::new(),
+                validation: future::ready(None).boxed_local(),
+            });
+        }
+
+        // Errors are caught by the scope instead of the uncaptured error handler, which panics
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shaders = changed
+            .into_iter()
+            .map(|(shader, source)| {
+                let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(shader.name()),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                });
+                (shader, module)
+            })
+            .collect::<HashMap<_, _>>();
+        let mut pipelines = Vec::new();
+        for (index, material) in self.materials.iter().enumerate() {
+            if let Some(shader) = shaders.get(&material.key.shader) {
+                let pipeline = create_pipeline(
+                    device,
+                    shader,
+                    self.targets,
+                    &material.key,
+                    &material.layout,
+                );
+                pipelines.push((index, pipeline));
+            }
+        }
+        let mut computes = Vec::new();
+        for (index, compute) in self.computes.iter().enumerate() {
+            if let Some(shader) = shaders.get(&compute.key.shader) {
+                let pipeline =
+                    create_compute_pipeline(device, shader, &compute.key, &compute.layout);
+                computes.push((index, pipeline));
+            }
+        }
+        Ok(PipelineRebuild {
+            shaders,
+            pipelines,
+            computes,
+            validation: device.pop_error_scope().boxed_local(),
+        })
+    }
+
+    /// The result of `rebuild` once the device has validated the new pipelines, or `None`
+    /// while it has not, without waiting; `rebuild` is done after it returned a result.
+    #[cfg(feature = "hot-reload")]
+    pub fn finish_rebuild(
+        &mut self,
+        rebuild: &mut PipelineRebuild,
+    ) -> Option<Result<usize, ShaderError>> {
+        let error = (&mut rebuild.validation).now_or_never()?;
+        Some(self.swap_rebuilt(
+            std::mem::take(&mut rebuild.shaders),
+            std::mem::take(&mut rebuild.pipelines),
+            std::mem::take(&mut rebuild.computes),
+            error,
+        ))
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn swap_rebuilt(
+        &mut self,
+        shaders: HashMap<Shader, wgpu::ShaderModule>,
+        pipelines: Vec<(usize, wgpu::RenderPipeline)>,
+        computes: Vec<(usize, wgpu::ComputePipeline)>,
+        error: Option<wgpu::Error>,
+    ) -> Result<usize, ShaderError> {
+        if let Some(error) = error {
+            self.stale.extend(shaders.into_keys());
+            return Err(ShaderError::Compile(error.to_string()));
+        }
+
+        for shader in shaders.keys() {
+            self.stale.remove(shader);
+        }
+        self.shaders.extend(shaders);
+        let rebuilt = pipelines.len() + computes.len();
+        for (index, pipeline) in pipelines {
+            self.materials[index].pipeline = pipeline;
+        }
+        for (index, pipeline) in computes {
+            self.computes[index].pipeline = pipeline;
+        }
+        Ok(rebuilt)
+    }
+}
+
+/// Pipelines built by `PipelineCache::rebuild` that the device has yet to validate.
+#[cfg(feature = "hot-reload")]
+pub struct PipelineRebuild {
+    shaders: HashMap<Shader, wgpu::ShaderModule>,
+    pipelines: Vec<(usize, wgpu::RenderPipeline)>,
+    computes: Vec<(usize, wgpu::ComputePipeline)>,
+    // the first validation error of the new modules and pipelines
+    validation: LocalBoxFuture<'static, Option<wgpu::Error>>,
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    targets: RenderTargets,
+    key: &MaterialKey,
+    layout: &wgpu::PipelineLayout,
+) -> wgpu::RenderPipeline {
+    let targets = key.targets.unwrap_or(targets);
+    let label = format!(
+        "{}_{}_render_pipeline",
+        key.shader.name(),
+        key.fragment_entry
+    );
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &key.vertex_buffers,
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: key.fragment_entry,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: targets.format,
+                blend: Some(key.blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            cull_mode: key.cull_mode,
+            ..Default::default()
+        },
+        depth_stencil: targets.depth_format.map(|format| {
+            let depth_test = key.depth_test.unwrap_or(DepthTest {
+                compare: wgpu::CompareFunction::Always,
+                write: false,
+            });
+            wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: depth_test.write,
+                depth_compare: depth_test.compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }
+        }),
+        multisample: wgpu::MultisampleState {
+            count: targets.sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+fn create_compute_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    key: &ComputeKey,
+    layout: &wgpu::PipelineLayout,
+) -> wgpu::ComputePipeline {
+    let label = format!("{}_{}_compute_pipeline", key.shader.name(), key.entry_point);
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(&label),
+        layout: Some(layout),
+        module: shader,
+        entry_point: key.entry_point,
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+        cache: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::star::StarInstanceRaw;
+
+    // A layout with the camera uniform, and a storage buffer the shader does not use
+    // if `extra`
+    fn camera_layout(device: &wgpu::Device, extra: bool) -> wgpu::BindGroupLayout {
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let mut entries = vec![entry(0, wgpu::BufferBindingType::Uniform)];
+        if extra {
+            entries.push(entry(
+                1,
+                wgpu::BufferBindingType::Storage { read_only: true },
+            ));
+        }
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &entries,
+        })
+    }
+
+    #[test]
+    fn materials_differing_in_layouts_are_separate() {
+        let Some((device, _queue)) = crate::testing::device() else {
+            return;
+        };
+        let mut cache = PipelineCache::new(
+            RenderTargets {
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                depth_format: None,
+                sample_count: 1,
+            },
+            ShaderLibrary::default(),
+        );
+        let (plain, extended) = (camera_layout(&device, false), camera_layout(&device, true));
+        let (plain, extended) = ([&plain], [&extended]);
+        let vertex_buffers = [StarInstanceRaw::desc()];
+        let desc = |bind_group_layouts| MaterialDesc {
+            shader: Shader::new("star.wgsl"),
+            fragment_entry: "fs_main",
+            bind_group_layouts,
+            vertex_buffers: &vertex_buffers,
+            blend: wgpu::BlendState::ALPHA_BLENDING,
+            depth_test: None,
+            cull_mode: None,
+            targets: None,
+        };
+
+        let first = cache.register(&device, &desc(&plain)).unwrap();
+        let second = cache.register(&device, &desc(&extended)).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(cache.register(&device, &desc(&plain)).unwrap(), first);
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, Weak},
+};
+
+use wgpu::util::DeviceExt;
+
+use crate::earth::mesh::MeshData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    Buffer,
+    Texture,
+}
+
+/// A GPU buffer or texture that is currently alive.
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    pub label: String,
+    pub kind: ResourceKind,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryReport {
+    pub allocations: Vec<Allocation>,
+}
+
+impl MemoryReport {
+    pub fn total_bytes(&self) -> u64 {
+        self.allocations.iter().map(|a| a.bytes).sum()
+    }
+
+    pub fn bytes_of(&self, kind: ResourceKind) -> u64 {
+        self.allocations
+            .iter()
+            .filter(|a| a.kind == kind)
+            .map(|a| a.bytes)
+            .sum()
+    }
+}
+
+#[derive(Default)]
+struct Allocations {
+    next_id: u64,
+    entries: BTreeMap<u64, Allocation>,
+}
+
+/// Keeps track of the GPU memory used by the resources registered to it.
+#[derive(Clone, Default)]
+pub struct MemoryTracker {
+    allocations: Arc<Mutex<Allocations>>,
+}
+
+impl MemoryTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The allocation is counted until the returned handle is dropped,
+    /// so keep it next to the resource.
+    pub fn track(&self, label: &str, kind: ResourceKind, bytes: u64) -> TrackedAllocation {
+        let mut allocations = self.allocations.lock().unwrap();
+        let id = allocations.next_id;
+        allocations.next_id += 1;
+        allocations.entries.insert(
+            id,
+            Allocation {
+                label: label.to_string(),
+                kind,
+                bytes,
+            },
+        );
+        TrackedAllocation {
+            id,
+            allocations: Arc::downgrade(&self.allocations),
+        }
+    }
+
+    pub fn report(&self) -> MemoryReport {
+        MemoryReport {
+            allocations: self
+                .allocations
+                .lock()
+                .unwrap()
+                .entries
+                .values()
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+pub struct TrackedAllocation {
+    id: u64,
+    allocations: Weak<Mutex<Allocations>>,
+}
+
+impl Drop for TrackedAllocation {
+    fn drop(&mut self) {
+        if let Some(allocations) = self.allocations.upgrade() {
+            allocations.lock().unwrap().entries.remove(&self.id);
+        }
+    }
+}
+
+/// Vertex and index buffers of a mesh, shared by every model drawing it.
+pub struct GpuMesh {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub num_elements: u32,
+    _allocations: [TrackedAllocation; 2],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MeshKey {
+    /// Where the mesh was loaded from, or any other name
+    Named(String),
+    /// Hash of the vertices and indices
+    Content(u64),
+}
+
+impl MeshKey {
+    pub fn from_content(mesh: &MeshData) -> Self {
+        let mut hasher = DefaultHasher::new();
+        bytemuck::cast_slice::<_, u8>(&mesh.vertices).hash(&mut hasher);
+        bytemuck::cast_slice::<_, u8>(&mesh.indices).hash(&mut hasher);
+        MeshKey::Content(hasher.finish())
+    }
+}
+
+/// Uploads each mesh once; a mesh is released when the last model using it is dropped.
+#[derive(Default)]
+pub struct MeshRegistry {
+    tracker: MemoryTracker,
+    meshes: HashMap<MeshKey, Weak<GpuMesh>>,
+}
+
+impl MeshRegistry {
+    pub fn new(tracker: MemoryTracker) -> Self {
+        Self {
+            tracker,
+            meshes: HashMap::new(),
+        }
+    }
+
+    pub fn tracker(&self) -> &MemoryTracker {
+        &self.tracker
+    }
+
+    pub fn get(&self, key: &MeshKey) -> Option<Arc<GpuMesh>> {
+        self.meshes.get(key).and_then(Weak::upgrade)
+    }
+
+    /// Returns the mesh registered as `key`, or uploads `mesh` under that key.
+    /// Use `MeshKey::from_content` to deduplicate meshes that have no name.
+    // wgpu resources are not `Send` on the web, but sharing them across threads is fine natively
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn insert(
+        &mut self,
+        device: &wgpu::Device,
+        key: MeshKey,
+        label: &str,
+        mesh: &MeshData,
+    ) -> Arc<GpuMesh> {
+        if let Some(mesh) = self.get(&key) {
+            return mesh;
+        }
+        self.meshes.retain(|_, mesh| mesh.strong_count() > 0);
+
+        let vertex_label = format!("{} vertex buffer", label);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&vertex_label),
+            contents: bytemuck::cast_slice(&mesh.vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_label = format!("{} index buffer", label);
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&index_label),
+            contents: bytemuck::cast_slice(&mesh.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let gpu_mesh = Arc::new(GpuMesh {
+            _allocations: [
+                self.tracker
+                    .track(&vertex_label, ResourceKind::Buffer, vertex_buffer.size()),
+                self.tracker
+                    .track(&index_label, ResourceKind::Buffer, index_buffer.size()),
+            ],
+            vertex_buffer,
+            index_buffer,
+            num_elements: mesh.indices.len() as u32,
+        });
+        self.meshes.insert(key, Arc::downgrade(&gpu_mesh));
+        gpu_mesh
+    }
+}
@@ -0,0 +1,867 @@
+#[cfg(feature = "hot-reload")]
+use crate::{
+    pipeline::PipelineRebuild,
+    shader::{ShaderWatcher, WatchSource},
+};
+use futures::future::LocalBoxFuture;
+
+use crate::{
+    asset::AssetSource,
+    bloom::{BloomPass, BloomSettings},
+    camera::{
+        controller::{CameraController, CameraMode, FreeFlyController, OrbitController, OrbitView},
+        geometry::CameraGeometry,
+        perspective::CameraPerspective,
+        Camera,
+    },
+    earth::{
+        model::{
+            create_earth_and_atmosphere_model, AtmosphereModel, DrawModel, EarthGeometry,
+            EARTH_TEXTURE_PATH,
+        },
+        property::EarthProperty,
+        texture::{TextureCompression, TextureOptions},
+        vertex::ModelVertex,
+        Earth,
+    },
+    error::GraphicsError,
+    graph::{RenderGraph, RenderGraphBuilder, TextureDesc, TextureId},
+    pipeline::{DepthTest, MaterialDesc, MaterialId, PipelineCache, RenderTargets},
+    resource::{MemoryReport, MemoryTracker, MeshRegistry},
+    shader::{Shader, ShaderError, ShaderLibrary},
+    star::{Star, StarInstanceRaw},
+    sun::{
+        property::{SunProperty, SunVertex},
+        Sun,
+    },
+    tonemap::{hdr_format, Exposure, TonemapPass, Tonemapper, HDR_FORMAT},
+};
+
+#[derive(Debug, Clone)]
+pub struct SceneOptions {
+    pub earth_geometry: EarthGeometry,
+    /// Used when the Earth model does not come with a base color texture
+    pub earth_texture: String,
+    pub earth_texture_options: TextureOptions,
+    /// KTX2 alternatives of `earth_texture`; the first one the device supports is used instead
+    pub earth_texture_variants: Vec<(TextureCompression, String)>,
+    /// MSAA sample count; clamped to what the adapter supports
+    pub sample_count: u32,
+    /// Format of the target the scene is rendered into before tone mapping;
+    /// `hdr_format` picks the one the adapter can blend into
+    pub hdr_format: wgpu::TextureFormat,
+    pub tonemapper: Tonemapper,
+    pub exposure: Exposure,
+    pub bloom: BloomSettings,
+    /// Samples taken along each view ray through the atmosphere; more is smoother and slower
+    pub atmosphere_samples: u32,
+    pub camera_mode: CameraMode,
+}
+
+impl Default for SceneOptions {
+    fn default() -> Self {
+        Self {
+            earth_geometry: EarthGeometry::default(),
+            earth_texture: EARTH_TEXTURE_PATH.to_string(),
+            earth_texture_options: TextureOptions::default(),
+            earth_texture_variants: Vec::new(),
+            sample_count: 4,
+            hdr_format: HDR_FORMAT,
+            tonemapper: Tonemapper::default(),
+            exposure: Exposure::default(),
+            bloom: BloomSettings::default(),
+            atmosphere_samples: 5,
+            camera_mode: CameraMode::default(),
+        }
+    }
+}
+
+/// Everything drawn in a frame, independent of where the frame is presented.
+pub struct Scene {
+    pub camera: Camera,
+    camera_mode: CameraMode,
+
+    earth: Earth,
+    atmosphere_model: AtmosphereModel,
+    star: Star,
+    sun: Sun,
+
+    meshes: MeshRegistry,
+
+    width: u32,
+    height: u32,
+    sample_count: u32,
+    pipelines: PipelineCache,
+    materials: Materials,
+    graph: RenderGraph<Scene>,
+    hdr_texture: TextureId,
+    bloom: BloomPass,
+    tonemap: TonemapPass,
+}
+
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+// Lowest altitude of an orbiting camera, relative to the radius of the Earth
+const ORBIT_MIN_ALTITUDE: f32 = 0.01;
+// Distance the free-fly camera moves per second
+const FREE_FLY_SPEED: f32 = 3000.0;
+
+// A controller of `mode` starting from `geom`; `radius` is the radius of the Earth
+fn create_controller(
+    mode: CameraMode,
+    geom: &CameraGeometry,
+    radius: f32,
+) -> Box<dyn CameraController> {
+    match mode {
+        CameraMode::FreeFly => Box::new(FreeFlyController::new(*geom, FREE_FLY_SPEED)),
+        CameraMode::Orbit => Box::new(OrbitController::from_geometry(
+            geom,
+            radius,
+            radius * ORBIT_MIN_ALTITUDE,
+        )),
+    }
+}
+
+/// Sample counts usable for the scene on `adapter`, in ascending order.
+pub fn supported_sample_counts(adapter: &wgpu::Adapter) -> Vec<u32> {
+    let color = adapter
+        .get_texture_format_features(hdr_format(adapter))
+        .flags;
+    let depth = adapter.get_texture_format_features(DEPTH_FORMAT).flags;
+    [1, 2, 4, 8]
+        .into_iter()
+        .filter(|&count| {
+            count == 1
+                || (color.sample_count_supported(count)
+                    && color.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+                    && depth.sample_count_supported(count))
+        })
+        .collect()
+}
+
+/// The greatest supported sample count that does not exceed `requested`.
+pub fn clamp_sample_count(requested: u32, supported: &[u32]) -> u32 {
+    let count = supported
+        .iter()
+        .copied()
+        .filter(|&count| count <= requested)
+        .max()
+        .unwrap_or(1);
+    if count != requested {
+        log::warn!("{}x MSAA is not supported; using {}x", requested, count);
+    }
+    count
+}
+
+// The passes of a frame; returns the graph and its HDR texture
+fn build_graph(
+    hdr_format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> (RenderGraph<Scene>, TextureId) {
+    let mut graph = RenderGraphBuilder::<Scene>::new();
+    let hdr = graph.texture(TextureDesc {
+        label: "hdr_texture",
+        format: hdr_format,
+        sample_count: 1,
+        // read back by `OffscreenRenderer::render_hdr`
+        usage: wgpu::TextureUsages::COPY_SRC,
+    });
+    let depth = graph.texture(TextureDesc {
+        label: "depth_texture",
+        format: DEPTH_FORMAT,
+        sample_count,
+        usage: wgpu::TextureUsages::empty(),
+    });
+    // multisampled color target resolved into `hdr`
+    let msaa = (sample_count > 1).then(|| {
+        graph.texture(TextureDesc {
+            label: "msaa_texture",
+            format: hdr_format,
+            sample_count,
+            usage: wgpu::TextureUsages::empty(),
+        })
+    });
+    let output = graph.output();
+
+    let scene_targets = [hdr, depth].into_iter().chain(msaa).collect::<Vec<_>>();
+    graph.pass(
+        "scene",
+        &[],
+        &scene_targets,
+        move |scene, encoder, resources| {
+            scene.draw(
+                encoder,
+                resources.view(hdr),
+                msaa.map(|msaa| resources.view(msaa)),
+                resources.view(depth),
+            );
+        },
+    );
+    graph.pass("bloom", &[hdr], &[hdr], move |scene, encoder, resources| {
+        scene
+            .bloom
+            .render(&scene.pipelines, encoder, resources.view(hdr));
+    });
+    graph.pass(
+        "tonemap",
+        &[hdr],
+        &[output],
+        move |scene, encoder, resources| {
+            scene.tonemap.render(
+                &scene.pipelines,
+                encoder,
+                resources.view(output),
+                resources.size(),
+            );
+        },
+    );
+
+    let graph = graph
+        .build()
+        .expect("the scene passes do not depend on each other");
+    log::debug!("Render passes: {:?}", graph.pass_names());
+    (graph, hdr)
+}
+
+impl Scene {
+    /// `format` is the format of the views passed to `render`.
+    pub async fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        source: &dyn AssetSource,
+        options: &SceneOptions,
+    ) -> Result<Self, GraphicsError> {
+        let earth_property = EarthProperty::default();
+        let source = &FetchErrors(source);
+
+        let mut meshes = MeshRegistry::new(MemoryTracker::new());
+        let (earth_model, atmosphere_model) = create_earth_and_atmosphere_model(
+            device,
+            queue,
+            &mut meshes,
+            source,
+            options,
+            earth_property.radius,
+        )
+        .await?;
+
+        let earth = Earth::new(device, earth_model, earth_property.clone());
+
+        let geom = CameraGeometry::new(
+            (
+                earth_property.radius * 4.0,
+                earth_property.radius,
+                -earth_property.radius * 1.0,
+            )
+                .into(),
+            (0.0, 0.0, 0.0).into(),
+            cgmath::Vector3::unit_y(),
+        );
+        let perspective = CameraPerspective::new(
+            create_controller(options.camera_mode, &geom, earth_property.radius),
+            width as f32 / height as f32,
+            45.0,
+            0.1,
+        );
+        let camera = Camera::new(device, perspective);
+
+        let sun_property = SunProperty::new(
+            (
+                0.0,
+                0.0,
+                earth_property.get_distance_between_earth_and_sun(),
+            )
+                .into(),
+            (1.0, 1.0, 1.0).into(),
+        );
+
+        let sun = Sun::new(device, sun_property);
+
+        let star = Star::new(device, queue);
+
+        // Errors are caught by the scope instead of the uncaptured error handler, which panics
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let sample_count = options.sample_count.max(1);
+        let mut pipelines = PipelineCache::new(
+            RenderTargets {
+                format: options.hdr_format,
+                depth_format: Some(DEPTH_FORMAT),
+                sample_count,
+            },
+            ShaderLibrary::default(),
+        );
+        let materials = Materials::register(&mut pipelines, device, options, &camera, &earth, &sun);
+        let (mut graph, hdr_texture) = build_graph(options.hdr_format, sample_count);
+        graph.resize(device, width, height);
+        let hdr_view = graph.view(hdr_texture);
+        let bloom = BloomPass::new(
+            device,
+            &mut pipelines,
+            options.hdr_format,
+            hdr_view,
+            width,
+            height,
+            options.bloom,
+        );
+        let tonemap = TonemapPass::new(
+            device,
+            &mut pipelines,
+            format,
+            options.hdr_format,
+            hdr_view,
+            options.tonemapper,
+            options.exposure,
+        );
+        let error = device.pop_error_scope().await;
+        let (materials, bloom, tonemap) = (materials?, bloom?, tonemap?);
+        if let Some(error) = error {
+            return Err(ShaderError::Compile(error.to_string()).into());
+        }
+
+        Ok(Self {
+            camera,
+            camera_mode: options.camera_mode,
+            earth,
+            atmosphere_model,
+            star,
+            sun,
+            meshes,
+            width,
+            height,
+            sample_count,
+            pipelines,
+            materials,
+            graph,
+            hdr_texture,
+            bloom,
+            tonemap,
+        })
+    }
+
+    /// `dt` is the time in seconds since the last update.
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32) {
+        self.camera.perspective.tween(dt);
+        self.camera.enque_update(queue);
+        self.earth.property.rotate(0.001);
+        self.earth.enque_update_uniform(queue);
+        self.bloom.enque_update(queue);
+        self.tonemap.enque_update(queue);
+    }
+
+    /// GPU memory used by the meshes and textures of the scene.
+    pub fn memory_report(&self) -> MemoryReport {
+        self.meshes.tracker().report()
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.resize_graph(device);
+        self.camera
+            .perspective
+            .update_aspect(width as f32 / height as f32);
+    }
+
+    pub fn tonemapper(&self) -> Tonemapper {
+        self.tonemap.tonemapper()
+    }
+
+    pub fn set_tonemapper(&mut self, tonemapper: Tonemapper) {
+        self.tonemap.set_tonemapper(tonemapper);
+    }
+
+    pub fn exposure(&self) -> Exposure {
+        self.tonemap.exposure()
+    }
+
+    pub fn set_exposure(&mut self, exposure: Exposure) {
+        self.tonemap.set_exposure(exposure);
+    }
+
+    pub fn bloom(&self) -> BloomSettings {
+        self.bloom.settings()
+    }
+
+    pub fn set_bloom(&mut self, bloom: BloomSettings) {
+        self.bloom.set_settings(bloom);
+    }
+
+    pub fn camera_mode(&self) -> CameraMode {
+        self.camera_mode
+    }
+
+    /// The camera eases from where it is heading to the view of the new mode.
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        if mode == self.camera_mode {
+            return;
+        }
+        self.camera_mode = mode;
+        let perspective = &mut self.camera.perspective;
+        let controller = create_controller(mode, &perspective.goal(), self.earth.property.radius);
+        perspective.set_controller(controller);
+    }
+
+    /// Switches to the orbit mode, easing over to `view`.
+    pub fn fly_to(&mut self, view: OrbitView) {
+        self.camera_mode = CameraMode::Orbit;
+        let radius = self.earth.property.radius;
+        let controller = OrbitController::new(view, radius, radius * ORBIT_MIN_ALTITUDE);
+        self.camera.perspective.set_controller(Box::new(controller));
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Watches `source` for changes to the shaders of the library, laid out like `src/shader`.
+    #[cfg(feature = "hot-reload")]
+    pub fn watch_shaders(&self, source: impl WatchSource) -> ShaderWatcher {
+        ShaderWatcher::new(source, self.pipelines.library())
+    }
+
+    /// Swaps in the pipelines of the layers and post-processing passes whose shaders
+    /// change. The auto exposure histogram keeps the compute shader built into the crate.
+    #[cfg(feature = "hot-reload")]
+    pub async fn set_shader_sources(
+        &mut self,
+        device: &wgpu::Device,
+        sources: &[(String, String)],
+    ) -> Result<usize, ShaderError> {
+        self.pipelines.set_shader_sources(device, sources).await
+    }
+
+    /// Same as `set_shader_sources` without waiting for the device; see
+    /// `PipelineCache::rebuild`.
+    #[cfg(feature = "hot-reload")]
+    pub fn rebuild_shaders(
+        &mut self,
+        device: &wgpu::Device,
+        sources: &[(String, String)],
+    ) -> Result<PipelineRebuild, ShaderError> {
+        self.pipelines.rebuild(device, sources)
+    }
+
+    /// The result of `rebuild_shaders`, or `None` while the device has not validated it.
+    #[cfg(feature = "hot-reload")]
+    pub fn finish_rebuild(
+        &mut self,
+        rebuild: &mut PipelineRebuild,
+    ) -> Option<Result<usize, ShaderError>> {
+        self.pipelines.finish_rebuild(rebuild)
+    }
+
+    /// Rebuilds the pipelines and the render graph. `sample_count` has to be supported by the adapter.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        if sample_count == self.sample_count {
+            return;
+        }
+        self.sample_count = sample_count;
+        self.pipelines.set_targets(
+            device,
+            RenderTargets {
+                sample_count,
+                ..self.pipelines.targets()
+            },
+        );
+        (self.graph, self.hdr_texture) = build_graph(self.pipelines.targets().format, sample_count);
+        self.resize_graph(device);
+    }
+
+    fn resize_graph(&mut self, device: &wgpu::Device) {
+        self.graph.resize(device, self.width, self.height);
+        let hdr_view = self.graph.view(self.hdr_texture);
+        self.bloom.resize(device, hdr_view, self.width, self.height);
+        self.tonemap.resize(device, hdr_view);
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        self.graph.run(self, encoder, view);
+    }
+
+    /// The linear HDR frame of the last `render`, with bloom but before exposure and
+    /// tone mapping.
+    pub fn hdr_texture(&self) -> &wgpu::Texture {
+        self.graph.texture(self.hdr_texture)
+    }
+
+    // Draws the models and the sky into the HDR target
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr_view: &wgpu::TextureView,
+        msaa_view: Option<&wgpu::TextureView>,
+        depth_view: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: msaa_view.unwrap_or(hdr_view),
+                resolve_target: msaa_view.map(|_| hdr_view),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.269 / 255.0,
+                        g: 0.388 / 255.0,
+                        b: 0.342 / 255.0,
+                        a: 1.0,
+                    }),
+                    // the samples are not needed once they are resolved
+                    store: if msaa_view.is_some() {
+                        wgpu::StoreOp::Discard
+                    } else {
+                        wgpu::StoreOp::Store
+                    },
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        // The Earth first, so that it occludes the sky behind it
+        render_pass.set_pipeline(self.pipelines.pipeline(self.materials.earth));
+        render_pass.draw_earth_model(
+            &self.earth.model,
+            &self.camera.bind_group,
+            &self.sun.uniform_bind_group,
+            &self.earth.uniform_bind_group,
+        );
+
+        render_pass.set_pipeline(self.pipelines.pipeline(self.materials.star));
+        render_pass.set_vertex_buffer(0, self.star.instance_buffer.slice(..));
+        render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
+        render_pass.set_index_buffer(self.star.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(
+            0..self.star.num_indices,
+            0,
+            0..self.star.instances.len() as u32,
+        );
+
+        render_pass.set_pipeline(self.pipelines.pipeline(self.materials.sun));
+        render_pass.set_vertex_buffer(0, self.sun.vertex_buffer.slice(..));
+        render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        render_pass.set_pipeline(self.pipelines.pipeline(self.materials.atmosphere));
+        render_pass.draw_atmosphere_model(
+            &self.atmosphere_model,
+            &self.camera.bind_group,
+            &self.sun.uniform_bind_group,
+            &self.earth.uniform_bind_group,
+        );
+    }
+}
+
+// Pipelines of the layers drawn into the HDR target
+struct Materials {
+    earth: MaterialId,
+    atmosphere: MaterialId,
+    star: MaterialId,
+    sun: MaterialId,
+}
+
+impl Materials {
+    fn register(
+        pipelines: &mut PipelineCache,
+        device: &wgpu::Device,
+        options: &SceneOptions,
+        camera: &Camera,
+        earth: &Earth,
+        sun: &Sun,
+    ) -> Result<Self, ShaderError> {
+        let blend = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+        // Objects closer to the camera have greater depth (reversed Z)
+        let depth_test = |write| DepthTest {
+            compare: wgpu::CompareFunction::Greater,
+            write,
+        };
+        // Stars and the sun are at infinity, where the depth is exactly 0
+        let sky_depth_test = DepthTest {
+            compare: wgpu::CompareFunction::GreaterEqual,
+            write: false,
+        };
+
+        Ok(Self {
+            earth: pipelines.register(
+                device,
+                &MaterialDesc {
+                    shader: Shader::new("earth.wgsl"),
+                    fragment_entry: "fs_main",
+                    bind_group_layouts: &[
+                        &camera.bind_group_layout,
+                        &earth.model.texture_bind_group_layout,
+                        &sun.uniform_bind_group_layout,
+                        &earth.uniform_bind_group_layout,
+                    ],
+                    vertex_buffers: &[ModelVertex::desc()],
+                    blend: wgpu::BlendState::REPLACE,
+                    depth_test: Some(depth_test(true)),
+                    cull_mode: Some(wgpu::Face::Back),
+                    targets: None,
+                },
+            )?,
+            atmosphere: pipelines.register(
+                device,
+                &MaterialDesc {
+                    shader: Shader::new("atmosphere.wgsl")
+                        .define("ATMOSPHERE_SAMPLES", options.atmosphere_samples.max(1)),
+                    fragment_entry: "fs_main",
+                    bind_group_layouts: &[
+                        &camera.bind_group_layout,
+                        &sun.uniform_bind_group_layout,
+                        &earth.uniform_bind_group_layout,
+                    ],
+                    vertex_buffers: &[ModelVertex::desc()],
+                    blend,
+                    depth_test: Some(depth_test(false)),
+                    cull_mode: Some(wgpu::Face::Back),
+                    targets: None,
+                },
+            )?,
+            star: pipelines.register(
+                device,
+                &MaterialDesc {
+                    shader: Shader::new("star.wgsl"),
+                    fragment_entry: "fs_main",
+                    bind_group_layouts: &[&camera.bind_group_layout],
+                    vertex_buffers: &[StarInstanceRaw::desc()],
+                    blend,
+                    depth_test: Some(sky_depth_test),
+                    cull_mode: Some(wgpu::Face::Back),
+                    targets: None,
+                },
+            )?,
+            sun: pipelines.register(
+                device,
+                &MaterialDesc {
+                    shader: Shader::new("sun.wgsl"),
+                    fragment_entry: "fs_main",
+                    bind_group_layouts: &[&camera.bind_group_layout],
+                    vertex_buffers: &[SunVertex::desc()],
+                    blend,
+                    depth_test: Some(sky_depth_test),
+                    cull_mode: Some(wgpu::Face::Back),
+                    targets: None,
+                },
+            )?,
+        })
+    }
+}
+
+// Tags the errors of `source` as fetch errors, so that `GraphicsError` can tell them
+// apart from errors decoding the fetched resources
+struct FetchErrors<'a>(&'a dyn AssetSource);
+
+impl AssetSource for FetchErrors<'_> {
+    fn fetch<'a>(&'a self, path: &'a str) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        self.fetch_with_progress(path, &|_, _| {})
+    }
+
+    fn fetch_with_progress<'a>(
+        &'a self,
+        path: &'a str,
+        progress: &'a dyn Fn(u64, Option<u64>),
+    ) -> LocalBoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move {
+            self.0
+                .fetch_with_progress(path, progress)
+                .await
+                .map_err(|e| GraphicsError::fetch(path, e).into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::{
+        asset::{Loader, MemorySource},
+        earth::model::EARTH_MODEL_PATH,
+        testing::{device, earth_resources},
+        tonemap::LDR_FORMAT,
+    };
+
+    // None without an adapter
+    fn load(source: &dyn AssetSource) -> Option<Result<Scene, GraphicsError>> {
+        let (device, queue) = device()?;
+        let options = SceneOptions {
+            sample_count: 1,
+            ..SceneOptions::default()
+        };
+        Some(block_on(Scene::new(
+            &device,
+            &queue,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            64,
+            48,
+            source,
+            &options,
+        )))
+    }
+
+    #[test]
+    fn loads_from_memory() {
+        if let Some(result) = load(&earth_resources()) {
+            let scene = result.unwrap();
+            assert!(scene.memory_report().total_bytes() > 0);
+        }
+    }
+
+    #[test]
+    fn missing_assets_are_fetch_errors() {
+        let source = MemorySource::new().with(
+            EARTH_MODEL_PATH,
+            &include_bytes!("../resources/earth/earth.obj")[..],
+        );
+        if let Some(result) = load(&source) {
+            match result.err().unwrap() {
+                GraphicsError::AssetFetch { path, .. } => {
+                    assert_eq!(path, "resources/earth/earth.mtl")
+                }
+                e => panic!("Unexpected error: {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_assets_are_decode_errors() {
+        let source = earth_resources().with(EARTH_MODEL_PATH, &b"v 0 0 0\nf 1 1 1\n"[..]);
+        if let Some(result) = load(&source) {
+            let error = result.err().unwrap();
+            assert!(
+                matches!(error, GraphicsError::AssetDecode(_)),
+                "{:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn cancelled_loads_are_reported() {
+        let loader = Loader::new(earth_resources());
+        loader.cancel_token().cancel();
+        if let Some(result) = load(&loader) {
+            let error = result.err().unwrap();
+            assert!(matches!(error, GraphicsError::Cancelled), "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn renders_into_the_ldr_fallback() {
+        let Some((device, queue)) = device() else {
+            return;
+        };
+        let options = SceneOptions {
+            sample_count: 1,
+            hdr_format: LDR_FORMAT,
+            ..SceneOptions::default()
+        };
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let mut scene = block_on(Scene::new(
+            &device,
+            &queue,
+            format,
+            64,
+            48,
+            &earth_resources(),
+            &options,
+        ))
+        .unwrap();
+        assert_eq!(scene.hdr_texture().format(), LDR_FORMAT);
+
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: 64,
+                height: 48,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        scene.update(&queue, 0.0);
+        let mut encoder = device.create_command_encoder(&Default::default());
+        scene.render(&mut encoder, &view);
+        queue.submit([encoder.finish()]);
+        assert!(block_on(device.pop_error_scope()).is_none());
+    }
+
+    #[cfg(feature = "hot-reload")]
+    #[test]
+    fn rebuilds_post_processing_shaders() {
+        let Some((device, queue)) = device() else {
+            return;
+        };
+        let options = SceneOptions {
+            sample_count: 1,
+            ..SceneOptions::default()
+        };
+        let mut scene = block_on(Scene::new(
+            &device,
+            &queue,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            64,
+            48,
+            &earth_resources(),
+            &options,
+        ))
+        .unwrap();
+        // the two entry points of the histogram, where compute shaders are supported
+        let histogram_pipelines = if scene.tonemap.supports_auto_exposure() {
+            2
+        } else {
+            0
+        };
+        let mut rebuild = |path: &str, source: String| -> Result<usize, ShaderError> {
+            let mut rebuild = scene.rebuild_shaders(&device, &[(path.to_string(), source)])?;
+            device.poll(wgpu::Maintain::Wait);
+            scene.finish_rebuild(&mut rebuild).unwrap()
+        };
+
+        // one pipeline per fragment entry point of the bloom
+        let library = ShaderLibrary::default();
+        let bloom = format!("{}\n// edited\n", library.source("bloom.wgsl").unwrap());
+        assert_eq!(rebuild("bloom.wgsl", bloom), Ok(4));
+        let tonemap = format!("{}\n// edited\n", library.source("tonemap.wgsl").unwrap());
+        assert_eq!(rebuild("tonemap.wgsl", tonemap), Ok(1));
+        let histogram = format!("{}\n// edited\n", library.source("histogram.wgsl").unwrap());
+        assert_eq!(
+            rebuild("histogram.wgsl", histogram),
+            Ok(histogram_pipelines)
+        );
+        let broken = rebuild("tonemap.wgsl", "fn fs_main(".to_string());
+        assert!(
+            matches!(broken, Err(ShaderError::Compile(_))),
+            "{:?}",
+            broken
+        );
+    }
+}
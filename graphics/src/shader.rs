@@ -0,0 +1,264 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+#[cfg(feature = "hot-reload")]
+pub use watch::{ShaderWatcher, WatchSource};
+
+#[cfg(feature = "hot-reload")]
+mod watch;
+
+// Shaders built into the crate, by their path under `src/shader`
+const BUILTIN_SHADERS: &[(&str, &str)] = &[
+    ("earth.wgsl", include_str!("shader/earth.wgsl")),
+    ("atmosphere.wgsl", include_str!("shader/atmosphere.wgsl")),
+    ("star.wgsl", include_str!("shader/star.wgsl")),
+    ("sun.wgsl", include_str!("shader/sun.wgsl")),
+    ("bloom.wgsl", include_str!("shader/bloom.wgsl")),
+    ("histogram.wgsl", include_str!("shader/histogram.wgsl")),
+    ("tonemap.wgsl", include_str!("shader/tonemap.wgsl")),
+    (
+        "include/camera.wgsl",
+        include_str!("shader/include/camera.wgsl"),
+    ),
+    (
+        "include/earth.wgsl",
+        include_str!("shader/include/earth.wgsl"),
+    ),
+    (
+        "include/math.wgsl",
+        include_str!("shader/include/math.wgsl"),
+    ),
+    (
+        "include/model.wgsl",
+        include_str!("shader/include/model.wgsl"),
+    ),
+    ("include/sun.wgsl", include_str!("shader/include/sun.wgsl")),
+];
+
+/// A shader of a `ShaderLibrary` with the defines it is compiled with;
+/// each combination of defines is a separate variant.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Shader {
+    pub path: &'static str,
+    pub defines: Vec<(&'static str, String)>,
+}
+
+impl Shader {
+    pub fn new(path: &'static str) -> Self {
+        Self {
+            path,
+            defines: Vec::new(),
+        }
+    }
+
+    pub fn define(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.defines.push((name, value.to_string()));
+        self
+    }
+
+    /// The file name without extension, used in labels.
+    pub fn name(&self) -> &'static str {
+        let file = self.path.rsplit('/').next().unwrap_or(self.path);
+        file.strip_suffix(".wgsl").unwrap_or(file)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShaderError {
+    UnknownShader(String),
+    /// Unknown directive, or one missing its argument or its `#if`
+    InvalidDirective {
+        path: String,
+        line: usize,
+    },
+    UnterminatedIf(String),
+    /// The preprocessed WGSL does not compile or link into the pipelines using it
+    Compile(String),
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderError::UnknownShader(path) => write!(f, "Unknown shader: {}", path),
+            ShaderError::InvalidDirective { path, line } => {
+                write!(f, "Invalid preprocessor directive at {}:{}", path, line)
+            }
+            ShaderError::UnterminatedIf(path) => write!(f, "Missing #endif in {}", path),
+            ShaderError::Compile(message) => write!(f, "Shader compilation failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+/// WGSL sources extended with a few preprocessor directives, each on its own line:
+///
+/// - `#include "path"` (or `#import`) pastes another source of the library, once per shader;
+///   paths are relative to the library root
+/// - `#define NAME [value]` defines `NAME`, which is then replaced by `value` in the code
+/// - `#ifdef NAME`, `#ifndef NAME` and `#if NAME` (defined and neither `0` nor `false`),
+///   followed by an optional `#else` and an `#endif`
+#[derive(Clone)]
+pub struct ShaderLibrary {
+    sources: HashMap<String, String>,
+}
+
+impl Default for ShaderLibrary {
+    fn default() -> Self {
+        Self {
+            sources: BUILTIN_SHADERS
+                .iter()
+                .map(|&(path, source)| (path.to_string(), source.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl ShaderLibrary {
+    #[cfg(feature = "hot-reload")]
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.sources.keys().map(String::as_str)
+    }
+
+    #[cfg(feature = "hot-reload")]
+    pub fn source(&self, path: &str) -> Option<&str> {
+        self.sources.get(path).map(String::as_str)
+    }
+
+    /// Replaces the source of a shader of the library; returns whether it changed.
+    #[cfg(feature = "hot-reload")]
+    pub fn set_source(&mut self, path: &str, source: String) -> Result<bool, ShaderError> {
+        let current = self
+            .sources
+            .get_mut(path)
+            .ok_or_else(|| ShaderError::UnknownShader(path.to_string()))?;
+        if *current == source {
+            return Ok(false);
+        }
+        *current = source;
+        Ok(true)
+    }
+
+    /// Expands the directives of `shader` into plain WGSL.
+    pub fn preprocess(&self, shader: &Shader) -> Result<String, ShaderError> {
+        let mut defines = shader
+            .defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        let mut output = String::new();
+        self.expand(shader.path, &mut defines, &mut HashSet::new(), &mut output)?;
+        Ok(output)
+    }
+
+    fn expand<'a>(
+        &'a self,
+        path: &'a str,
+        defines: &mut HashMap<String, String>,
+        included: &mut HashSet<&'a str>,
+        output: &mut String,
+    ) -> Result<(), ShaderError> {
+        let source = self
+            .sources
+            .get(path)
+            .ok_or_else(|| ShaderError::UnknownShader(path.to_string()))?;
+        included.insert(path);
+
+        // whether the branch of each enclosing #if is taken, and whether its #else was seen
+        let mut conditions: Vec<(bool, bool)> = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let invalid = || ShaderError::InvalidDirective {
+                path: path.to_string(),
+                line: index + 1,
+            };
+            let active = conditions.iter().all(|&(taken, _)| taken);
+
+            let Some(directive) = line.trim().strip_prefix('#') else {
+                if active {
+                    substitute(line, defines, output);
+                    output.push('\n');
+                }
+                continue;
+            };
+            let (keyword, argument) = directive
+                .split_once(char::is_whitespace)
+                .map(|(keyword, argument)| (keyword, argument.trim()))
+                .unwrap_or((directive, ""));
+
+            match keyword {
+                "ifdef" | "ifndef" | "if" => {
+                    if argument.is_empty() {
+                        return Err(invalid());
+                    }
+                    let value = defines.get(argument);
+                    let taken = match keyword {
+                        "ifdef" => value.is_some(),
+                        "ifndef" => value.is_none(),
+                        _ => value.is_some_and(|value| value != "0" && value != "false"),
+                    };
+                    conditions.push((taken, false));
+                }
+                "else" => match conditions.last_mut() {
+                    Some((taken, seen_else)) if !*seen_else => {
+                        *taken = !*taken;
+                        *seen_else = true;
+                    }
+                    _ => return Err(invalid()),
+                },
+                "endif" => {
+                    conditions.pop().ok_or_else(invalid)?;
+                }
+                _ if !active => {}
+                "define" => {
+                    let (name, value) = argument
+                        .split_once(char::is_whitespace)
+                        .unwrap_or((argument, ""));
+                    if name.is_empty() {
+                        return Err(invalid());
+                    }
+                    defines.insert(name.to_string(), value.trim().to_string());
+                }
+                "include" | "import" => {
+                    let include = argument
+                        .strip_prefix('"')
+                        .and_then(|argument| argument.strip_suffix('"'))
+                        .ok_or_else(invalid)?;
+                    if !included.contains(include) {
+                        let (include, _) = self
+                            .sources
+                            .get_key_value(include)
+                            .ok_or_else(|| ShaderError::UnknownShader(include.to_string()))?;
+                        self.expand(include, defines, included, output)?;
+                    }
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        if !conditions.is_empty() {
+            return Err(ShaderError::UnterminatedIf(path.to_string()));
+        }
+        Ok(())
+    }
+}
+
+// Appends `line` with the identifiers that have a defined value replaced by it
+fn substitute(line: &str, defines: &HashMap<String, String>, output: &mut String) {
+    let mut rest = line;
+    while let Some(start) = rest.find(|c: char| c.is_alphabetic() || c == '_') {
+        let (before, word) = rest.split_at(start);
+        output.push_str(before);
+        let end = word
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(word.len());
+        let (identifier, after) = word.split_at(end);
+        match defines.get(identifier) {
+            Some(value) if !value.is_empty() => output.push_str(value),
+            _ => output.push_str(identifier),
+        }
+        rest = after;
+    }
+    output.push_str(rest);
+}
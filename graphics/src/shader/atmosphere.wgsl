@@ -1,66 +1,23 @@
+#include "include/camera.wgsl"
+#include "include/model.wgsl"
+#include "include/sun.wgsl"
+#include "include/earth.wgsl"
+#include "include/math.wgsl"
 
-struct CameraUniform {
-    view_pos: vec4<f32>,
-    target_pos: vec4<f32>,
-    view_proj: mat4x4<f32>,
-    aspect: f32,
-}
+// Samples taken along each ray, overridden by `SceneOptions::atmosphere_samples`
+#ifndef ATMOSPHERE_SAMPLES
+#define ATMOSPHERE_SAMPLES 5
+#endif
 
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
-struct SunUniform {
-    position: vec4<f32>,
-    color: vec3<f32>,
-    _padding: u32,
-}
-
 @group(1) @binding(0)
 var<uniform> sun: SunUniform;
 
-struct EarthUniform {
-    radius: f32,
-    atmosphere_radius: f32,
-    rotation: f32,
-    _padding0: f32,
-    axis: vec3<f32>,
-    _padding1: f32,
-}
-
 @group(2) @binding(0)
 var<uniform> earth: EarthUniform;
 
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
-    @location(2) normal: vec3<f32>,
-};
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>,
-    @location(1) normal: vec3<f32>,
-    @location(2) model_position: vec3<f32>,
-};
-
-fn intersection_sphere(ray_origin: vec3<f32>, ray_direction: vec3<f32>, sphere_center: vec3<f32>, sphere_radius: f32) -> mat2x3<f32> {
-    let oc = ray_origin - sphere_center;
-    let a = dot(ray_direction, ray_direction)+0.000001;
-    let b = 2.0 * dot(oc, ray_direction);
-    let c = dot(oc, oc) - sphere_radius * sphere_radius;
-    let discriminant = b * b - 4.0 * a * c;
-
-    if (discriminant < 0.0) {
-        return mat2x3<f32>(sphere_center, sphere_center);
-    } else {
-        let t1 = (-b - sqrt(discriminant)) / (2.0 * a);
-        let t2 = (-b + sqrt(discriminant)) / (2.0 * a);
-        let p1 = ray_origin + t1 * ray_direction;
-        let p2 = ray_origin + t2 * ray_direction;
-        return mat2x3<f32>(p1, p2);
-    }
-}
-
 @vertex
 fn vs_main(
     model: VertexInput,
@@ -87,7 +44,7 @@ fn atmosphere_density(position: vec3<f32>, center: vec3<f32>) -> f32 {
 }
 
 fn optical_depth(ray_start: vec3<f32>, ray_end: vec3<f32>, center: vec3<f32>) -> f32 {
-    let division = 5;
+    let division: i32 = ATMOSPHERE_SAMPLES;
     let sample_interval = (ray_end - ray_start) / f32(division-1);
     var optical_depth_sum = 0.0;
     for (var i: i32 = 0; i < division; i++) {
@@ -117,7 +74,7 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     
     var strength_average: f32 = 0.0;
     if (!vector3_equals(atmosphere_start, center) && !vector3_equals(atmosphere_end, center)) {
-        let division: i32 = 5;
+        let division: i32 = ATMOSPHERE_SAMPLES;
         var strength_sum: f32 = 0.0;
         let sample_interval = (atmosphere_end - atmosphere_start) / f32(division-1);
         for (var i: i32 = 0; i < division; i++) {
@@ -140,7 +97,8 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         strength_average = strength_sum/f32(division);
     }
 
-    let object_color: vec4<f32> = vec4<f32>(sun.color, strength_average*0.3);
+    // float targets do not clamp the output, and an alpha above 1 would darken what is behind
+    let object_color: vec4<f32> = vec4<f32>(sun.color, clamp(strength_average*0.3, 0.0, 1.0));
 
     return object_color;
 }
@@ -0,0 +1,90 @@
+struct Settings {
+    threshold: f32,
+    // width of the soft transition below the threshold
+    knee: f32,
+    intensity: f32,
+    radius: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> settings: Settings;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // a single triangle covering the screen
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+fn sample_offset(uv: vec2<f32>, texel: vec2<f32>, x: f32, y: f32) -> vec3<f32> {
+    return textureSampleLevel(source, source_sampler, uv + texel * vec2<f32>(x, y), 0.0).rgb;
+}
+
+// 13 tap filter from "Next Generation Post Processing in Call of Duty: Advanced Warfare"
+fn downsample_13(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let outer = sample_offset(uv, texel, -2.0, -2.0) + sample_offset(uv, texel, 2.0, -2.0)
+        + sample_offset(uv, texel, -2.0, 2.0) + sample_offset(uv, texel, 2.0, 2.0);
+    let cross = sample_offset(uv, texel, 0.0, -2.0) + sample_offset(uv, texel, -2.0, 0.0)
+        + sample_offset(uv, texel, 2.0, 0.0) + sample_offset(uv, texel, 0.0, 2.0);
+    let inner = sample_offset(uv, texel, -1.0, -1.0) + sample_offset(uv, texel, 1.0, -1.0)
+        + sample_offset(uv, texel, -1.0, 1.0) + sample_offset(uv, texel, 1.0, 1.0);
+    let center = sample_offset(uv, texel, 0.0, 0.0);
+    return center * 0.125 + outer * 0.03125 + cross * 0.0625 + inner * 0.125;
+}
+
+// 3x3 tent filter whose size is scaled by the radius
+fn upsample_tent(uv: vec2<f32>) -> vec3<f32> {
+    let texel = settings.radius / vec2<f32>(textureDimensions(source));
+    let corners = sample_offset(uv, texel, -1.0, -1.0) + sample_offset(uv, texel, 1.0, -1.0)
+        + sample_offset(uv, texel, -1.0, 1.0) + sample_offset(uv, texel, 1.0, 1.0);
+    let cross = sample_offset(uv, texel, 0.0, -1.0) + sample_offset(uv, texel, -1.0, 0.0)
+        + sample_offset(uv, texel, 1.0, 0.0) + sample_offset(uv, texel, 0.0, 1.0);
+    let center = sample_offset(uv, texel, 0.0, 0.0);
+    return (center * 4.0 + cross * 2.0 + corners) / 16.0;
+}
+
+// Keeps the part of the color above the threshold, with a quadratic knee below it
+fn threshold(hdr_color: vec3<f32>) -> vec3<f32> {
+    // negative colors would be scaled up by the division below
+    let color = max(hdr_color, vec3<f32>(0.0));
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - settings.threshold + settings.knee, 0.0, 2.0 * settings.knee);
+    soft = soft * soft / (4.0 * settings.knee + 1e-4);
+    let contribution = max(soft, brightness - settings.threshold) / max(brightness, 1e-4);
+    return color * contribution;
+}
+
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(threshold(downsample_13(in.uv)), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample_13(in.uv), 1.0);
+}
+
+// Blended additively into the next larger level
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(upsample_tent(in.uv), 1.0);
+}
+
+// Blended additively into the HDR target
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(upsample_tent(in.uv) * settings.intensity, 1.0);
+}
@@ -1,10 +1,8 @@
-
-struct CameraUniform {
-    view_pos: vec4<f32>,
-    target_pos: vec4<f32>,
-    view_proj: mat4x4<f32>,
-    aspect: f32,
-}
+#include "include/camera.wgsl"
+#include "include/model.wgsl"
+#include "include/sun.wgsl"
+#include "include/earth.wgsl"
+#include "include/math.wgsl"
 
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
@@ -14,54 +12,27 @@ var t_diffuse: texture_2d<f32>;
 @group(1) @binding(1)
 var s_diffuse: sampler;
 
-struct SunUniform {
-    position: vec4<f32>,
-    color: vec3<f32>,
-    _padding: u32,
+struct MaterialUniform {
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
 }
 
+@group(1) @binding(2)
+var<uniform> material: MaterialUniform;
+// roughness in green and metalness in blue, white without a texture
+@group(1) @binding(3)
+var t_metallic_roughness: texture_2d<f32>;
+@group(1) @binding(4)
+var t_emissive: texture_2d<f32>;
+
 @group(2) @binding(0)
 var<uniform> sun: SunUniform;
 
-struct EarthUniform {
-    radius: f32,
-    atmosphere_radius: f32,
-    rotation: f32,
-    _padding0: f32,
-    axis: vec3<f32>,
-    _padding1: f32,
-}
-
 @group(3) @binding(0)
 var<uniform> earth: EarthUniform;
 
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
-    @location(2) normal: vec3<f32>,
-};
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>,
-    @location(1) normal: vec3<f32>,
-    @location(2) model_position: vec3<f32>,
-};
-
-fn rotation_matrix(angle: f32, axis: vec3<f32>) -> mat3x3<f32> {
-    let c = cos(angle);
-    let s = sin(angle);
-    let oc = 1.0 - c;
-    let x = axis.x;
-    let y = axis.y;
-    let z = axis.z;
-    return mat3x3<f32>(
-        vec3<f32>(oc*x*x+c, oc*x*y-s*z, oc*x*z+s*y),
-        vec3<f32>(oc*x*y+s*z, oc*y*y+c, oc*y*z-s*x),
-        vec3<f32>(oc*x*z-s*y, oc*y*z+s*x, oc*z*z+c),
-    );
-}
-
 @vertex
 fn vs_main(
     model: VertexInput,
@@ -77,7 +48,14 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords) * material.base_color_factor;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_diffuse, in.tex_coords);
+    let metallic = material.metallic * metallic_roughness.b;
+    // Blinn-Phong exponent equivalent to the GGX roughness (alpha = roughness^2)
+    let roughness = material.roughness * metallic_roughness.g;
+    let alpha = max(roughness * roughness, 0.01);
+    let specular_exponent = clamp(2.0 / (alpha * alpha) - 2.0, 1.0, 2048.0);
+    let emissive = material.emissive_factor * textureSample(t_emissive, s_diffuse, in.tex_coords).rgb;
     
     let ambient_strength = 0.0;
     let ambient_color = sun.color * ambient_strength;
@@ -85,15 +63,20 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let sun_dir = normalize(sun.position.xyz - in.model_position);
 
     let diffuse_strength = min(max(dot(sun_dir, in.normal), 0.0), 1.0);
-    let diffuse_color = sun.color * diffuse_strength;
+    let diffuse_color = sun.color * diffuse_strength * (1.0 - metallic);
 
     let view_dir = normalize(camera.view_pos.xyz - in.model_position);
     let reflect_dir = reflect(-sun_dir, in.normal);
 
-    let specular_strength = pow(max(dot(reflect_dir, view_dir), 0.0), 18.0);
+    // the reflection also lines up with the view on the night side, which must stay dark
+    let specular_strength = select(
+        0.0,
+        pow(max(dot(reflect_dir, view_dir), 0.0), specular_exponent),
+        diffuse_strength > 0.0,
+    );
     let specular_color = sun.color * specular_strength;
    
-    let result = (ambient_strength + diffuse_color + specular_color) * object_color.xyz;
+    let result = (ambient_strength + diffuse_color + specular_color) * object_color.xyz + emissive;
 
     return vec4<f32>(result, object_color.a);
 }
\ No newline at end of file
@@ -0,0 +1,78 @@
+struct Params {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // fraction of the way to the new average covered every frame
+    adaptation: f32,
+    _padding: f32,
+}
+
+@group(0) @binding(0)
+var hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+// average scene luminance, read by tonemap.wgsl
+@group(0) @binding(2)
+var<storage, read_write> luminance: f32;
+@group(0) @binding(3)
+var<uniform> params: Params;
+
+var<workgroup> local_histogram: array<atomic<u32>, 256>;
+var<workgroup> weighted_sum: array<f32, 256>;
+
+// Bin 0 holds the pixels too dark to matter, like the background of space;
+// the others split the log luminance range
+fn luminance_bin(color: vec3<f32>) -> u32 {
+    let l = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    var t = (log2(max(l, 1e-10)) - params.min_log_luminance) / params.log_luminance_range;
+    if t < 0.0 {
+        return 0u;
+    }
+    t = min(t, 1.0);
+    return u32(t * 254.0 + 1.0);
+}
+
+@compute @workgroup_size(16, 16)
+fn build(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    atomicStore(&local_histogram[index], 0u);
+    workgroupBarrier();
+
+    let size = textureDimensions(hdr);
+    if id.x < size.x && id.y < size.y {
+        let color = textureLoad(hdr, vec2<i32>(id.xy), 0).rgb;
+        atomicAdd(&local_histogram[luminance_bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[index], atomicLoad(&local_histogram[index]));
+}
+
+@compute @workgroup_size(256)
+fn average(@builtin(local_invocation_index) index: u32) {
+    let count = atomicLoad(&histogram[index]);
+    weighted_sum[index] = f32(count) * f32(index);
+    atomicStore(&histogram[index], 0u);
+    workgroupBarrier();
+
+    for (var stride = 128u; stride > 0u; stride = stride >> 1u) {
+        if index < stride {
+            weighted_sum[index] += weighted_sum[index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if index == 0u {
+        let size = textureDimensions(hdr);
+        // `count` is the number of dark pixels for this invocation
+        let lit_pixels = max(f32(size.x * size.y) - f32(count), 1.0);
+        let mean_bin = weighted_sum[0] / lit_pixels - 1.0;
+        let target_luminance = exp2(mean_bin / 254.0 * params.log_luminance_range + params.min_log_luminance);
+        if luminance <= 0.0 {
+            luminance = target_luminance;
+        } else {
+            luminance += (target_luminance - luminance) * params.adaptation;
+        }
+    }
+}
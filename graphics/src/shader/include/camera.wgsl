@@ -0,0 +1,6 @@
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    target_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    aspect: f32,
+}
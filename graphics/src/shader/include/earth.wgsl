@@ -0,0 +1,8 @@
+struct EarthUniform {
+    radius: f32,
+    atmosphere_radius: f32,
+    rotation: f32,
+    _padding0: f32,
+    axis: vec3<f32>,
+    _padding1: f32,
+}
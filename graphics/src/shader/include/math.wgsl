@@ -0,0 +1,31 @@
+fn rotation_matrix(angle: f32, axis: vec3<f32>) -> mat3x3<f32> {
+    let c = cos(angle);
+    let s = sin(angle);
+    let oc = 1.0 - c;
+    let x = axis.x;
+    let y = axis.y;
+    let z = axis.z;
+    return mat3x3<f32>(
+        vec3<f32>(oc*x*x+c, oc*x*y-s*z, oc*x*z+s*y),
+        vec3<f32>(oc*x*y+s*z, oc*y*y+c, oc*y*z-s*x),
+        vec3<f32>(oc*x*z-s*y, oc*y*z+s*x, oc*z*z+c),
+    );
+}
+
+fn intersection_sphere(ray_origin: vec3<f32>, ray_direction: vec3<f32>, sphere_center: vec3<f32>, sphere_radius: f32) -> mat2x3<f32> {
+    let oc = ray_origin - sphere_center;
+    let a = dot(ray_direction, ray_direction)+0.000001;
+    let b = 2.0 * dot(oc, ray_direction);
+    let c = dot(oc, oc) - sphere_radius * sphere_radius;
+    let discriminant = b * b - 4.0 * a * c;
+
+    if (discriminant < 0.0) {
+        return mat2x3<f32>(sphere_center, sphere_center);
+    } else {
+        let t1 = (-b - sqrt(discriminant)) / (2.0 * a);
+        let t2 = (-b + sqrt(discriminant)) / (2.0 * a);
+        let p1 = ray_origin + t1 * ray_direction;
+        let p2 = ray_origin + t2 * ray_direction;
+        return mat2x3<f32>(p1, p2);
+    }
+}
@@ -0,0 +1,6 @@
+struct SunUniform {
+    position: vec3<f32>,
+    _padding0: u32,
+    color: vec3<f32>,
+    _padding1: u32,
+}
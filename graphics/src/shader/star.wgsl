@@ -1,9 +1,4 @@
-struct CameraUniform {
-    view_pos: vec4<f32>,
-    target_pos: vec4<f32>,
-    view_proj: mat4x4<f32>,
-    aspect: f32,
-}
+#include "include/camera.wgsl"
 
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
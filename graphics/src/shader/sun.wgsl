@@ -1,9 +1,4 @@
-struct CameraUniform {
-    view_pos: vec4<f32>,
-    target_pos: vec4<f32>,
-    view_proj: mat4x4<f32>,
-    aspect: f32,
-}
+#include "include/camera.wgsl"
 
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
@@ -34,6 +29,9 @@ fn vs_main(
     return out;
 }
 
+// Radiance of the solar disc relative to a lit surface; tone mapping brings it back to white
+const SUN_INTENSITY: f32 = 16.0;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
@@ -41,6 +39,6 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let potential_inner = 0.9;
     let brightness = pow(min(potential/potential_inner, 1.0), 16.0);
 
-    let object_color = vec4<f32>(1.0, 1.0, 0.98, brightness);
+    let object_color = vec4<f32>(vec3<f32>(1.0, 1.0, 0.98) * SUN_INTENSITY, brightness);
     return object_color;
 }
\ No newline at end of file
@@ -0,0 +1,90 @@
+struct Settings {
+    tonemapper: u32,
+    auto_exposure: u32,
+    // manual exposure, or compensation of the automatic exposure
+    exposure_ev: f32,
+    // 0 when the scene target is 8-bit and already clamped to the displayable range
+    hdr: u32,
+}
+
+@group(0) @binding(0)
+var hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> settings: Settings;
+// x: average scene luminance, adapted over time by histogram.wgsl
+@group(0) @binding(2)
+var<uniform> luminance: vec4<f32>;
+
+const TONEMAPPER_ACES: u32 = 0u;
+const TONEMAPPER_REINHARD: u32 = 1u;
+const TONEMAPPER_AGX: u32 = 2u;
+
+// Luminance that auto exposure maps to middle grey
+const KEY_VALUE: f32 = 0.18;
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // a single triangle covering the screen
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn reinhard(x: vec3<f32>) -> vec3<f32> {
+    return x / (1.0 + x);
+}
+
+// Polynomial approximation of AgX by Benjamin Wrensch
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn agx(x: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    );
+    let outset = mat3x3<f32>(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var color = clamp(log2(max(inset * x, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    color = agx_contrast((color - min_ev) / (max_ev - min_ev));
+    // AgX outputs display-encoded values, while the target applies the sRGB encoding itself
+    return pow(max(outset * color, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    var exposure = exp2(settings.exposure_ev);
+    if settings.auto_exposure != 0u {
+        exposure *= KEY_VALUE / max(luminance.x, 1e-4);
+    }
+
+    let color = textureLoad(hdr, vec2<i32>(position.xy), 0).rgb * exposure;
+    if settings.hdr == 0u {
+        return vec4<f32>(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+    }
+    switch settings.tonemapper {
+        case TONEMAPPER_REINHARD: {
+            return vec4<f32>(reinhard(color), 1.0);
+        }
+        case TONEMAPPER_AGX: {
+            return vec4<f32>(agx(color), 1.0);
+        }
+        default: {
+            return vec4<f32>(aces(color), 1.0);
+        }
+    }
+}
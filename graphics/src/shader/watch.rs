@@ -0,0 +1,131 @@
+use std::{collections::HashMap, time::Duration};
+
+use futures::channel::mpsc;
+use futures_timer::Delay;
+
+use crate::asset::AssetSource;
+
+use super::ShaderLibrary;
+
+// Time between two polls of the source
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A source the watcher can poll from its own task, which is a thread outside the web.
+#[cfg(not(target_arch = "wasm32"))]
+pub trait WatchSource: AssetSource + Send + 'static {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: AssetSource + Send + 'static> WatchSource for T {}
+
+/// A source the watcher can poll from its own task, which is a thread outside the web.
+#[cfg(target_arch = "wasm32")]
+pub trait WatchSource: AssetSource + 'static {}
+#[cfg(target_arch = "wasm32")]
+impl<T: AssetSource + 'static> WatchSource for T {}
+
+/// Polls an `AssetSource` laid out like `src/shader` on its own task for sources of a
+/// `ShaderLibrary` that changed since the last poll. The task stops with the watcher.
+pub struct ShaderWatcher {
+    changes: mpsc::UnboundedReceiver<Vec<(String, String)>>,
+}
+
+impl ShaderWatcher {
+    /// Changes are relative to the sources of `library`, so the first poll reports the
+    /// shaders that differ from the built-in ones.
+    pub fn new(source: impl WatchSource, library: &ShaderLibrary) -> Self {
+        let (sender, changes) = mpsc::unbounded();
+        let poller = Poller {
+            source: Box::new(source),
+            sources: library
+                .paths()
+                .filter_map(|path| Some((path.to_string(), library.source(path)?.to_string())))
+                .collect(),
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(move || futures::executor::block_on(poller.run(sender)));
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(poller.run(sender));
+        Self { changes }
+    }
+
+    /// Paths and sources that changed since the last call, in the order they changed;
+    /// returns at once with what the task has found so far.
+    pub fn changes(&mut self) -> Vec<(String, String)> {
+        let mut changes = Vec::new();
+        while let Ok(Some(batch)) = self.changes.try_next() {
+            changes.extend(batch);
+        }
+        changes
+    }
+}
+
+struct Poller {
+    source: Box<dyn WatchSource>,
+    sources: HashMap<String, String>,
+}
+
+impl Poller {
+    // Polls until the watcher is dropped
+    async fn run(mut self, sender: mpsc::UnboundedSender<Vec<(String, String)>>) {
+        while !sender.is_closed() {
+            let changes = self.poll().await;
+            if !changes.is_empty() && sender.unbounded_send(changes).is_err() {
+                break;
+            }
+            Delay::new(POLL_INTERVAL).await;
+        }
+    }
+
+    // Paths and sources that changed; shaders that cannot be fetched are skipped
+    async fn poll(&mut self) -> Vec<(String, String)> {
+        let mut changes = Vec::new();
+        for (path, current) in &mut self.sources {
+            let source = match self.source.fetch(path).await.and_then(|bytes| {
+                String::from_utf8(bytes).map_err(|_| anyhow::anyhow!("{} is not UTF-8", path))
+            }) {
+                Ok(source) => source,
+                Err(e) => {
+                    log::debug!("Failed to poll shader {}: {:?}", path, e);
+                    continue;
+                }
+            };
+            if source != *current {
+                *current = source.clone();
+                changes.push((path.clone(), source));
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::asset::MemorySource;
+
+    #[test]
+    fn reports_changed_shaders() {
+        let library = ShaderLibrary::default();
+        let edited = format!("{}\n// edited\n", library.source("tonemap.wgsl").unwrap());
+        let source = MemorySource::new()
+            .with(
+                "bloom.wgsl",
+                library.source("bloom.wgsl").unwrap().as_bytes().to_vec(),
+            )
+            .with("tonemap.wgsl", edited.clone().into_bytes());
+        let mut watcher = ShaderWatcher::new(source, &library);
+
+        let start = Instant::now();
+        let mut changes = watcher.changes();
+        while changes.is_empty() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+            changes = watcher.changes();
+        }
+        assert_eq!(changes, [("tonemap.wgsl".to_string(), edited)]);
+
+        // later polls only report further changes
+        std::thread::sleep(POLL_INTERVAL * 2);
+        assert!(watcher.changes().is_empty());
+    }
+}
@@ -1,101 +1,145 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+#[cfg(feature = "web")]
 use wasm_bindgen::prelude::*;
-use wgpu::{RenderPipelineDescriptor, SurfaceTarget};
+
+use futures::channel::mpsc;
+#[cfg(target_arch = "wasm32")]
+use futures::StreamExt;
+use wgpu::SurfaceTarget;
+
+#[cfg(feature = "hot-reload")]
+use std::collections::HashMap;
 
 use crate::{
-    camera::{geometry::CameraGeometry, perspective::CameraPerspective, Camera},
-    earth::{
-        model::{create_earth_and_atmosphere_model, AtmosphereModel, DrawModel},
-        property::EarthProperty,
-        vertex::ModelVertex,
-        Earth,
-    },
-    fetch::Fetcher,
-    key::{KeyState, KeyStateMap},
-    star::{Star, StarInstanceRaw},
-    sun::{
-        property::{SunProperty, SunVertex},
-        Sun,
-    },
+    action::{Action, ActionMap, InputProfile},
+    asset::AssetSource,
+    bloom::BloomSettings,
+    camera::controller::{CameraMode, OrbitView},
+    earth::texture::compression_features,
+    error::GraphicsError,
+    gamepad::GamepadState,
+    gesture::GestureRecognizer,
+    resource::MemoryReport,
+    scene::{clamp_sample_count, supported_sample_counts, Scene, SceneOptions},
+    tonemap::{hdr_format, Exposure, Tonemapper},
 };
+#[cfg(feature = "hot-reload")]
+use crate::{
+    pipeline::PipelineRebuild,
+    shader::{ShaderWatcher, WatchSource},
+};
+
+/// What happened to the GPU device, as reported to the device listener.
+#[cfg_attr(feature = "web", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceEvent {
+    /// The device stopped working; frames are skipped until it is restored
+    Lost,
+    /// A new device renders the scene again
+    Restored,
+    /// No device could be created again; the state has to be recreated
+    RestoreFailed,
+    /// A frame was skipped as the GPU ran out of memory; the device keeps working
+    OutOfMemory,
+}
+
+type DeviceListener = Box<dyn Fn(DeviceEvent, &str)>;
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "web", wasm_bindgen)]
 pub struct State {
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
     surface: wgpu::Surface<'static>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
-    key_states: KeyStateMap,
-    camera: Camera,
-
-    earth: Earth,
-    earth_render_pipeline: wgpu::RenderPipeline,
-
-    atmosphere_model: AtmosphereModel,
-    atmosphere_render_pipeline: wgpu::RenderPipeline,
-
-    star: Star,
-    star_render_pipeline: wgpu::RenderPipeline,
+    use_gl_instead: bool,
+    // set with the reason by the device lost callback, which may run on another thread
+    device_lost: Arc<Mutex<Option<String>>>,
+    // the reasons sent by the device lost callback, to pass on to the listener
+    lost_sender: mpsc::UnboundedSender<String>,
+    #[cfg(not(target_arch = "wasm32"))]
+    lost_receiver: mpsc::UnboundedReceiver<String>,
+    restore_failed: bool,
+    device_listener: Rc<RefCell<Option<DeviceListener>>>,
+    // the surface has no area, e.g. in a minimized window
+    minimized: bool,
+    actions: ActionMap,
+    gestures: GestureRecognizer,
+    sample_counts: Vec<u32>,
+    // where the scene was created from, to fetch it again for a new device
+    source: Box<dyn AssetSource>,
+    options: SceneOptions,
+    scene: Scene,
+    last_update: Option<f32>,
+    // seconds between the last two updates, which scrolling between them lasts
+    last_dt: f32,
+    #[cfg(feature = "hot-reload")]
+    shader_reload: ShaderReload,
+}
 
-    sun: Sun,
-    sun_render_pipeline: wgpu::RenderPipeline,
+// Pixels scrolled per line by wheels that scroll by lines
+#[cfg(feature = "web")]
+const WHEEL_LINE_HEIGHT: f64 = 16.0;
+
+// Seconds per unit of the `time` passed to `update`
+const TIME_UNIT: f32 = 1.0 / 60.0;
+
+#[cfg(feature = "hot-reload")]
+#[derive(Default)]
+struct ShaderReload {
+    watcher: Option<ShaderWatcher>,
+    // sources set since the last update, applied once `rebuild` is done
+    queued: Vec<(String, String)>,
+    // every source applied so far, applied again to the scene of a new device
+    applied: HashMap<String, String>,
+    rebuild: Option<PipelineRebuild>,
+    error: Option<String>,
 }
 
-#[wasm_bindgen]
 impl State {
-    pub(crate) async fn new(
-        canvas: web_sys::HtmlCanvasElement,
+    /// `source` is kept to fetch the resources again when a lost device is restored.
+    pub async fn new(
+        surface_target: impl Into<SurfaceTarget<'static>>,
+        width: u32,
+        height: u32,
         use_gl_instead: bool,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let width = canvas.width();
-        let height = canvas.height();
-
+        source: impl AssetSource + 'static,
+        options: &SceneOptions,
+    ) -> Result<Self, GraphicsError> {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
             ..Default::default()
         });
 
-        let surface_target = SurfaceTarget::Canvas(canvas);
         let surface = instance.create_surface(surface_target)?;
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
+        let adapter = request_adapter(&instance, &surface)
             .await
-            .expect("Failed to find an appropriate adapter");
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    required_features: wgpu::Features::empty(),
-                    required_limits: if use_gl_instead {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
-                    },
-                    label: None,
-                    memory_hints: wgpu::MemoryHints::default(),
-                },
-                None,
-            )
-            .await?;
+            .ok_or(GraphicsError::AdapterUnavailable)?;
+        let (device, queue) = request_device(&adapter, use_gl_instead).await?;
+        let device_lost = Arc::new(Mutex::new(None));
+        let (lost_sender, lost_receiver) = mpsc::unbounded();
+        watch_device_lost(&device, &device_lost, &lost_sender);
+        let device_listener = Rc::new(RefCell::new(None));
+        #[cfg(target_arch = "wasm32")]
+        report_device_lost(lost_receiver, &device_listener);
 
         let surface_caps: wgpu::SurfaceCapabilities = surface.get_capabilities(&adapter);
+        let unsupported = |message: &str| GraphicsError::UnsupportedSurface(message.to_string());
         let surface_format = surface_caps
             .formats
             .iter()
             .find(|f| f.is_srgb())
+            .or(surface_caps.formats.first())
             .copied()
-            .unwrap_or(
-                surface_caps
-                    .formats
-                    .first()
-                    .copied()
-                    .expect("No surface formats"),
-            );
+            .ok_or_else(|| unsupported("No surface formats"))?;
 
+        let (width, height) = clamp_surface_size(&device, width, height);
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
@@ -105,12 +149,12 @@ impl State {
                 .present_modes
                 .first()
                 .copied()
-                .expect("No present modes"),
+                .ok_or_else(|| unsupported("No present modes"))?,
             alpha_mode: surface_caps
                 .alpha_modes
                 .first()
                 .copied()
-                .expect("No alpha modes"),
+                .ok_or_else(|| unsupported("No alpha modes"))?,
             view_formats: if !surface_format.is_srgb() {
                 vec![surface_format.add_srgb_suffix()]
             } else {
@@ -119,303 +163,514 @@ impl State {
             desired_maximum_frame_latency: 2,
         };
 
-        let href = web_sys::window().unwrap().location().href().unwrap();
-        let fetcher = Fetcher::new(&href);
-
-        let (earth_model, atmosphere_model) =
-            create_earth_and_atmosphere_model(&device, &queue, &fetcher).await?;
-
-        let earth_property = EarthProperty::default();
-        let earth = Earth::new(&device, earth_model, earth_property.clone());
-
-        let perspective = CameraPerspective::new(
-            CameraGeometry::new(
-                (
-                    earth_property.radius * 4.0,
-                    earth_property.radius,
-                    -earth_property.radius * 1.0,
-                )
-                    .into(),
-                (0.0, 0.0, 0.0).into(),
-                cgmath::Vector3::unit_y(),
-            ),
-            50.0,
-            config.width as f32 / config.height as f32,
-            45.0,
-            0.1,
-            100.0,
-        );
-        let camera = Camera::new(&device, perspective);
-
-        let sun_property = SunProperty::new(
-            (
-                0.0,
-                0.0,
-                earth_property.get_distance_between_earth_and_sun(),
-            )
-                .into(),
-            (1.0, 1.0, 1.0).into(),
-        );
+        let sample_counts = supported_sample_counts(&adapter);
+        let options = SceneOptions {
+            sample_count: clamp_sample_count(options.sample_count, &sample_counts),
+            hdr_format: hdr_format(&adapter),
+            ..options.clone()
+        };
+        let scene = Scene::new(
+            &device,
+            &queue,
+            config.format.add_srgb_suffix(),
+            width,
+            height,
+            &source,
+            &options,
+        )
+        .await?;
+
+        Ok(Self {
+            instance,
+            adapter,
+            surface,
+            device,
+            queue,
+            config,
+            use_gl_instead,
+            device_lost,
+            lost_sender,
+            #[cfg(not(target_arch = "wasm32"))]
+            lost_receiver,
+            restore_failed: false,
+            device_listener,
+            minimized: false,
+            actions: ActionMap::new(InputProfile::default()),
+            gestures: GestureRecognizer::new(),
+            sample_counts,
+            source: Box::new(source),
+            options,
+            scene,
+            last_update: None,
+            last_dt: 0.0,
+            #[cfg(feature = "hot-reload")]
+            shader_reload: ShaderReload::default(),
+        })
+    }
 
-        let sun = Sun::new(&device, sun_property);
+    /// `listener` is called with what happened to the GPU device and why. On the web, a
+    /// lost device is reported as soon as it is lost; elsewhere by the next `update`.
+    pub fn on_device_event(&mut self, listener: impl Fn(DeviceEvent, &str) + 'static) {
+        *self.device_listener.borrow_mut() = Some(Box::new(listener));
+    }
 
-        let blend_state = wgpu::BlendState {
-            color: wgpu::BlendComponent {
-                src_factor: wgpu::BlendFactor::SrcAlpha,
-                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                operation: wgpu::BlendOperation::Add,
-            },
-            alpha: wgpu::BlendComponent {
-                src_factor: wgpu::BlendFactor::Zero,
-                dst_factor: wgpu::BlendFactor::One,
-                operation: wgpu::BlendOperation::Add,
-            },
-        };
+    fn notify(&self, event: DeviceEvent, message: &str) {
+        if let Some(listener) = &*self.device_listener.borrow() {
+            listener(event, message);
+        }
+    }
 
-        let primitive = wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
-            polygon_mode: wgpu::PolygonMode::Fill,
-            unclipped_depth: false,
-            conservative: false,
-        };
+    // Passes on the losses the device lost callback sent from wherever it ran
+    #[cfg(not(target_arch = "wasm32"))]
+    fn report_device_lost(&mut self) {
+        while let Ok(Some(message)) = self.lost_receiver.try_next() {
+            self.notify(DeviceEvent::Lost, &message);
+        }
+    }
 
-        let multisample = wgpu::MultisampleState {
-            count: 1,
-            mask: !0,
-            alpha_to_coverage_enabled: false,
+    // Creates the device, the surface configuration and the scene again, keeping the
+    // camera and the settings changed since the state was created
+    async fn restore_device(&mut self) -> Result<(), GraphicsError> {
+        let (device, queue) = match request_device(&self.adapter, self.use_gl_instead).await {
+            Ok(device) => device,
+            // the adapter goes away too when the GPU is reset or removed
+            Err(_) => {
+                self.adapter = request_adapter(&self.instance, &self.surface)
+                    .await
+                    .ok_or(GraphicsError::AdapterUnavailable)?;
+                request_device(&self.adapter, self.use_gl_instead).await?
+            }
         };
+        self.surface.configure(&device, &self.config);
+
+        self.sample_counts = supported_sample_counts(&self.adapter);
+        let options = SceneOptions {
+            sample_count: clamp_sample_count(self.scene.sample_count(), &self.sample_counts),
+            hdr_format: hdr_format(&self.adapter),
+            tonemapper: self.scene.tonemapper(),
+            exposure: self.scene.exposure(),
+            bloom: self.scene.bloom(),
+            camera_mode: self.scene.camera_mode(),
+            ..self.options.clone()
+        };
+        let mut scene = Scene::new(
+            &device,
+            &queue,
+            self.config.format.add_srgb_suffix(),
+            self.config.width,
+            self.config.height,
+            self.source.as_ref(),
+            &options,
+        )
+        .await?;
+        std::mem::swap(
+            &mut scene.camera.perspective,
+            &mut self.scene.camera.perspective,
+        );
 
-        let (star, star_render_pipeline) = {
-            let shader = device.create_shader_module(wgpu::include_wgsl!("./shader/star.wgsl"));
-            let render_pipeline_layout =
-                device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("star_render_pipeline_layout"),
-                    bind_group_layouts: &[&camera.bind_group_layout],
-                    push_constant_ranges: &[],
-                });
-
-            let star = Star::new(&device, &queue);
-
-            let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-                label: Some("star_render_pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_main",
-                    buffers: &[StarInstanceRaw::desc()],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "fs_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: config.format.add_srgb_suffix(),
-                        blend: Some(blend_state),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                }),
-                primitive,
-                depth_stencil: None,
-                multisample,
-                multiview: None,
-                cache: None,
-            });
+        *self.device_lost.lock().unwrap() = None;
+        watch_device_lost(&device, &self.device_lost, &self.lost_sender);
+        self.device = device;
+        self.queue = queue;
+        self.scene = scene;
+        #[cfg(feature = "hot-reload")]
+        {
+            // the new scene starts from the built-in shaders
+            let reload = &mut self.shader_reload;
+            reload.rebuild = None;
+            let queued = std::mem::take(&mut reload.queued);
+            reload.queued = reload.applied.clone().into_iter().chain(queued).collect();
+        }
+        Ok(())
+    }
 
-            (star, render_pipeline)
-        };
+    /// Polls `source` for changed shaders in the background; `update` reloads them.
+    #[cfg(feature = "hot-reload")]
+    pub fn watch_shaders(&mut self, source: impl WatchSource) {
+        self.shader_reload.watcher = Some(self.scene.watch_shaders(source));
+    }
 
-        let earth_render_pipeline = {
-            let shader = device.create_shader_module(wgpu::include_wgsl!("shader/earth.wgsl"));
-
-            let render_pipeline_layout =
-                device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("earth_render_pipeline_layout"),
-                    bind_group_layouts: &[
-                        &camera.bind_group_layout,
-                        &earth.model.texture_bind_group_layout,
-                        &sun.uniform_bind_group_layout,
-                        &earth.uniform_bind_group_layout,
-                    ],
-                    push_constant_ranges: &[],
-                });
-
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("earth_render_pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_main",
-                    buffers: &[ModelVertex::desc()],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "fs_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: config.format.add_srgb_suffix(),
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                }),
-                primitive,
-                depth_stencil: None,
-                multisample,
-                multiview: None,
-                cache: None,
-            })
-        };
+    // Swaps in the pipelines the device has validated, then starts rebuilding those of
+    // the shaders changed since; never waits for the device
+    #[cfg(feature = "hot-reload")]
+    fn reload_shaders(&mut self) {
+        let reload = &mut self.shader_reload;
+        if let Some(rebuild) = &mut reload.rebuild {
+            let Some(result) = self.scene.finish_rebuild(rebuild) else {
+                return;
+            };
+            reload.rebuild = None;
+            match result {
+                Ok(0) => reload.error = None,
+                Ok(count) => {
+                    log::info!("Reloaded {} pipelines", count);
+                    reload.error = None;
+                }
+                Err(e) => {
+                    log::error!("Failed to reload shaders: {}", e);
+                    reload.error = Some(e.to_string());
+                }
+            }
+        }
 
-        let atmosphere_render_pipeline = {
-            let shader = device.create_shader_module(wgpu::include_wgsl!("shader/atmosphere.wgsl"));
-
-            let render_pipeline_layout =
-                device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("atmosphere_render_pipeline_layout"),
-                    bind_group_layouts: &[
-                        &camera.bind_group_layout,
-                        &sun.uniform_bind_group_layout,
-                        &earth.uniform_bind_group_layout,
-                    ],
-                    push_constant_ranges: &[],
-                });
-
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("atmosphere_render_pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_main",
-                    buffers: &[ModelVertex::desc()],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "fs_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: config.format.add_srgb_suffix(),
-                        blend: Some(blend_state),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                }),
-                primitive,
-                depth_stencil: None,
-                multisample,
-                multiview: None,
-                cache: None,
-            })
-        };
+        if let Some(watcher) = &mut reload.watcher {
+            reload.queued.extend(watcher.changes());
+        }
+        if reload.queued.is_empty() {
+            return;
+        }
+        let sources = std::mem::take(&mut reload.queued);
+        reload.applied.extend(sources.iter().cloned());
+        match self.scene.rebuild_shaders(&self.device, &sources) {
+            Ok(rebuild) => reload.rebuild = Some(rebuild),
+            Err(e) => {
+                log::error!("Failed to reload shaders: {}", e);
+                reload.error = Some(e.to_string());
+            }
+        }
+    }
 
-        let sun_render_pipeline = {
-            let shader = device.create_shader_module(wgpu::include_wgsl!("shader/sun.wgsl"));
-            let render_pipeline_layout =
-                device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("sun_render_pipeline_layout"),
-                    bind_group_layouts: &[&camera.bind_group_layout],
-                    push_constant_ranges: &[],
-                });
-
-            device.create_render_pipeline(&RenderPipelineDescriptor {
-                label: Some("sun_render_pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_main",
-                    buffers: &[SunVertex::desc()],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "fs_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: config.format.add_srgb_suffix(),
-                        blend: Some(blend_state),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                }),
-                primitive,
-                depth_stencil: None,
-                multisample,
-                multiview: None,
-                cache: None,
-            })
-        };
+    /// The current state of the gamepads, e.g. from gilrs once per update. On the web,
+    /// `update` reads the Gamepad API itself.
+    pub fn gamepad_input(&mut self, gamepads: &[GamepadState]) {
+        self.actions.gamepad_input(gamepads);
+    }
 
-        Ok(Self {
-            surface,
-            device,
-            queue,
-            config,
-            key_states: KeyStateMap::new(),
-            camera,
-            sun,
-            earth_render_pipeline,
-            earth,
-            atmosphere_render_pipeline,
-            atmosphere_model,
-            star_render_pipeline,
-            star,
-            sun_render_pipeline,
-        })
+    pub fn memory_report(&self) -> MemoryReport {
+        self.scene.memory_report()
     }
+}
 
+#[cfg_attr(feature = "web", wasm_bindgen)]
+impl State {
+    #[cfg(feature = "web")]
     #[wasm_bindgen]
     pub fn key_event(&mut self, event: &web_sys::KeyboardEvent) {
-        let released = event.type_() == "keyup";
-        let key = event.key();
-        if released {
-            self.key_states.insert(key, KeyState::Release);
-        } else {
-            if self.key_states.get(&key).is_some() {
-                return;
-            }
-            self.key_states.insert(key, KeyState::Press);
+        self.key_input(&event.code(), event.type_() == "keyup");
+    }
+
+    /// `code` names the physical key like `KeyboardEvent.code`, e.g. `KeyW` or `ShiftLeft`.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn key_input(&mut self, code: &str, released: bool) {
+        self.actions.key_input(code, released);
+    }
+
+    /// The key bindings as JSON; see `set_input_profile`.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn input_profile(&self) -> String {
+        self.actions.profile().to_json()
+    }
+
+    /// Replaces the key bindings with a profile like
+    /// `{"bindings":[{"action":"MoveUp","code":"KeyW","modifiers":["Shift"]}]}`.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn set_input_profile(&mut self, json: &str) -> Result<(), String> {
+        let profile = InputProfile::from_json(json).map_err(|e| e.to_string())?;
+        self.actions.set_profile(profile);
+        Ok(())
+    }
+
+    /// Handles `pointerdown`, `pointermove`, `pointerup` and `pointercancel` of the canvas.
+    #[cfg(feature = "web")]
+    #[wasm_bindgen]
+    pub fn pointer_event(&mut self, event: &web_sys::PointerEvent) {
+        let (id, x, y) = (
+            event.pointer_id(),
+            event.offset_x() as f32,
+            event.offset_y() as f32,
+        );
+        match event.type_().as_str() {
+            "pointerdown" => self.pointer_down(id, x, y),
+            "pointermove" => self.pointer_move(id, x, y),
+            "pointerup" | "pointercancel" => self.pointer_up(id),
+            _ => {}
         }
     }
 
+    #[cfg(feature = "web")]
     #[wasm_bindgen]
+    pub fn wheel_event(&mut self, event: &web_sys::WheelEvent) {
+        let delta = match event.delta_mode() {
+            web_sys::WheelEvent::DOM_DELTA_LINE => event.delta_y() * WHEEL_LINE_HEIGHT,
+            web_sys::WheelEvent::DOM_DELTA_PAGE => event.delta_y() * self.config.height as f64,
+            _ => event.delta_y(),
+        };
+        self.wheel(
+            event.offset_x() as f32,
+            event.offset_y() as f32,
+            delta as f32,
+        );
+    }
+
+    /// Pointer positions are in pixels from the top left corner of the surface;
+    /// `id` tells touches apart.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn pointer_down(&mut self, id: i32, x: f32, y: f32) {
+        self.gestures.pointer_down(id, (x, y).into());
+    }
+
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn pointer_move(&mut self, id: i32, x: f32, y: f32) {
+        self.gestures.pointer_move(id, (x, y).into());
+    }
+
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn pointer_up(&mut self, id: i32) {
+        self.gestures.pointer_up(id);
+    }
+
+    /// `delta` is in pixels, positive when scrolling down, which zooms out.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn wheel(&mut self, x: f32, y: f32, delta: f32) {
+        self.gestures.wheel((x, y).into(), delta);
+    }
+
+    #[cfg_attr(feature = "web", wasm_bindgen)]
     pub fn leave(&mut self) {
-        self.key_states.purge();
+        self.actions.purge();
+        self.gestures.cancel();
     }
 
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "web", wasm_bindgen)]
     pub fn scroll_to_right(&mut self) {
-        self.camera.perspective.scroll_to_right();
+        self.scene.camera.perspective.scroll_to_right(self.last_dt);
     }
 
     pub fn scroll_to_left(&mut self) {
-        self.camera.perspective.scroll_to_left();
+        self.scene.camera.perspective.scroll_to_left(self.last_dt);
     }
 
+    /// Polls the shaders under `url`, such as a development server serving `src/shader`.
+    #[cfg(all(feature = "web", feature = "hot-reload"))]
     #[wasm_bindgen]
-    pub async fn update(&mut self, _time: f32) {
-        self.camera.perspective.process_events(&self.key_states);
-        self.camera.perspective.tween(0.15);
-        self.camera.enque_update(&self.queue);
-        self.earth.property.rotate(0.001);
-        self.earth.enque_update_uniform(&self.queue);
-        self.key_states.update();
+    pub fn watch_shaders_at(&mut self, url: &str) -> Result<(), String> {
+        let source = crate::asset::HttpSource::new(url).map_err(|e| e.to_string())?;
+        self.watch_shaders(source);
+        Ok(())
+    }
+
+    /// Replaces a shader such as `atmosphere.wgsl` or `include/sun.wgsl` during the next
+    /// `update`; if the affected pipelines do not compile, the error is kept in `shader_error`.
+    #[cfg(feature = "hot-reload")]
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn set_shader_source(&mut self, path: String, source: String) {
+        self.shader_reload.queued.push((path, source));
+    }
+
+    /// Error of the last shader reload, until a reload succeeds.
+    #[cfg(feature = "hot-reload")]
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn shader_error(&self) -> Option<String> {
+        self.shader_reload.error.clone()
     }
 
+    /// `listener(event, message)` is called with a `DeviceEvent` when the GPU device is lost,
+    /// and again once it is restored or cannot be, or when a frame runs out of memory.
+    #[cfg(feature = "web")]
     #[wasm_bindgen]
+    pub fn set_device_listener(&mut self, listener: js_sys::Function) {
+        self.on_device_event(move |event, message| {
+            let _ = listener.call2(
+                &JsValue::NULL,
+                &JsValue::from(event),
+                &JsValue::from(message),
+            );
+        });
+    }
+
+    /// Whether the GPU device is lost, until `restore` replaces it.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.lock().unwrap().is_some()
+    }
+
+    /// Creates the device and the scene again once the device is lost; resolves to whether
+    /// a device works. No other method may be called until it resolves.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub async fn restore(&mut self) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.report_device_lost();
+        if !self.is_device_lost() {
+            return true;
+        }
+        if self.restore_failed {
+            return false;
+        }
+        match self.restore_device().await {
+            Ok(()) => {
+                log::info!("Restored the GPU device");
+                self.notify(DeviceEvent::Restored, "");
+                true
+            }
+            Err(e) => {
+                log::error!("Failed to restore the GPU device: {}", e);
+                self.restore_failed = true;
+                self.notify(DeviceEvent::RestoreFailed, &e.to_string());
+                false
+            }
+        }
+    }
+
+    /// Updates the scene, unless the device is lost and has to be restored first. `time`
+    /// counts 60ths of a second; the camera moves by the time passed since the previous update.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn update(&mut self, time: f32) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.report_device_lost();
+        if self.is_device_lost() {
+            // the camera does not jump by the time spent restoring
+            self.last_update = Some(time);
+            return;
+        }
+
+        #[cfg(feature = "hot-reload")]
+        self.reload_shaders();
+
+        let dt = self
+            .last_update
+            .map_or(0.0, |last| (time - last) * TIME_UNIT);
+        self.last_update = Some(time);
+        self.last_dt = dt;
+
+        #[cfg(feature = "web")]
+        self.actions.gamepad_input(&crate::gamepad::read_gamepads());
+        self.scene
+            .camera
+            .perspective
+            .process_events(&self.actions, dt);
+        if self.actions.just_pressed(Action::ToggleCameraMode) {
+            self.scene.set_camera_mode(match self.scene.camera_mode() {
+                CameraMode::FreeFly => CameraMode::Orbit,
+                CameraMode::Orbit => CameraMode::FreeFly,
+            });
+        }
+        let (width, height) = (self.config.width as f32, self.config.height as f32);
+        for gesture in self.gestures.update(dt) {
+            self.scene
+                .camera
+                .perspective
+                .apply_gesture(gesture, width, height);
+        }
+        self.scene.update(&self.queue, dt);
+        self.actions.update();
+    }
+
+    /// Bytes of GPU memory used by meshes and textures.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn gpu_memory_bytes(&self) -> f64 {
+        self.memory_report().total_bytes() as f64
+    }
+
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn sample_count(&self) -> u32 {
+        self.scene.sample_count()
+    }
+
+    /// Changes the MSAA sample count (1, 2, 4 or 8) and returns the count actually used,
+    /// which is lower if the adapter does not support the requested one.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn set_sample_count(&mut self, sample_count: u32) -> u32 {
+        let sample_count = clamp_sample_count(sample_count, &self.sample_counts);
+        self.scene.set_sample_count(&self.device, sample_count);
+        sample_count
+    }
+
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn camera_mode(&self) -> CameraMode {
+        self.scene.camera_mode()
+    }
+
+    /// The camera eases over to the new mode; the orbit mode starts above where it is.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        self.scene.set_camera_mode(mode);
+    }
+
+    /// Eases over to `view` in the orbit mode.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn fly_to(&mut self, view: OrbitView) {
+        self.scene.fly_to(view);
+    }
+
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn tonemapper(&self) -> Tonemapper {
+        self.scene.tonemapper()
+    }
+
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn set_tonemapper(&mut self, tonemapper: Tonemapper) {
+        self.scene.set_tonemapper(tonemapper);
+    }
+
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn exposure_ev(&self) -> f32 {
+        self.scene.exposure().ev
+    }
+
+    /// Exposure in stops; with auto exposure on, it compensates the metered exposure instead.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn set_exposure_ev(&mut self, ev: f32) {
+        let exposure = self.scene.exposure();
+        self.scene.set_exposure(Exposure { ev, ..exposure });
+    }
+
+    /// Returns whether auto exposure is on, as it needs compute shaders (not available on WebGL).
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn set_auto_exposure(&mut self, auto: bool) -> bool {
+        let exposure = self.scene.exposure();
+        self.scene.set_exposure(Exposure { auto, ..exposure });
+        self.scene.exposure().auto
+    }
+
+    /// A zero `intensity` turns bloom off.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
+    pub fn set_bloom(&mut self, threshold: f32, intensity: f32, radius: f32) {
+        self.scene.set_bloom(BloomSettings {
+            threshold,
+            intensity,
+            radius,
+        });
+    }
+
+    /// A zero size, e.g. of a minimized window, pauses rendering until the next resize.
+    /// Sizes beyond what the device supports are clamped.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
     pub fn resize(&mut self, width: u32, height: u32) {
+        self.minimized = width == 0 || height == 0;
+        if self.minimized {
+            return;
+        }
+        let (width, height) = clamp_surface_size(&self.device, width, height);
         self.config.width = width;
         self.config.height = height;
         self.surface.configure(&self.device, &self.config);
-        self.camera
-            .perspective
-            .update_aspect(width as f32 / height as f32);
+        self.scene.resize(&self.device, width, height);
     }
 
-    #[wasm_bindgen]
+    /// Skips the frame if there is nothing to present to, or while the device is lost.
+    #[cfg_attr(feature = "web", wasm_bindgen)]
     pub fn render(&mut self) {
-        let output = self.surface.get_current_texture().unwrap();
+        if self.minimized || self.is_device_lost() {
+            return;
+        }
+        let output = match self.surface.get_current_texture() {
+            Ok(output) => output,
+            // the surface no longer matches the window, e.g. after it moved to another display
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                self.surface.configure(&self.device, &self.config);
+                return;
+            }
+            Err(wgpu::SurfaceError::Timeout) => {
+                log::warn!("Timed out waiting for a frame");
+                return;
+            }
+            // the memory may be freed by the next frame, which tries again
+            Err(wgpu::SurfaceError::OutOfMemory) => {
+                log::error!("Out of memory acquiring a frame");
+                self.notify(DeviceEvent::OutOfMemory, "Out of memory acquiring a frame");
+                return;
+            }
+        };
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor {
             format: Some(self.config.format.add_srgb_suffix()),
             ..Default::default()
@@ -425,61 +680,97 @@ impl State {
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.269 / 255.0,
-                            g: 0.388 / 255.0,
-                            b: 0.342 / 255.0,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
+        self.scene.render(&mut encoder, &view);
 
-            render_pass.set_pipeline(&self.star_render_pipeline);
-            render_pass.set_vertex_buffer(0, self.star.instance_buffer.slice(..));
-            render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
-            render_pass
-                .set_index_buffer(self.star.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(
-                0..self.star.num_indices,
-                0,
-                0..self.star.instances.len() as u32,
-            );
+        self.queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+    }
+}
 
-            render_pass.set_pipeline(&self.sun_render_pipeline);
-            render_pass.set_vertex_buffer(0, self.sun.vertex_buffer.slice(..));
-            render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
-            render_pass.draw(0..3, 0..1);
-
-            render_pass.set_pipeline(&self.earth_render_pipeline);
-            render_pass.draw_earth_model(
-                &self.earth.model,
-                &self.camera.bind_group,
-                &self.sun.uniform_bind_group,
-                &self.earth.uniform_bind_group,
-            );
+async fn request_adapter(
+    instance: &wgpu::Instance,
+    surface: &wgpu::Surface<'static>,
+) -> Option<wgpu::Adapter> {
+    instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: Some(surface),
+            force_fallback_adapter: false,
+        })
+        .await
+}
 
-            render_pass.set_pipeline(&self.atmosphere_render_pipeline);
-            render_pass.draw_atmosphere_model(
-                &self.atmosphere_model,
-                &self.camera.bind_group,
-                &self.sun.uniform_bind_group,
-                &self.earth.uniform_bind_group,
-            );
+async fn request_device(
+    adapter: &wgpu::Adapter,
+    use_gl_instead: bool,
+) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                required_features: compression_features(adapter.features()),
+                required_limits: if use_gl_instead {
+                    wgpu::Limits::downlevel_webgl2_defaults()
+                } else {
+                    wgpu::Limits::default()
+                },
+                label: None,
+                memory_hints: wgpu::MemoryHints::default(),
+            },
+            None,
+        )
+        .await
+}
+
+// Records why the device is lost and sends it to be reported, unless the state dropped
+// the device itself
+fn watch_device_lost(
+    device: &wgpu::Device,
+    device_lost: &Arc<Mutex<Option<String>>>,
+    sender: &mpsc::UnboundedSender<String>,
+) {
+    let device_lost = device_lost.clone();
+    let sender = sender.clone();
+    device.set_device_lost_callback(move |reason, message| {
+        if matches!(
+            reason,
+            wgpu::DeviceLostReason::Dropped | wgpu::DeviceLostReason::ReplacedCallback
+        ) {
+            return;
         }
+        log::error!("GPU device lost ({:?}): {}", reason, message);
+        *device_lost.lock().unwrap() = Some(message.clone());
+        let _ = sender.unbounded_send(message);
+    });
+}
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+// Tells the listener about each loss as soon as it is sent; the callback runs on the
+// main thread between calls into the state here, and so does this task
+#[cfg(target_arch = "wasm32")]
+fn report_device_lost(
+    mut receiver: mpsc::UnboundedReceiver<String>,
+    listener: &Rc<RefCell<Option<DeviceListener>>>,
+) {
+    let listener = listener.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        while let Some(message) = receiver.next().await {
+            if let Some(listener) = &*listener.borrow() {
+                listener(DeviceEvent::Lost, &message);
+            }
+        }
+    });
+}
+
+fn clamp_surface_size(device: &wgpu::Device, width: u32, height: u32) -> (u32, u32) {
+    let max = device.limits().max_texture_dimension_2d;
+    let clamped = (width.clamp(1, max), height.clamp(1, max));
+    if clamped != (width, height) {
+        log::warn!(
+            "{}x{} is not supported by the device; using {}x{}",
+            width,
+            height,
+            clamped.0,
+            clamped.1
+        );
     }
+    clamped
 }
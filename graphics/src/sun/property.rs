@@ -1,3 +1,5 @@
+use crate::layout::uniform_layout;
+
 pub struct SunProperty {
     position: cgmath::Point3<f32>,
     color: cgmath::Point3<f32>,
@@ -12,6 +14,10 @@ pub struct SunUniform {
     _padding1: u32,
 }
 
+uniform_layout!(SunUniform = "SunUniform" in "include/sun.wgsl" {
+    position, _padding0, color, _padding1,
+});
+
 pub type SunVertex = SunUniform;
 
 impl SunProperty {
@@ -20,19 +26,19 @@ impl SunProperty {
     }
 
     pub fn build_uniform(&self) -> SunUniform {
-        return SunUniform {
+        SunUniform {
             position: self.position.into(),
             color: self.color.into(),
             ..SunUniform::default()
-        };
+        }
     }
 
     pub fn build_vertex(&self) -> SunVertex {
-        return SunVertex {
+        SunVertex {
             position: self.position.into(),
             color: self.color.into(),
             ..SunVertex::default()
-        };
+        }
     }
 }
 
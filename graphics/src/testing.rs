@@ -0,0 +1,114 @@
+// Fixtures shared by the tests
+
+use base64::Engine;
+
+use crate::asset::MemorySource;
+
+/// The committed Earth model, with a generated stand-in for the diffuse texture,
+/// which is too large to commit.
+pub fn earth_resources() -> MemorySource {
+    let texture = image::RgbaImage::from_fn(64, 32, |x, y| {
+        image::Rgba([(x * 4) as u8, (y * 8) as u8, 160, 255])
+    });
+    let mut png = std::io::Cursor::new(Vec::new());
+    texture
+        .write_to(&mut png, image::ImageFormat::Png)
+        .expect("PNG encoding into memory does not fail");
+
+    MemorySource::new()
+        .with(
+            "resources/earth/earth.obj",
+            &include_bytes!("../resources/earth/earth.obj")[..],
+        )
+        .with(
+            "resources/earth/earth.mtl",
+            &include_bytes!("../resources/earth/earth.mtl")[..],
+        )
+        .with("resources/earth/earth_diff.png", png.into_inner())
+}
+
+/// A device of any adapter, or None on machines without a GPU or software renderer,
+/// where the tests needing it pass without checking anything.
+pub fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    futures::executor::block_on(async {
+        let Some(adapter) = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+        else {
+            eprintln!("Skipping, no adapter");
+            return None;
+        };
+        let device = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    required_features: crate::earth::texture::compression_features(
+                        adapter.features(),
+                    ),
+                    required_limits: wgpu::Limits::downlevel_defaults()
+                        .using_resolution(adapter.limits()),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .expect("the adapter provides a device with its own limits");
+        Some(device)
+    })
+}
+
+/// A glTF document with a triangle per entry of `materials`, each a primitive
+/// with that material: 0 is red, metallic and emissive, 1 is blue.
+pub fn gltf_with_materials(materials: &[usize]) -> String {
+    let mut buffer = Vec::new();
+    for value in [
+        0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, // positions
+        0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, // normals
+        0.0, 0.0, 1.0, 0.0, 0.0, 1.0, // texture coordinates
+    ] {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    let primitives = materials
+        .iter()
+        .map(|material| {
+            serde_json::json!({
+                "attributes": { "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2 },
+                "material": material,
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "asset": { "version": "2.0" },
+        "buffers": [{
+            "byteLength": buffer.len(),
+            "uri": format!(
+                "data:application/octet-stream;base64,{}",
+                base64::engine::general_purpose::STANDARD.encode(&buffer)
+            ),
+        }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 72, "byteLength": 24 },
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+              "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] },
+            { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3" },
+            { "bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC2" },
+        ],
+        "materials": [
+            {
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [1.0, 0.0, 0.0, 1.0],
+                    "metallicFactor": 0.25,
+                    "roughnessFactor": 0.75,
+                },
+                "emissiveFactor": [0.5, 0.5, 0.0],
+            },
+            { "pbrMetallicRoughness": { "baseColorFactor": [0.0, 0.0, 1.0, 1.0] } },
+        ],
+        "meshes": [{ "primitives": primitives }],
+    })
+    .to_string()
+}
@@ -0,0 +1,503 @@
+#[cfg(feature = "web")]
+use wasm_bindgen::prelude::*;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    layout::uniform_layout,
+    pipeline::{ComputeDesc, ComputeId, MaterialDesc, MaterialId, PipelineCache, RenderTargets},
+    shader::{Shader, ShaderError},
+};
+
+/// Format of the target the scene is rendered into before tone mapping.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Used instead of `HDR_FORMAT` where the adapter cannot blend into it, as on WebGL2 without
+/// float blending. Colors are clamped to [0, 1], so they are exposed but not tone mapped.
+pub const LDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// `HDR_FORMAT` if `adapter` can render and blend into it, as the atmosphere and the sun do,
+/// otherwise `LDR_FORMAT`.
+pub fn hdr_format(adapter: &wgpu::Adapter) -> wgpu::TextureFormat {
+    let features = adapter.get_texture_format_features(HDR_FORMAT);
+    if features
+        .allowed_usages
+        .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+        && features
+            .flags
+            .contains(wgpu::TextureFormatFeatureFlags::BLENDABLE)
+    {
+        HDR_FORMAT
+    } else {
+        log::warn!("{:?} is not blendable; rendering without HDR", HDR_FORMAT);
+        LDR_FORMAT
+    }
+}
+
+/// Maps the HDR colors of the scene to the displayable range.
+#[cfg_attr(feature = "web", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tonemapper {
+    #[default]
+    Aces = 0,
+    Reinhard = 1,
+    Agx = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Exposure {
+    /// Exposure in EV, or the compensation added to the automatic exposure
+    pub ev: f32,
+    /// Adapts the exposure to the average luminance of the frame;
+    /// ignored where compute shaders are not available (WebGL)
+    pub auto: bool,
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self {
+            ev: 0.0,
+            auto: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct SettingsUniform {
+    tonemapper: u32,
+    auto_exposure: u32,
+    exposure_ev: f32,
+    hdr: u32,
+}
+
+uniform_layout!(SettingsUniform = "Settings" in "tonemap.wgsl" {
+    tonemapper, auto_exposure, exposure_ev, hdr,
+});
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct HistogramParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    _padding: f32,
+}
+
+uniform_layout!(HistogramParams = "Params" in "histogram.wgsl" {
+    min_log_luminance, log_luminance_range, adaptation, _padding,
+});
+
+const HISTOGRAM_PARAMS: HistogramParams = HistogramParams {
+    min_log_luminance: -8.0,
+    log_luminance_range: 12.0,
+    adaptation: 0.05,
+    _padding: 0.0,
+};
+
+/// Final pass of a frame, which tone maps and exposes the HDR target into the output view.
+pub struct TonemapPass {
+    tonemapper: Tonemapper,
+    // false when the scene is rendered into `LDR_FORMAT`, which needs no tone mapping
+    hdr: bool,
+    exposure: Exposure,
+    settings_buffer: wgpu::Buffer,
+    // average luminance, written by the histogram pass and read as a uniform
+    luminance_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    material: MaterialId,
+    histogram: Option<HistogramPass>,
+}
+
+impl TonemapPass {
+    /// Registers the material of the pass in `pipelines`, which rebuilds it when
+    /// `tonemap.wgsl` or `histogram.wgsl` changes; `format` is the format of the output views
+    /// and `hdr_format` the one of `hdr_view`.
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        format: wgpu::TextureFormat,
+        hdr_format: wgpu::TextureFormat,
+        hdr_view: &wgpu::TextureView,
+        tonemapper: Tonemapper,
+        exposure: Exposure,
+    ) -> Result<Self, ShaderError> {
+        let luminance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("luminance_buffer"),
+            contents: bytemuck::cast_slice(&[0.0f32; 4]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::STORAGE,
+        });
+
+        // WebGL has no compute shaders, so it gets manual exposure only
+        let limits = device.limits();
+        let histogram = if limits.max_compute_workgroups_per_dimension > 0
+            && limits.max_storage_buffers_per_shader_stage >= 2
+        {
+            Some(HistogramPass::new(
+                device,
+                pipelines,
+                hdr_view,
+                &luminance_buffer,
+            )?)
+        } else {
+            None
+        };
+        let exposure = Exposure {
+            auto: exposure.auto && histogram.is_some(),
+            ..exposure
+        };
+
+        let hdr = hdr_format != LDR_FORMAT;
+        let settings = SettingsUniform {
+            tonemapper: tonemapper as u32,
+            auto_exposure: exposure.auto as u32,
+            exposure_ev: exposure.ev,
+            hdr: hdr as u32,
+        };
+        let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("tonemap_settings_buffer"),
+            contents: bytemuck::cast_slice(&[settings]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("tonemap_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                uniform_layout_entry(1),
+                uniform_layout_entry(2),
+            ],
+        });
+
+        let material = pipelines.register(
+            device,
+            &MaterialDesc {
+                shader: Shader::new("tonemap.wgsl"),
+                fragment_entry: "fs_main",
+                bind_group_layouts: &[&bind_group_layout],
+                vertex_buffers: &[],
+                blend: wgpu::BlendState::REPLACE,
+                depth_test: None,
+                cull_mode: None,
+                targets: Some(RenderTargets {
+                    format,
+                    depth_format: None,
+                    sample_count: 1,
+                }),
+            },
+        )?;
+
+        let bind_group = create_bind_group(
+            device,
+            &bind_group_layout,
+            hdr_view,
+            &settings_buffer,
+            &luminance_buffer,
+        );
+
+        Ok(Self {
+            tonemapper,
+            hdr,
+            exposure,
+            settings_buffer,
+            luminance_buffer,
+            bind_group_layout,
+            bind_group,
+            material,
+            histogram,
+        })
+    }
+
+    /// Follows the HDR target after it is recreated.
+    pub fn resize(&mut self, device: &wgpu::Device, hdr_view: &wgpu::TextureView) {
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            hdr_view,
+            &self.settings_buffer,
+            &self.luminance_buffer,
+        );
+        if let Some(histogram) = &mut self.histogram {
+            histogram.resize(device, hdr_view, &self.luminance_buffer);
+        }
+    }
+
+    pub fn tonemapper(&self) -> Tonemapper {
+        self.tonemapper
+    }
+
+    pub fn set_tonemapper(&mut self, tonemapper: Tonemapper) {
+        self.tonemapper = tonemapper;
+    }
+
+    pub fn exposure(&self) -> Exposure {
+        self.exposure
+    }
+
+    /// Auto exposure stays off when the histogram pass is not supported.
+    pub fn set_exposure(&mut self, exposure: Exposure) {
+        self.exposure = Exposure {
+            auto: exposure.auto && self.supports_auto_exposure(),
+            ..exposure
+        };
+    }
+
+    pub fn supports_auto_exposure(&self) -> bool {
+        self.histogram.is_some()
+    }
+
+    pub fn enque_update(&self, queue: &wgpu::Queue) {
+        let settings = SettingsUniform {
+            tonemapper: self.tonemapper as u32,
+            auto_exposure: self.exposure.auto as u32,
+            exposure_ev: self.exposure.ev,
+            hdr: self.hdr as u32,
+        };
+        queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+    }
+
+    /// `hdr_size` is the size of the HDR target, which has to be resolved already.
+    /// `pipelines` is the cache the pass was created with.
+    pub fn render(
+        &self,
+        pipelines: &PipelineCache,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        hdr_size: (u32, u32),
+    ) {
+        if let Some(histogram) = self.histogram.as_ref().filter(|_| self.exposure.auto) {
+            histogram.dispatch(pipelines, encoder, hdr_size);
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(pipelines.pipeline(self.material));
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn uniform_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    hdr_view: &wgpu::TextureView,
+    settings_buffer: &wgpu::Buffer,
+    luminance_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("tonemap_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(hdr_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: settings_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: luminance_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+// Builds a luminance histogram of the HDR target and averages it into the luminance buffer
+struct HistogramPass {
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    histogram_buffer: wgpu::Buffer,
+    params_buffer: wgpu::Buffer,
+    build_pipeline: ComputeId,
+    average_pipeline: ComputeId,
+}
+
+impl HistogramPass {
+    fn new(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        hdr_view: &wgpu::TextureView,
+        luminance_buffer: &wgpu::Buffer,
+    ) -> Result<Self, ShaderError> {
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("histogram_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                storage_entry(1),
+                storage_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let histogram_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("histogram_buffer"),
+            contents: bytemuck::cast_slice(&[0u32; 256]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("histogram_params_buffer"),
+            contents: bytemuck::cast_slice(&[HISTOGRAM_PARAMS]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let mut register = |entry_point| {
+            pipelines.register_compute(
+                device,
+                &ComputeDesc {
+                    shader: Shader::new("histogram.wgsl"),
+                    entry_point,
+                    bind_group_layouts: &[&bind_group_layout],
+                },
+            )
+        };
+
+        Ok(Self {
+            build_pipeline: register("build")?,
+            average_pipeline: register("average")?,
+            bind_group: create_histogram_bind_group(
+                device,
+                &bind_group_layout,
+                hdr_view,
+                &histogram_buffer,
+                luminance_buffer,
+                &params_buffer,
+            ),
+            bind_group_layout,
+            histogram_buffer,
+            params_buffer,
+        })
+    }
+
+    fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        luminance_buffer: &wgpu::Buffer,
+    ) {
+        self.bind_group = create_histogram_bind_group(
+            device,
+            &self.bind_group_layout,
+            hdr_view,
+            &self.histogram_buffer,
+            luminance_buffer,
+            &self.params_buffer,
+        );
+    }
+
+    fn dispatch(
+        &self,
+        pipelines: &PipelineCache,
+        encoder: &mut wgpu::CommandEncoder,
+        (width, height): (u32, u32),
+    ) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Histogram Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.set_pipeline(pipelines.compute_pipeline(self.build_pipeline));
+        compute_pass.dispatch_workgroups(width.div_ceil(16), height.div_ceil(16), 1);
+        compute_pass.set_pipeline(pipelines.compute_pipeline(self.average_pipeline));
+        compute_pass.dispatch_workgroups(1, 1, 1);
+    }
+}
+
+fn create_histogram_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    hdr_view: &wgpu::TextureView,
+    histogram_buffer: &wgpu::Buffer,
+    luminance_buffer: &wgpu::Buffer,
+    params_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("histogram_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(hdr_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: histogram_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: luminance_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(4),
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: params_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
@@ -1,4 +1,8 @@
-import init, { create_state, type State } from "../pkg/graphics.js";
+import init, {
+	create_state,
+	DeviceEvent,
+	type State,
+} from "../pkg/graphics.js";
 
 function fullscreenCanvas(
 	canvas: HTMLCanvasElement,
@@ -11,6 +15,28 @@ function fullscreenCanvas(
 	}
 }
 
+// `create_state` rejects with an `Error` whose `code` tells what went wrong
+function errorMessage(error: unknown): string {
+	const code = (error as { code?: string }).code;
+	switch (code) {
+		case "adapter-unavailable":
+		case "device-request-failed":
+			return "Sorry, no GPU could be used. Hardware acceleration may be disabled in your browser.";
+		case "unsupported-surface":
+			return "Sorry, the canvas cannot be drawn on by your GPU.";
+		case "asset-fetch-failed":
+			return "Failed to download the resources. Please check your connection and reload.";
+		case "asset-decode-failed":
+			return "The resources are broken. Please reload the page.";
+		case "shader-error":
+			return "Sorry, your GPU could not compile the shaders.";
+		case "cancelled":
+			return "";
+		default:
+			return "Failed to load the resources.";
+	}
+}
+
 async function main() {
 	// initialize
 	const canvas = document.getElementById("main-canvas") as HTMLCanvasElement;
@@ -32,36 +58,140 @@ async function main() {
 	}
 
 	// create state
-	const state = await create_state(canvas, false);
-	if (!state) {
+	const loading = document.getElementById("loading");
+	const received = new Map<string, [number, number | undefined]>();
+	const onProgress = (
+		path: string,
+		bytes: number,
+		total: number | undefined,
+	) => {
+		received.set(path, [bytes, total]);
+		if (!loading) {
+			return;
+		}
+		const sum = [...received.values()].reduce((a, [b]) => a + b, 0);
+		const totals = [...received.values()].map(([, t]) => t);
+		if (totals.every((t) => t !== undefined)) {
+			const all = totals.reduce((a, t) => a + (t ?? 0), 0);
+			loading.textContent = `Loading... ${Math.floor((sum / all) * 100)}%`;
+		} else {
+			loading.textContent = `Loading... ${(sum / 1024 / 1024).toFixed(1)} MB`;
+		}
+	};
+	let state: State;
+	try {
+		state = await create_state(canvas, false, onProgress);
+	} catch (error) {
+		console.error(error);
+		if (loading) {
+			loading.textContent = errorMessage(error);
+		}
 		return;
 	}
+	if (loading) {
+		loading.textContent = "";
+	}
+
+	// the GPU device can be lost, e.g. when the driver is updated, and is restored by the state
+	state.set_device_listener((event: DeviceEvent, message: string) => {
+		if (!loading) {
+			return;
+		}
+		if (event === DeviceEvent.Lost) {
+			loading.textContent = "The GPU was lost. Restoring...";
+		} else if (event === DeviceEvent.Restored) {
+			loading.textContent = "";
+		} else if (event === DeviceEvent.OutOfMemory) {
+			console.warn(message);
+		} else {
+			loading.textContent = `Failed to restore the GPU: ${message}`;
+		}
+	});
+
+	// `restore` creates a lost device again; the state cannot be used until it resolves
+	let restoring = false;
+	let restoreFailed = false;
+	const ready = () => {
+		if (restoring || restoreFailed) {
+			return false;
+		}
+		if (state.is_device_lost()) {
+			restoring = true;
+			state.restore().then((restored) => {
+				restoring = false;
+				restoreFailed = !restored;
+				if (restored) {
+					fullscreenCanvas(canvas, state);
+				}
+			});
+			return false;
+		}
+		return true;
+	};
 
 	// resize
-	addEventListener("resize", () => fullscreenCanvas(canvas, state));
+	addEventListener("resize", () => {
+		if (ready()) {
+			fullscreenCanvas(canvas, state);
+		}
+	});
 	fullscreenCanvas(canvas, state);
 
 	// `render` is called per the refresh rate of the display
 	const renderloop = () => {
-		state.render();
+		if (ready()) {
+			state.render();
+		}
 		requestAnimationFrame(renderloop);
 	};
 	requestAnimationFrame(renderloop);
 
 	// key event
 	addEventListener("keydown", (event) => {
-		state.key_event(event);
+		if (ready()) {
+			state.key_event(event);
+		}
 	});
 	addEventListener("keyup", (event) => {
-		state.key_event(event);
+		if (ready()) {
+			state.key_event(event);
+		}
 	});
 
+	// dragging rotates, the wheel and pinching zoom to the pointer, and twisting turns
+	const onPointer = (event: PointerEvent) => {
+		if (event.type === "pointerdown") {
+			canvas.setPointerCapture(event.pointerId);
+		}
+		if (ready()) {
+			state.pointer_event(event);
+		}
+	};
+	canvas.addEventListener("pointerdown", onPointer);
+	canvas.addEventListener("pointermove", onPointer);
+	canvas.addEventListener("pointerup", onPointer);
+	canvas.addEventListener("pointercancel", onPointer);
+	canvas.addEventListener(
+		"wheel",
+		(event) => {
+			event.preventDefault();
+			if (ready()) {
+				state.wheel_event(event);
+			}
+		},
+		{ passive: false },
+	);
+
 	// reset all events when the page is not visible
 	document.addEventListener("visibilitychange", () => {
-		state.leave();
+		if (ready()) {
+			state.leave();
+		}
 	});
 	document.addEventListener("blur", () => {
-		state.leave();
+		if (ready()) {
+			state.leave();
+		}
 	});
 
 	const allowLeft = document.getElementById("allow-left");
@@ -107,12 +237,14 @@ async function main() {
 
 	const updateloop = () => {
 		const currentTime = Date.now();
-		state.update((currentTime - initialTime) / updateInterval);
-		if (isAllowLeft) {
-			state.scroll_to_left();
-		}
-		if (isAllowRight) {
-			state.scroll_to_right();
+		if (ready()) {
+			state.update((currentTime - initialTime) / updateInterval);
+			if (isAllowLeft) {
+				state.scroll_to_left();
+			}
+			if (isAllowRight) {
+				state.scroll_to_right();
+			}
 		}
 		const nextTime = Date.now();
 